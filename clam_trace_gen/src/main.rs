@@ -1,7 +1,9 @@
-#![feature(get_mut_unchecked)]
-
 mod trace;
 
+/// Traces the kernel file given as the only argument, or mvt without one.
 fn main() {
-    trace::trace_polybench("mvt", 512, 64, &[]);
+    match std::env::args().nth(1) {
+        Some(path) => trace::trace_kernel_file(&path, 64),
+        None => trace::trace_polybench("mvt", 512, 64, &[]),
+    }
 }
@@ -1,9 +1,10 @@
 use std::fs::File;
 use std::io::Write;
 
+use dace::program::Program;
 use dace_tests::polybench_simplify;
 use hist::Hist;
-use static_ri::tracing_ri_with_trace;
+use static_ri::{tracing_ri_program_with_trace, tracing_ri_with_trace};
 
 pub fn trace_polybench(
     bench: &str,
@@ -84,6 +85,13 @@ pub fn trace_polybench(
     // write_hist_to_file(&_hist, "output.csv");
 }
 
+/// Traces a kernel saved by `Program::save`, or written by another tool in that format.
+pub fn trace_kernel_file(path: &str, cache_line_size: usize) {
+    let program = Program::load(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    program.root_node().print_structure(0);
+    let _hist = tracing_ri_program_with_trace(&program, cache_line_size);
+}
+
 #[allow(dead_code)]
 fn write_hist_to_file(hist: &Hist, file_path: &str) {
     let mut file = File::create(file_path).expect("Unable to create file");
@@ -0,0 +1,248 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Mul, Neg, Sub};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types;
+
+/// An affine function of the iteration vector, `coeffs . ivec + constant`.
+///
+/// `coeffs[k]` is the coefficient of the index of the k-th enclosing loop, outermost first.
+/// Missing trailing coefficients are zero, so `[1]` and `[1, 0, 0]` denote the same
+/// expression and compare equal.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AffineExpr {
+    pub coeffs: Vec<i32>,
+    pub constant: i32,
+}
+
+impl AffineExpr {
+    pub fn new(coeffs: Vec<i32>, constant: i32) -> Self {
+        AffineExpr { coeffs, constant }
+    }
+
+    pub fn constant(constant: i32) -> Self {
+        AffineExpr {
+            coeffs: vec![],
+            constant,
+        }
+    }
+
+    /// The index of the loop at depth `pos`.
+    pub fn var(pos: usize) -> Self {
+        let mut coeffs = vec![0; pos + 1];
+        coeffs[pos] = 1;
+        AffineExpr::new(coeffs, 0)
+    }
+
+    pub fn coeff(&self, pos: usize) -> i32 {
+        self.coeffs.get(pos).copied().unwrap_or(0)
+    }
+
+    /// The number of enclosing loops the expression depends on, i.e. one past the deepest
+    /// loop with a non-zero coefficient.
+    pub fn depth(&self) -> usize {
+        self.coeffs
+            .iter()
+            .rposition(|&c| c != 0)
+            .map_or(0, |p| p + 1)
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.depth() == 0
+    }
+
+    pub fn eval(&self, ivec: &[i32]) -> i32 {
+        self.coeffs
+            .iter()
+            .zip(ivec.iter())
+            .map(|(a, i)| a * i)
+            .sum::<i32>()
+            + self.constant
+    }
+
+    /// Renders the expression using `names` for the loop indices, e.g. `2*i+j-1`.
+    /// Loops without a name are written as `i0`, `i1`, ...
+    pub fn to_string_with(&self, names: &[String]) -> String {
+        let mut out = String::new();
+        for (pos, &c) in self.coeffs.iter().enumerate().filter(|(_, &c)| c != 0) {
+            let name = names
+                .get(pos)
+                .cloned()
+                .unwrap_or_else(|| format!("i{}", pos));
+            let sign = if c < 0 {
+                "-"
+            } else if out.is_empty() {
+                ""
+            } else {
+                "+"
+            };
+            match c.abs() {
+                1 => out.push_str(&format!("{}{}", sign, name)),
+                k => out.push_str(&format!("{}{}*{}", sign, k, name)),
+            }
+        }
+        match (out.is_empty(), self.constant) {
+            (true, c) => c.to_string(),
+            (false, 0) => out,
+            (false, c) if c < 0 => format!("{}{}", out, c),
+            (false, c) => format!("{}+{}", out, c),
+        }
+    }
+
+    /// The sum, or `None` if a coefficient or the constant overflows.
+    pub fn checked_add(self, other: &AffineExpr) -> Option<Self> {
+        self.try_zip_with(other, i32::checked_add)
+    }
+
+    /// The difference, or `None` if a coefficient or the constant overflows.
+    pub fn checked_sub(self, other: &AffineExpr) -> Option<Self> {
+        self.try_zip_with(other, i32::checked_sub)
+    }
+
+    /// The product with `k`, or `None` if a coefficient or the constant overflows.
+    pub fn checked_mul(mut self, k: i32) -> Option<Self> {
+        for c in &mut self.coeffs {
+            *c = c.checked_mul(k)?;
+        }
+        self.constant = self.constant.checked_mul(k)?;
+        Some(self)
+    }
+
+    fn trimmed(&self) -> &[i32] {
+        &self.coeffs[..self.depth()]
+    }
+
+    fn try_zip_with(
+        mut self,
+        other: &AffineExpr,
+        f: impl Fn(i32, i32) -> Option<i32>,
+    ) -> Option<Self> {
+        if self.coeffs.len() < other.coeffs.len() {
+            self.coeffs.resize(other.coeffs.len(), 0);
+        }
+        for (pos, c) in self.coeffs.iter_mut().enumerate() {
+            *c = f(*c, other.coeff(pos))?;
+        }
+        self.constant = f(self.constant, other.constant)?;
+        Some(self)
+    }
+
+    fn zip_with(self, other: &AffineExpr, f: impl Fn(i32, i32) -> i32) -> Self {
+        self.try_zip_with(other, |a, b| Some(f(a, b))).unwrap()
+    }
+}
+
+impl PartialEq for AffineExpr {
+    fn eq(&self, other: &Self) -> bool {
+        self.constant == other.constant && self.trimmed() == other.trimmed()
+    }
+}
+
+impl Eq for AffineExpr {}
+
+impl Hash for AffineExpr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.trimmed().hash(state);
+        self.constant.hash(state);
+    }
+}
+
+impl fmt::Display for AffineExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(&[]))
+    }
+}
+
+impl From<i32> for AffineExpr {
+    fn from(value: i32) -> Self {
+        AffineExpr::constant(value)
+    }
+}
+
+impl Add for AffineExpr {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.zip_with(&other, |a, b| a + b)
+    }
+}
+
+impl Sub for AffineExpr {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.zip_with(&other, |a, b| a - b)
+    }
+}
+
+impl Neg for AffineExpr {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self * -1
+    }
+}
+
+impl Mul<i32> for AffineExpr {
+    type Output = Self;
+
+    fn mul(mut self, k: i32) -> Self {
+        self.coeffs.iter_mut().for_each(|c| *c *= k);
+        self.constant *= k;
+        self
+    }
+}
+
+/// Compiles per-dimension subscript expressions into the evaluation closure of an `AryRef`.
+pub fn compile_sub(subs: &[AffineExpr]) -> Rc<types::DynFunc> {
+    let subs = subs.to_vec();
+    Rc::new(move |ivec: &[i32]| subs.iter().map(|e| e.eval(ivec) as usize).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        // 2*i + j - k - 1
+        let e = AffineExpr::var(0) * 2 + AffineExpr::var(1) - AffineExpr::var(2) - 1.into();
+        assert_eq!(e.coeffs, [2, 1, -1]);
+        assert_eq!(e.eval(&[3, 4, 5]), 4);
+        assert_eq!(e.depth(), 3);
+        assert_eq!(e.clone().checked_add(&1.into()), Some(e.clone() + 1.into()));
+        assert_eq!(e.clone().checked_mul(i32::MAX), None);
+        assert_eq!(AffineExpr::constant(i32::MIN).checked_sub(&1.into()), None);
+        assert_eq!(-e.clone() + e, AffineExpr::constant(0));
+    }
+
+    #[test]
+    fn trailing_zeros() {
+        let a = AffineExpr::new(vec![1, 0, 0], 2);
+        let b = AffineExpr::new(vec![1], 2);
+        assert_eq!(a, b);
+        assert_eq!(a.depth(), 1);
+        assert!(AffineExpr::new(vec![0, 0], 7).is_constant());
+    }
+
+    #[test]
+    fn display() {
+        let names = ["i".to_string(), "j".to_string()];
+        assert_eq!(
+            AffineExpr::new(vec![1, -2], 0).to_string_with(&names),
+            "i-2*j"
+        );
+        assert_eq!(AffineExpr::new(vec![0, 1], 1).to_string_with(&names), "j+1");
+        assert_eq!(AffineExpr::new(vec![0, 0, 3], -1).to_string(), "3*i2-1");
+        assert_eq!(AffineExpr::constant(-4).to_string(), "-4");
+    }
+
+    #[test]
+    fn compiled() {
+        let sub = compile_sub(&[AffineExpr::var(0) + 1.into(), AffineExpr::var(2) * 2]);
+        assert_eq!(sub(&[1, 2, 3]), [2, 6]);
+    }
+}
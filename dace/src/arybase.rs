@@ -1,52 +1,120 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::ast::{Node, Stmt};
+use crate::ast::{AryRef, ElemType, Node, Stmt};
 use crate::iter::Walk;
 
+/// Element size of the arrays whose references don't declare one, that of a `double`.
+pub const DEFAULT_ELEM_SIZE: usize = 8;
+
+/// Where `set_arybase` starts each array. The alignment and padding of the array's own
+/// `ElemType` apply as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Each array right after the previous one.
+    #[default]
+    Packed,
+    /// Each array at a multiple of this many bytes, e.g. 4096 to start every array on a page.
+    Aligned(usize),
+    /// This many unused bytes between consecutive arrays.
+    Padded(usize),
+    /// The given base byte addresses, e.g. as seen in a run of the real program. Arrays that
+    /// are not in the table are packed after the end of those that are.
+    Explicit(HashMap<String, usize>),
+}
+
 ///
 /// This function assigns a unique base address to each array in a given loop.
-/// It takes a mutable reference to a `Node` object, which represents a loop, and returns a tuple containing a `HashMap` and a `usize`.
-/// The `HashMap` stores the base address for each array, and the `usize` is the current base address.
-/// 1. It initializes a `HashMap` and a counter (`cur_base`) to 0 and `Walk` object from the loop.
-/// 2. It filters the nodes in the loop to only include those that are array references (`Stmt::Ref(_)`).
-/// 4. For each filtered node:
-///    - If the array name of the array is not already in the `HashMap`, it inserts the current base address into the `HashMap` for that array name, calculates the size of the array by multiplying its dimensions, and increments the current base address by the size of the array.
-///    - It retrieves the base address for the array from the `HashMap` and sets it in the node.
+/// It takes a reference to a `Node` object, which represents a loop, and returns a tuple containing a `HashMap` and a `usize`.
+/// The `HashMap` stores the base byte address for each array, and the `usize` is the number of bytes up to the end of the last array.
+/// 1. It collects the arrays in the order of their first reference, walking the loop with a `Walk` object.
+/// 2. It places the arrays with a base in a `Placement::Explicit` table there, and starts a counter (`cur_base`) at the end of the last of them (or at 0).
+/// 3. For each other array, in order:
+///    - It adds the gap of a `Placement::Padded` policy unless this is the first array placed, rounds the current base address up to the alignment of the array's elements and to that of a `Placement::Aligned` policy, inserts it into the `HashMap` for that array name, and increments the current base address by the size of the array in bytes (as laid out by its `Layout`) plus its padding.
 ///
-pub fn set_arybase(aloop: &Rc<Node>) -> (HashMap<String, usize>, usize) {
-    let init = (HashMap::<String, usize>::new(), 0);
-    Walk::new(aloop)
-        .filter(|node| matches!(&node.stmt, Stmt::Ref(_)))
-        .fold::<(HashMap<String, usize>, usize), _>(init, |(mut tbl, mut cur_base), mut node| {
-            let ary_name = node.ref_only_ref(|a_ref| &a_ref.name).unwrap().as_str();
-            if !tbl.contains_key(ary_name) {
-                tbl.insert(ary_name.to_string(), cur_base);
-                let dim = node.ref_only_ref(|a_ref| &a_ref.dim).unwrap();
-                let ary_size: usize = dim.iter().product();
-                cur_base += ary_size;
+/// The element type of an array is that of its first reference, or a naturally aligned
+/// element of `elem_size` bytes if it has none.
+/// The nodes are left untouched; `Program` looks up the base of each reference site in the table.
+///
+pub fn set_arybase(
+    aloop: &Rc<Node>,
+    elem_size: usize,
+    placement: &Placement,
+) -> (HashMap<String, usize>, usize) {
+    let nodes: Vec<Rc<Node>> = Walk::new(aloop).collect();
+    let mut arrays: Vec<&AryRef> = vec![];
+    for node in &nodes {
+        if let Stmt::Ref(a_ref) = &node.stmt {
+            if arrays.iter().all(|a| a.name != a_ref.name) {
+                arrays.push(a_ref);
+            }
+        }
+    }
+    // bytes taken by an array, from its base
+    let bytes = |a_ref: &AryRef| {
+        let elem = elem_type(a_ref, elem_size);
+        a_ref.layout.size(&a_ref.dim) * elem.size + elem.pad
+    };
+
+    let mut tbl = HashMap::<String, usize>::new();
+    let mut cur_base = 0;
+    if let Placement::Explicit(bases) = placement {
+        for a_ref in &arrays {
+            if let Some(&base) = bases.get(&a_ref.name) {
+                tbl.insert(a_ref.name.clone(), base);
+                cur_base = cur_base.max(base + bytes(a_ref));
             }
-            let ary_base = tbl.get(ary_name).unwrap();
-            let mutable = unsafe { Rc::get_mut_unchecked(&mut node) };
-            let my_base = mutable.ref_only_mut_ref(|a_ref| &mut a_ref.base).unwrap();
-            *my_base = Some(*ary_base);
-            (tbl, cur_base)
-        })
+        }
+    }
+    let mut end = cur_base;
+    let mut first = true;
+    for a_ref in arrays {
+        if tbl.contains_key(&a_ref.name) {
+            continue;
+        }
+        let mut align = elem_type(a_ref, elem_size).align.max(1);
+        match placement {
+            Placement::Aligned(n) => align = lcm(align, (*n).max(1)),
+            Placement::Padded(n) if !first => cur_base += n,
+            _ => {}
+        }
+        cur_base = cur_base.next_multiple_of(align);
+        tbl.insert(a_ref.name.clone(), cur_base);
+        cur_base += bytes(a_ref);
+        end = cur_base;
+        first = false;
+    }
+    (tbl, end)
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+/// The element type of the array `a_ref` accesses, with `elem_size` bytes as the default.
+pub fn elem_type(a_ref: &AryRef, elem_size: usize) -> ElemType {
+    a_ref.elem.unwrap_or(ElemType::new(elem_size))
 }
 
 #[cfg(test)]
 mod test {
+    use crate::program::Program;
     use crate::{branch_node, loop_node};
 
     use super::*;
 
     #[test]
-    #[should_panic]
     fn nobase() {
-        let node = Node::new_ref("A", vec![1], |_| vec![0]);
-        if let Stmt::Ref(aref) = &node.stmt {
-            let _ = aref.base.unwrap();
-        }
+        // only references have a base
+        let mut node = Node::new_ref("A", vec![1], |_| vec![0]);
+        let mut aloop = Node::new_single_loop("i", 0, 1);
+        Node::extend_loop_body(&mut aloop, &mut node);
+        let program = Program::new(&aloop);
+        assert_eq!(program.base(program.root()), None);
     }
 
     #[test]
@@ -65,7 +133,7 @@ mod test {
         // creating loop i = 0, n
         let mut i_loop = loop_node!("i", 0 => ubound, step: |x| x + 2);
         Node::extend_loop_body(&mut i_loop, &mut branch);
-        let (tbl, _size) = set_arybase(&i_loop);
+        let (tbl, _size) = set_arybase(&i_loop, 1, &Placement::Packed);
         println!("{:?}", tbl);
         assert_eq!(tbl.len(), 1);
     }
@@ -80,7 +148,7 @@ mod test {
         // creating loop i = 0, n
         // let mut i_loop = loop_node!("i", 0 => ubound, step: |x| x + 2);
         // Node::extend_loop_body(&mut i_loop, &mut block);
-        let (tbl, _size) = set_arybase(&n);
+        let (tbl, _size) = set_arybase(&n, 1, &Placement::Packed);
         println!("{:?}", tbl);
         assert_eq!(tbl.len(), 2);
     }
@@ -102,29 +170,69 @@ mod test {
             .iter_mut()
             .for_each(|s| Node::extend_loop_body(&mut iloop, s));
 
-        let (tbl, size) = set_arybase(&iloop);
+        let (tbl, size) = set_arybase(&iloop, 1, &Placement::Packed);
         assert_eq!(tbl.len(), 3);
         // println!("{:?}", tbl);
         assert_eq!(size, n + n * n + n * n * n);
 
+        let program = Program::new(&iloop);
+        let bases: Vec<Option<usize>> = program
+            .children(program.root())
+            .iter()
+            .map(|&id| program.base(id))
+            .collect();
+        let ds = DEFAULT_ELEM_SIZE;
+        assert_eq!(bases, [Some(0), Some(n * ds), Some((n + n * n) * ds)]);
+        // Walk::new(&iloop).for_each( |node| println!("{:?}", node) );
+    }
+
+    #[test]
+    fn mixed_elements() {
+        // char C[3]; double D[2] padded by 8; int I[5] aligned to 64; E[1] of the default size
+        let refs = [
+            Node::new_ref("C", vec![3], |_| vec![0]).with_elem(ElemType::new(1)),
+            Node::new_ref("D", vec![2], |_| vec![0]).with_elem(ElemType::new(8).padded(8)),
+            Node::new_ref("I", vec![5], |_| vec![0]).with_elem(ElemType::new(4).aligned(64)),
+            Node::new_ref("E", vec![1], |_| vec![0]),
+        ];
+        let block = Node::new_node(Stmt::Block(refs.to_vec()));
+        let (tbl, size) = set_arybase(&block, 2, &Placement::Packed);
+        assert_eq!(tbl["C"], 0);
+        assert_eq!(tbl["D"], 8);
+        assert_eq!(tbl["I"], 64);
+        assert_eq!(tbl["E"], 84);
+        assert_eq!(size, 86);
+
+        let program = Program::new(&block);
+        let sizes: Vec<Option<usize>> = program.refs().map(|r| program.elem_size(r)).collect();
+        assert_eq!(sizes, [Some(1), Some(8), Some(4), Some(DEFAULT_ELEM_SIZE)]);
+    }
+
+    #[test]
+    fn placements() {
+        // A[10], B[10] and C[10] of 8 bytes, and D[3] of 4
+        let refs = [
+            Node::new_ref("A", vec![10], |_| vec![0]),
+            Node::new_ref("B", vec![10], |_| vec![0]),
+            Node::new_ref("C", vec![10], |_| vec![0]),
+            Node::new_ref("D", vec![3], |_| vec![0]).with_elem(ElemType::new(4)),
+        ];
+        let block = Node::new_node(Stmt::Block(refs.to_vec()));
+        let place = |placement: Placement| {
+            let (tbl, size) = set_arybase(&block, 8, &placement);
+            (["A", "B", "C", "D"].map(|name| tbl[name]), size)
+        };
+
+        assert_eq!(place(Placement::Packed), ([0, 80, 160, 240], 252));
+        assert_eq!(place(Placement::Aligned(64)), ([0, 128, 256, 384], 396));
+        assert_eq!(place(Placement::Padded(4)), ([0, 88, 176, 260], 272));
+        let explicit = HashMap::from([("B".to_string(), 1000), ("D".to_string(), 0)]);
         assert_eq!(
-            iloop
-                .loop_only(|lp| lp.body[0].ref_only(|rf| rf.base).unwrap())
-                .unwrap(),
-            Some(0)
-        );
-        assert_eq!(
-            iloop
-                .loop_only(|lp| lp.body[1].ref_only(|rf| rf.base).unwrap())
-                .unwrap(),
-            Some(n)
-        );
-        assert_eq!(
-            iloop
-                .loop_only(|lp| lp.body[2].ref_only(|rf| rf.base).unwrap())
-                .unwrap(),
-            Some(n + n * n)
+            place(Placement::Explicit(explicit)),
+            ([1080, 1000, 1160, 0], 1240)
         );
-        // Walk::new(&iloop).for_each( |node| println!("{:?}", node) );
+
+        let program = Program::with_placement(&block, 8, &Placement::Aligned(4096));
+        assert_eq!(program.ary_bases()["D"], 3 * 4096);
     }
 }
@@ -1,14 +1,19 @@
+use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::ops::{Add, Div, Mul, Sub};
 use std::rc::{Rc, Weak};
 
+use serde::{Deserialize, Serialize};
+
+use crate::affine::{compile_sub, AffineExpr};
+use crate::layout::Layout;
 use crate::types;
 
 /// Each loop and statement is a node in a loop tree.
 #[derive(Debug)]
 pub struct Node {
     pub stmt: Stmt,
-    pub(crate) parent: Weak<Node>,
+    pub(crate) parent: RefCell<Weak<Node>>,
 }
 
 #[derive(Debug)]
@@ -19,6 +24,7 @@ pub enum Stmt {
     Branch(BranchStmt),
 }
 
+#[derive(Clone)]
 pub struct AryRef {
     pub name: String,
     /// array dimensions, e.g. [5,5]
@@ -27,16 +33,158 @@ pub struct AryRef {
     /// Subscript expressions: one function for each data dimension.
     /// Each function takes the indices of its loop nest and returns indices of the array access.
     #[allow(clippy::type_complexity)]
-    pub sub: Box<dyn for<'a> Fn(&'a [i32]) -> types::AryAcc>,
-    pub base: Option<usize>,
-    pub ref_id: Option<usize>,
-    pub access_matrix: Vec<Vec<Vec<usize>>>,
+    pub sub: Rc<dyn for<'a> Fn(&'a [i32]) -> types::AryAcc>,
+    /// Symbolic form of `sub`, one affine expression per data dimension, when known.
+    /// `sub` is compiled from it, so the two always agree.
+    pub sub_affine: Option<Vec<AffineExpr>>,
     pub ri: Vec<String>,
+    /// Whether the access loads, stores or updates the element, see `Node::with_kind`.
+    pub kind: AccessKind,
+    /// How the array's elements are stored, see `Node::with_elem`. `None` leaves the choice
+    /// to the tracer, as its default element size.
+    pub elem: Option<ElemType>,
+    /// The order of the array's elements in memory, see `Node::with_layout`.
+    pub layout: Layout,
+}
+
+impl AryRef {
+    /// The offset in elements, from the start of the array, of the element at `index`.
+    pub fn offset(&self, index: &[usize]) -> usize {
+        self.layout.offset(index, &self.dim)
+    }
+}
+
+/// How a reference touches memory.
+///
+/// `ReadWrite` models an update such as `C[i][j] += ...` as a single access. The parser
+/// instead emits a `Read` followed by a `Write`, one reference per memory operation.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum AccessKind {
+    #[default]
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl AccessKind {
+    pub fn reads(self) -> bool {
+        self != AccessKind::Write
+    }
+
+    pub fn writes(self) -> bool {
+        self != AccessKind::Read
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccessKind::Read => "R",
+            AccessKind::Write => "W",
+            AccessKind::ReadWrite => "RW",
+        }
+    }
+}
+
+/// The storage of the elements of an array, used by `arybase::set_arybase` to place it in
+/// memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ElemType {
+    /// Bytes per element.
+    pub size: usize,
+    /// The array starts at a multiple of this many bytes.
+    pub align: usize,
+    /// Bytes left unused after the last element, before the next array.
+    pub pad: usize,
+    /// The C type the array was declared with, if it came from source text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub c_type: Option<CType>,
+}
+
+impl ElemType {
+    /// A naturally aligned, unpadded element of `size` bytes.
+    pub fn new(size: usize) -> Self {
+        ElemType {
+            size,
+            align: size,
+            pad: 0,
+            c_type: None,
+        }
+    }
+
+    /// A naturally aligned, unpadded element of the C type `c_type`.
+    pub fn of(c_type: CType) -> Self {
+        ElemType {
+            c_type: Some(c_type),
+            ..ElemType::new(c_type.size())
+        }
+    }
+
+    pub fn aligned(self, align: usize) -> Self {
+        ElemType { align, ..self }
+    }
+
+    pub fn padded(self, pad: usize) -> Self {
+        ElemType { pad, ..self }
+    }
+}
+
+/// The scalar types accepted in array declarations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CType {
+    Double,
+    Float,
+    Int,
+    Long,
+    Short,
+    Char,
+}
+
+impl CType {
+    pub const ALL: [CType; 6] = [
+        CType::Double,
+        CType::Float,
+        CType::Int,
+        CType::Long,
+        CType::Short,
+        CType::Char,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CType::Double => "double",
+            CType::Float => "float",
+            CType::Int => "int",
+            CType::Long => "long",
+            CType::Short => "short",
+            CType::Char => "char",
+        }
+    }
+
+    /// Bytes per element.
+    pub fn size(self) -> usize {
+        match self {
+            CType::Double | CType::Long => 8,
+            CType::Float | CType::Int => 4,
+            CType::Short => 2,
+            CType::Char => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 pub struct BranchStmt {
     #[allow(clippy::type_complexity)]
-    pub cond: Box<dyn Fn(&[i32]) -> bool>,
+    pub cond: Rc<dyn Fn(&[i32]) -> bool>,
+    /// Symbolic form of `cond`, when known. `cond` is compiled from it, see
+    /// `Node::new_branch`.
+    pub pred: Option<Predicate>,
     pub then_body: Rc<Node>,
     pub else_body: Option<Rc<Node>>,
 }
@@ -47,31 +195,138 @@ pub struct LoopStmt {
     pub ub: LoopBound,
     // The next two need the FnOnce trait, which we'll add later
     // Now we assume test is iv < ub
-    pub test: Box<dyn Fn(i32, i32) -> bool>,
+    pub test: Rc<dyn Fn(i32, i32) -> bool>,
     // Now we assume step is iv = iv + 1
-    pub step: Box<dyn Fn(i32) -> i32>,
-    pub body: Vec<Rc<Node>>,
-    pub rank: i32,
+    pub step: Rc<dyn Fn(i32) -> i32>,
+    /// Symbolic form of `test`, `iv cmp ub`, when known.
+    pub cmp: Option<Cmp>,
+    /// Symbolic form of `step`, `iv + stride`, when known.
+    pub stride: Option<i32>,
+    /// Filled in while the tree is being built, see `Node::extend_loop_body`.
+    pub body: RefCell<Vec<Rc<Node>>>,
+}
+
+/// A branch predicate over affine comparisons of the enclosing loop indices.
+/// `Cmp(op, e)` stands for `e op 0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    Cmp(Cmp, AffineExpr),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
 }
 
+impl Predicate {
+    pub fn eval(&self, ivec: &[i32]) -> bool {
+        match self {
+            Predicate::Cmp(op, e) => op.eval(e.eval(ivec), 0),
+            Predicate::And(a, b) => a.eval(ivec) && b.eval(ivec),
+            Predicate::Or(a, b) => a.eval(ivec) || b.eval(ivec),
+            Predicate::Not(a) => !a.eval(ivec),
+        }
+    }
+
+    /// The same predicate with `f` applied to every affine expression in it.
+    pub fn map(&self, f: &impl Fn(&AffineExpr) -> AffineExpr) -> Predicate {
+        match self {
+            Predicate::Cmp(op, e) => Predicate::Cmp(*op, f(e)),
+            Predicate::And(a, b) => Predicate::And(Box::new(a.map(f)), Box::new(b.map(f))),
+            Predicate::Or(a, b) => Predicate::Or(Box::new(a.map(f)), Box::new(b.map(f))),
+            Predicate::Not(a) => Predicate::Not(Box::new(a.map(f))),
+        }
+    }
+
+    /// Renders the predicate using `names` for the enclosing loop indices, e.g.
+    /// `i-j < 0 && !(j == 0)`. Nested `&&` and `||` are parenthesized, so the text parses
+    /// back into the same tree.
+    pub fn to_string_with(&self, names: &[String]) -> String {
+        let operand = |p: &Predicate| match p {
+            Predicate::And(..) | Predicate::Or(..) => format!("({})", p.to_string_with(names)),
+            _ => p.to_string_with(names),
+        };
+        match self {
+            Predicate::Cmp(op, e) => format!("{} {} 0", e.to_string_with(names), op.as_str()),
+            Predicate::And(a, b) => format!("{} && {}", operand(a), operand(b)),
+            Predicate::Or(a, b) => format!("{} || {}", operand(a), operand(b)),
+            Predicate::Not(a) => format!("!({})", a.to_string_with(names)),
+        }
+    }
+}
+
+/// A comparison operator: of a loop index against its upper bound, or of an affine
+/// expression against 0 in a `Predicate`. Loop tests are never `Eq` or `Ne`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Cmp {
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+}
+
+impl Cmp {
+    pub const ALL: [Cmp; 6] = [Cmp::Lt, Cmp::Le, Cmp::Gt, Cmp::Ge, Cmp::Eq, Cmp::Ne];
+
+    pub fn eval(self, iv: i32, ub: i32) -> bool {
+        match self {
+            Cmp::Lt => iv < ub,
+            Cmp::Le => iv <= ub,
+            Cmp::Gt => iv > ub,
+            Cmp::Ge => iv >= ub,
+            Cmp::Eq => iv == ub,
+            Cmp::Ne => iv != ub,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+        }
+    }
+
+    /// The operator written `s`, if any.
+    pub fn from_op(s: &str) -> Option<Cmp> {
+        Cmp::ALL.into_iter().find(|c| c.as_str() == s)
+    }
+}
+
+#[derive(Clone)]
 pub enum LoopBound {
     Fixed(i32),
     #[allow(clippy::type_complexity)]
-    Dynamic(Box<types::DynamicBoundFunction>),
+    Dynamic(Rc<types::DynamicBoundFunction>),
     Affine {
         a: Vec<i32>,
         b: i32,
     },
+    /// The smaller of two bounds, e.g. `min(ii + T, N)`. Build with `LoopBound::min`.
+    Min(Box<LoopBound>, Box<LoopBound>),
+    /// The larger of two bounds. Build with `LoopBound::max`.
+    Max(Box<LoopBound>, Box<LoopBound>),
+    /// `floor(bound / k)`. Build with `LoopBound::floor_div`.
+    FloorDiv(Box<LoopBound>, i32),
+    /// `ceil(bound / k)`. Build with `LoopBound::ceil_div`.
+    CeilDiv(Box<LoopBound>, i32),
 }
 
 impl Debug for AryRef {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(
             f,
-            "ArrayRef({}, {:?}, base: {:?})",
-            self.name,
-            self.dim,
-            self.base.unwrap_or(99999)
+            "ArrayRef({}, {:?}, indices: {:?}, {:?})",
+            self.name, self.dim, self.indices, self.kind
         )
     }
 }
@@ -102,6 +357,27 @@ impl Debug for LoopBound {
             LoopBound::Fixed(x) => write!(f, "Fixed({x})"),
             LoopBound::Dynamic(_) => write!(f, "Dynamic"),
             LoopBound::Affine { a, b } => write!(f, "Affine({:?}, {})", a, b),
+            LoopBound::Min(x, y) => write!(f, "Min({:?}, {:?})", x, y),
+            LoopBound::Max(x, y) => write!(f, "Max({:?}, {:?})", x, y),
+            LoopBound::FloorDiv(x, k) => write!(f, "FloorDiv({:?}, {})", x, k),
+            LoopBound::CeilDiv(x, k) => write!(f, "CeilDiv({:?}, {})", x, k),
+        }
+    }
+}
+
+/// Symbolic bounds compare by value; `Dynamic` ones only equal the same closure.
+impl PartialEq for LoopBound {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LoopBound::Dynamic(a), LoopBound::Dynamic(b)) => Rc::ptr_eq(a, b),
+            (LoopBound::Min(a, b), LoopBound::Min(c, d))
+            | (LoopBound::Max(a, b), LoopBound::Max(c, d)) => a == c && b == d,
+            (LoopBound::FloorDiv(a, k), LoopBound::FloorDiv(b, l))
+            | (LoopBound::CeilDiv(a, k), LoopBound::CeilDiv(b, l)) => k == l && a == b,
+            _ => match (self.as_affine(), other.as_affine()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
         }
     }
 }
@@ -117,7 +393,7 @@ where
     for<'a> F: Fn(&'a [i32]) -> i32 + 'static,
 {
     fn from(value: F) -> Self {
-        LoopBound::Dynamic(Box::new(value))
+        LoopBound::Dynamic(Rc::new(value))
     }
 }
 
@@ -129,62 +405,241 @@ impl From<(Vec<i32>, i32)> for LoopBound {
         }
     }
 }
-//Example:
-// impl Add for LoopBound {
-//     type Output = Self;
-
-//     fn add(self, other: Self) -> Self {
-//         match (self, other) {
-//             (LoopBound::Fixed(constant_a), LoopBound::Fixed(constant_b)) => LoopBound::Fixed(Add::add(constant_a, constant_b)),
-//             (LoopBound::Fixed(constant_a), LoopBound::Dynamic(dynamic_b)) => LoopBound::Dynamic(Box::new(move |vector: &[i32]| {
-//                 Add::add(constant_a, dynamic_b(vector))
-//             })),
-//             (LoopBound::Dynamic(dynamic_a), LoopBound::Fixed(constant_b)) => LoopBound::Dynamic(Box::new(move |vector: &[i32]| {
-//                 Add::add(dynamic_a(vector), constant_b)
-//             })),
-//             (LoopBound::Dynamic(dynamic_a), LoopBound::Dynamic(dynamic_b)) => LoopBound::Dynamic(Box::new(move |vector: &[i32]| {
-//                 Add::add(dynamic_a(vector), dynamic_b(vector))
-//             })),
-//             _ => unimplemented!("The operation + is not implemented for some type Affine"),
-//         }
-//     }
-// }
-macro_rules! loopbound_binop {
-    ($operation_trait:ident, $binop:ident) => {
-        impl $operation_trait for LoopBound {
-            type Output = Self;
-
-            fn $binop(self, other: LoopBound) -> LoopBound {
-                match (self, other) {
-                    (LoopBound::Fixed(constant_a), LoopBound::Fixed(constant_b)) => {
-                        LoopBound::Fixed($operation_trait::$binop(constant_a, constant_b))
-                    }
-                    (LoopBound::Fixed(constant_a), LoopBound::Dynamic(dynamic_b)) => {
-                        LoopBound::Dynamic(Box::new(move |vector: &[i32]| {
-                            $operation_trait::$binop(constant_a, dynamic_b(vector))
-                        }))
-                    }
-                    (LoopBound::Dynamic(dynamic_a), LoopBound::Fixed(constant_b)) => {
-                        LoopBound::Dynamic(Box::new(move |vector: &[i32]| {
-                            $operation_trait::$binop(dynamic_a(vector), constant_b)
-                        }))
-                    }
-                    (LoopBound::Dynamic(dynamic_a), LoopBound::Dynamic(dynamic_b)) => {
-                        LoopBound::Dynamic(Box::new(move |vector: &[i32]| {
-                            $operation_trait::$binop(dynamic_a(vector), dynamic_b(vector))
-                        }))
-                    }
-                    _ => unimplemented!("The operation + is not implemented for some type Affine"),
+
+impl From<AffineExpr> for LoopBound {
+    fn from(value: AffineExpr) -> Self {
+        if value.is_constant() {
+            LoopBound::Fixed(value.constant)
+        } else {
+            LoopBound::Affine {
+                a: value.coeffs,
+                b: value.constant,
+            }
+        }
+    }
+}
+impl LoopBound {
+    /// The smaller of two bounds, e.g. `min(ii + T, N)` for the last, partial tile.
+    /// Folds to a single bound when the two differ by a constant.
+    pub fn min(self, other: LoopBound) -> LoopBound {
+        match (self.as_affine(), other.as_affine()) {
+            (Some(a), Some(b)) if (a.clone() - b.clone()).is_constant() => {
+                if a.constant <= b.constant {
+                    self
+                } else {
+                    other
                 }
             }
+            _ => LoopBound::Min(Box::new(self), Box::new(other)),
         }
-    };
+    }
+
+    /// The larger of two bounds, e.g. `max(0, i - K)`.
+    pub fn max(self, other: LoopBound) -> LoopBound {
+        match (self.as_affine(), other.as_affine()) {
+            (Some(a), Some(b)) if (a.clone() - b.clone()).is_constant() => {
+                if a.constant >= b.constant {
+                    self
+                } else {
+                    other
+                }
+            }
+            _ => LoopBound::Max(Box::new(self), Box::new(other)),
+        }
+    }
+
+    /// `floor(self / k)`. Stays affine when `k` divides every coefficient.
+    pub fn floor_div(self, k: i32) -> LoopBound {
+        assert!(k != 0, "division of a loop bound by zero");
+        match self.as_affine() {
+            _ if k == 1 => self,
+            Some(e) if e.coeffs.iter().all(|c| c % k == 0) => {
+                let coeffs = e.coeffs.iter().map(|c| c / k).collect();
+                AffineExpr::new(coeffs, floor_div(e.constant, k)).into()
+            }
+            _ => LoopBound::FloorDiv(Box::new(self), k),
+        }
+    }
+
+    /// `ceil(self / k)`. Stays affine when `k` divides every coefficient.
+    pub fn ceil_div(self, k: i32) -> LoopBound {
+        assert!(k != 0, "division of a loop bound by zero");
+        match self.as_affine() {
+            _ if k == 1 => self,
+            Some(e) if e.coeffs.iter().all(|c| c % k == 0) => {
+                let coeffs = e.coeffs.iter().map(|c| c / k).collect();
+                AffineExpr::new(coeffs, -floor_div(-e.constant, k)).into()
+            }
+            _ => LoopBound::CeilDiv(Box::new(self), k),
+        }
+    }
+
+    /// The value of the bound for the enclosing loop indices `ivec`, outermost first.
+    pub fn eval(&self, ivec: &[i32]) -> i32 {
+        match self {
+            LoopBound::Fixed(x) => *x,
+            LoopBound::Dynamic(f) => f(ivec),
+            LoopBound::Affine { a, b } => {
+                a.iter().zip(ivec.iter()).map(|(x, y)| x * y).sum::<i32>() + b
+            }
+            LoopBound::Min(x, y) => x.eval(ivec).min(y.eval(ivec)),
+            LoopBound::Max(x, y) => x.eval(ivec).max(y.eval(ivec)),
+            LoopBound::FloorDiv(x, k) => floor_div(x.eval(ivec), *k),
+            LoopBound::CeilDiv(x, k) => -floor_div(-x.eval(ivec), *k),
+        }
+    }
+
+    /// The bound as an affine expression, if it is `Fixed` or `Affine`.
+    pub fn as_affine(&self) -> Option<AffineExpr> {
+        match self {
+            LoopBound::Fixed(x) => Some(AffineExpr::constant(*x)),
+            LoopBound::Affine { a, b } => Some(AffineExpr::new(a.clone(), *b)),
+            _ => None,
+        }
+    }
+
+    /// Whether the bound is built only from affine pieces, i.e. contains no `Dynamic` closure
+    /// and can be analysed without running it.
+    pub fn is_symbolic(&self) -> bool {
+        match self {
+            LoopBound::Fixed(_) | LoopBound::Affine { .. } => true,
+            LoopBound::Dynamic(_) => false,
+            LoopBound::Min(x, y) | LoopBound::Max(x, y) => x.is_symbolic() && y.is_symbolic(),
+            LoopBound::FloorDiv(x, _) | LoopBound::CeilDiv(x, _) => x.is_symbolic(),
+        }
+    }
+
+    /// Renders the bound using `names` for the enclosing loop indices, e.g. `min(ii+32, 100)`.
+    pub fn to_string_with(&self, names: &[String]) -> String {
+        match self {
+            LoopBound::Dynamic(_) => "Dynamic".to_string(),
+            LoopBound::Min(x, y) => {
+                format!(
+                    "min({}, {})",
+                    x.to_string_with(names),
+                    y.to_string_with(names)
+                )
+            }
+            LoopBound::Max(x, y) => {
+                format!(
+                    "max({}, {})",
+                    x.to_string_with(names),
+                    y.to_string_with(names)
+                )
+            }
+            LoopBound::FloorDiv(x, k) => format!("floord({}, {})", x.to_string_with(names), k),
+            LoopBound::CeilDiv(x, k) => format!("ceild({}, {})", x.to_string_with(names), k),
+            _ => self.as_affine().unwrap().to_string_with(names),
+        }
+    }
+
+    fn is_extremum(&self) -> bool {
+        matches!(self, LoopBound::Min(..) | LoopBound::Max(..))
+    }
+
+    /// Applies `f` to both operands of a `min`/`max`, turning one into the other when `f`
+    /// is decreasing.
+    fn distribute(self, decreasing: bool, f: impl Fn(LoopBound) -> LoopBound) -> LoopBound {
+        match (self, decreasing) {
+            (LoopBound::Min(x, y), false) | (LoopBound::Max(x, y), true) => f(*x).min(f(*y)),
+            (LoopBound::Max(x, y), false) | (LoopBound::Min(x, y), true) => f(*x).max(f(*y)),
+            (other, _) => f(other),
+        }
+    }
+
+    /// Falls back to evaluating both operands at run time.
+    fn lift(self, other: LoopBound, op: fn(i32, i32) -> i32) -> LoopBound {
+        LoopBound::Dynamic(Rc::new(move |ivec: &[i32]| {
+            op(self.eval(ivec), other.eval(ivec))
+        }))
+    }
 }
 
-loopbound_binop!(Add, add);
-loopbound_binop!(Sub, sub);
-loopbound_binop!(Mul, mul);
-loopbound_binop!(Div, div);
+fn floor_div(a: i32, k: i32) -> i32 {
+    let q = a / k;
+    if a % k != 0 && (a < 0) != (k < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+// Arithmetic stays symbolic as long as the result is affine (or a min/max of affine pieces)
+// and only falls back to a `Dynamic` closure otherwise.
+impl Add for LoopBound {
+    type Output = Self;
+
+    fn add(self, other: LoopBound) -> LoopBound {
+        match (self.as_affine(), other.as_affine()) {
+            (Some(a), Some(b)) => (a + b).into(),
+            (None, Some(b)) if self.is_extremum() => {
+                self.distribute(false, |x| x + b.clone().into())
+            }
+            (Some(a), None) if other.is_extremum() => {
+                other.distribute(false, |x| LoopBound::from(a.clone()) + x)
+            }
+            _ => self.lift(other, Add::add),
+        }
+    }
+}
+
+impl Sub for LoopBound {
+    type Output = Self;
+
+    fn sub(self, other: LoopBound) -> LoopBound {
+        match (self.as_affine(), other.as_affine()) {
+            (Some(a), Some(b)) => (a - b).into(),
+            (None, Some(b)) if self.is_extremum() => {
+                self.distribute(false, |x| x - b.clone().into())
+            }
+            (Some(a), None) if other.is_extremum() => {
+                other.distribute(true, |x| LoopBound::from(a.clone()) - x)
+            }
+            _ => self.lift(other, Sub::sub),
+        }
+    }
+}
+
+impl Mul for LoopBound {
+    type Output = Self;
+
+    fn mul(self, other: LoopBound) -> LoopBound {
+        match (self.as_affine(), other.as_affine()) {
+            (Some(a), Some(b)) if b.is_constant() => (a * b.constant).into(),
+            (Some(a), Some(b)) if a.is_constant() => (b * a.constant).into(),
+            (None, Some(b)) if self.is_extremum() && b.is_constant() => {
+                self.distribute(b.constant < 0, |x| x * b.constant.into())
+            }
+            (Some(a), None) if other.is_extremum() && a.is_constant() => {
+                other.distribute(a.constant < 0, |x| x * a.constant.into())
+            }
+            _ => self.lift(other, Mul::mul),
+        }
+    }
+}
+
+/// Integer division truncating toward zero, like `i32`. Use `floor_div`/`ceil_div` for the
+/// rounding usually wanted in tiled bounds.
+impl Div for LoopBound {
+    type Output = Self;
+
+    fn div(self, other: LoopBound) -> LoopBound {
+        match (self.as_affine(), other.as_affine()) {
+            (Some(a), Some(b)) if b.is_constant() && b.constant != 0 => {
+                let k = b.constant;
+                if a.coeffs.iter().all(|c| c % k == 0) && a.constant % k == 0 {
+                    let coeffs = a.coeffs.iter().map(|c| c / k).collect();
+                    AffineExpr::new(coeffs, a.constant / k).into()
+                } else if a.is_constant() {
+                    LoopBound::Fixed(a.constant / k)
+                } else {
+                    self.lift(other, Div::div)
+                }
+            }
+            _ => self.lift(other, Div::div),
+        }
+    }
+}
 
 #[macro_export]
 macro_rules! dynamic {
@@ -196,7 +651,7 @@ macro_rules! dynamic {
 #[macro_export]
 macro_rules! loop_node {
     ($ivar:expr, $lb:expr => $ub:expr) => {
-        $crate::ast::Node::new_loop($ivar, $lb.into(), $ub.into(), |i, ub| i < ub, |i| i + 1)
+        $crate::ast::Node::new_strided_loop($ivar, $lb.into(), $ub.into(), $crate::ast::Cmp::Lt, 1)
     };
     ($ivar:expr, $lb:expr => $ub:expr, step: $step:expr) => {
         $crate::ast::Node::new_loop($ivar, $lb.into(), $ub.into(), |i, ub| i < ub, $step)
@@ -213,46 +668,59 @@ macro_rules! loop_node {
 macro_rules! branch_node {
     (if ($cond:expr) {$then:tt}) => {
         $crate::ast::Node::new_node($crate::ast::Stmt::Branch($crate::ast::BranchStmt {
-            cond: Box::new($cond),
+            cond: std::rc::Rc::new($cond),
+            pred: None,
             then_body: $then,
             else_body: None,
         }))
     };
     (if ($cond:expr) {$then:tt} else {$else:tt}) => {
         $crate::ast::Node::new_node($crate::ast::Stmt::Branch($crate::ast::BranchStmt {
-            cond: Box::new($cond),
+            cond: std::rc::Rc::new($cond),
+            pred: None,
             then_body: $then,
             else_body: Some($else),
         }))
     };
 }
 
-fn print_bounds(bound: &LoopBound) {
-    match bound {
-        LoopBound::Fixed(val) => print!("{}", val),
-        LoopBound::Dynamic(_) => print!("Dynamic"),
-        LoopBound::Affine { a, b } => print!("Affine({:?}, {})", a, b),
-    }
-}
-
 impl Node {
     pub fn print_structure(&self, indent: usize) {
+        self.print_structure_in(indent, &mut vec![]);
+    }
+
+    /// `ivs` holds the indices of the loops enclosing `self`, outermost first.
+    fn print_structure_in(&self, indent: usize, ivs: &mut Vec<String>) {
         let indentation = " ".repeat(indent);
         match &self.stmt {
             Stmt::Loop(loop_stmt) => {
-                print!("{}Loop: {}: ", indentation, loop_stmt.iv);
-                print_bounds(&loop_stmt.lb);
-                print!(" to ");
-                print_bounds(&loop_stmt.ub);
+                print!(
+                    "{}Loop: {}: {} to {}",
+                    indentation,
+                    loop_stmt.iv,
+                    loop_stmt.lb.to_string_with(ivs),
+                    loop_stmt.ub.to_string_with(ivs)
+                );
                 // print!("  *Next index: {}", (loop_stmt.step)(0));
-                println!(" *Rank_{}", loop_stmt.rank);
+                println!(" *Rank_{}", ivs.len());
 
-                for child in &loop_stmt.body {
-                    child.print_structure(indent + 2);
+                ivs.push(loop_stmt.iv.clone());
+                for child in loop_stmt.body.borrow().iter() {
+                    child.print_structure_in(indent + 2, ivs);
                 }
+                ivs.pop();
             }
             Stmt::Ref(ary_ref) => {
-                if ary_ref.indices.is_empty() {
+                if let (true, Some(subs)) = (ary_ref.indices.is_empty(), &ary_ref.sub_affine) {
+                    let named_indices: Vec<String> =
+                        subs.iter().map(|e| e.to_string_with(ivs)).collect();
+                    println!(
+                        "{}{}[{}]",
+                        indentation,
+                        ary_ref.name,
+                        named_indices.join(", ")
+                    );
+                } else if ary_ref.indices.is_empty() {
                     let indices = (ary_ref.sub)(&[0, 1, 2, 3]); // Assuming a 3-dimensional array
                     let named_indices: Vec<String> = indices
                         .iter()
@@ -282,29 +750,25 @@ impl Node {
             Stmt::Block(children) => {
                 println!("{}Block", indentation);
                 for child in children {
-                    child.print_structure(indent + 2);
+                    child.print_structure_in(indent + 2, ivs);
                 }
             }
             Stmt::Branch(branch_stmt) => {
                 println!("{}Branch then", indentation);
-                branch_stmt.then_body.print_structure(indent + 2);
+                branch_stmt.then_body.print_structure_in(indent + 2, ivs);
                 if let Some(else_body) = &branch_stmt.else_body {
                     println!("{}Else", indentation);
-                    else_body.print_structure(indent + 2);
+                    else_body.print_structure_in(indent + 2, ivs);
                 }
             }
         }
     }
 
-    pub fn rank(&self) -> Option<i32> {
-        self.loop_only(|lp| lp.rank)
-    }
-
     /// Create a new Node with a given statement.
     pub fn new_node(a_stmt: Stmt) -> Rc<Node> {
         Rc::new(Node {
             stmt: a_stmt,
-            parent: Weak::new(),
+            parent: RefCell::new(Weak::new()),
         })
     }
 
@@ -316,15 +780,71 @@ impl Node {
             name: ary_nm.to_string(),
             dim: ary_dim,
             indices: vec![],
-            sub: Box::new(ary_sub),
-            base: None,
-            ref_id: None,
-            access_matrix: vec![],
+            sub: Rc::new(ary_sub),
+            sub_affine: None,
+            ri: vec![],
+            kind: AccessKind::Read,
+            elem: None,
+            layout: Layout::RowMajor,
+        };
+        Node::new_node(Stmt::Ref(ref_stmt))
+    }
+
+    /// Create a reference node whose subscripts are affine in the enclosing loop indices.
+    pub fn new_affine_ref(ary_nm: &str, ary_dim: Vec<usize>, ary_sub: Vec<AffineExpr>) -> Rc<Node> {
+        let ref_stmt = AryRef {
+            name: ary_nm.to_string(),
+            dim: ary_dim,
+            indices: vec![],
+            sub: compile_sub(&ary_sub),
+            sub_affine: Some(ary_sub),
             ri: vec![],
+            kind: AccessKind::Read,
+            elem: None,
+            layout: Layout::RowMajor,
         };
         Node::new_node(Stmt::Ref(ref_stmt))
     }
 
+    /// Set the access kind of a freshly built reference node, e.g.
+    /// `Node::new_ref("C", dim, sub).with_kind(AccessKind::Write)`. References are reads unless
+    /// set otherwise.
+    ///
+    /// # Panics
+    /// If `self` is not a reference, or is already shared (it has been put in a loop body or
+    /// cloned).
+    pub fn with_kind(self: Rc<Self>, kind: AccessKind) -> Rc<Self> {
+        self.with_ref(|aref| aref.kind = kind)
+    }
+
+    /// Set the element type of the array a freshly built reference node accesses, e.g.
+    /// `Node::new_ref("X", dim, sub).with_elem(ElemType::new(4))` for an `int` array.
+    ///
+    /// # Panics
+    /// As `with_kind`.
+    pub fn with_elem(self: Rc<Self>, elem: ElemType) -> Rc<Self> {
+        self.with_ref(|aref| aref.elem = Some(elem))
+    }
+
+    /// Set the layout of the array a freshly built reference node accesses, e.g.
+    /// `Node::new_ref("A", dim, sub).with_layout(Layout::ColumnMajor)`. Arrays are row-major
+    /// unless set otherwise.
+    ///
+    /// # Panics
+    /// As `with_kind`.
+    pub fn with_layout(self: Rc<Self>, layout: Layout) -> Rc<Self> {
+        self.with_ref(|aref| aref.layout = layout)
+    }
+
+    fn with_ref(mut self: Rc<Self>, f: impl FnOnce(&mut AryRef)) -> Rc<Self> {
+        let node = Rc::get_mut(&mut self).expect("the reference node is already shared");
+        match &mut node.stmt {
+            Stmt::Ref(aref) => f(aref),
+            _ => panic!("only a reference has an access kind, element type or layout"),
+        }
+        self
+    }
+
     pub fn new_loop<F, G>(ivar: &str, lb: LoopBound, ub: LoopBound, test: F, step: G) -> Rc<Self>
     where
         F: Fn(i32, i32) -> bool + 'static,
@@ -334,21 +854,60 @@ impl Node {
             iv: ivar.to_string(),
             lb,
             ub,
-            test: Box::new(test),
-            step: Box::new(step),
-            body: vec![],
-            rank: 0,
+            test: Rc::new(test),
+            step: Rc::new(step),
+            cmp: None,
+            stride: None,
+            body: RefCell::new(vec![]),
+        };
+        Self::new_node(Stmt::Loop(loop_stmt))
+    }
+
+    /// Create a loop `for (iv = lb; iv cmp ub; iv += stride)` whose test and step are known
+    /// symbolically.
+    pub fn new_strided_loop(
+        ivar: &str,
+        lb: LoopBound,
+        ub: LoopBound,
+        cmp: Cmp,
+        stride: i32,
+    ) -> Rc<Self> {
+        let loop_stmt = LoopStmt {
+            iv: ivar.to_string(),
+            lb,
+            ub,
+            test: Rc::new(move |i, ub| cmp.eval(i, ub)),
+            step: Rc::new(move |i| i + stride),
+            cmp: Some(cmp),
+            stride: Some(stride),
+            body: RefCell::new(vec![]),
         };
         Self::new_node(Stmt::Loop(loop_stmt))
     }
 
+    /// Create a branch `if (pred) then_body else else_body` whose predicate is known
+    /// symbolically.
+    pub fn new_branch(
+        pred: Predicate,
+        then_body: Rc<Node>,
+        else_body: Option<Rc<Node>>,
+    ) -> Rc<Self> {
+        let cond = pred.clone();
+        Self::new_node(Stmt::Branch(BranchStmt {
+            cond: Rc::new(move |ivec: &[i32]| cond.eval(ivec)),
+            pred: Some(pred),
+            then_body,
+            else_body,
+        }))
+    }
+
     pub fn new_single_loop(ivar: &str, low: i32, high: i32) -> Rc<Self> {
-        Self::new_loop(
+        Self::new_strided_loop(
             ivar,
             LoopBound::Fixed(low),
             LoopBound::Fixed(high),
-            |i, ub| i < ub,
-            |i| i + 1,
+            Cmp::Lt,
+            1,
         )
     }
 
@@ -357,23 +916,21 @@ impl Node {
         low: i32,
         ub: Box<types::DynamicBoundFunction>,
     ) -> Rc<Self> {
-        Self::new_loop(
+        Self::new_strided_loop(
             ivar,
             LoopBound::Fixed(low),
-            LoopBound::Dynamic(ub),
-            |i, ub| i < ub,
-            |i| i + 1,
+            LoopBound::Dynamic(Rc::from(ub)),
+            Cmp::Lt,
+            1,
         )
     }
 
     /// Extend the body of a loop node with another node.
     pub fn extend_loop_body(lup: &mut Rc<Node>, stmt: &mut Rc<Node>) {
-        let lup_node = unsafe { Rc::get_mut_unchecked(lup) };
-        lup_node.loop_only_mut(|lp| lp.body.push(Rc::clone(stmt)));
+        lup.loop_only(|lp| lp.body.borrow_mut().push(Rc::clone(stmt)));
 
         // officiating the parent-child relationship
-        let stmt_node = unsafe { Rc::get_mut_unchecked(stmt) };
-        stmt_node.parent = Rc::downgrade(lup);
+        *stmt.parent.borrow_mut() = Rc::downgrade(lup);
     }
 
     pub fn loop_only<U, F>(&self, f: F) -> Option<U>
@@ -444,7 +1001,14 @@ impl Node {
         match &self.stmt {
             //    The body of a loop is a vector of Node's, so we need to
             //    iterate over the vector and sum the sanity of each node.
-            Stmt::Loop(a_loop) => 1 + a_loop.body.iter().map(|x| x.node_count()).sum::<u32>(),
+            Stmt::Loop(a_loop) => {
+                1 + a_loop
+                    .body
+                    .borrow()
+                    .iter()
+                    .map(|x| x.node_count())
+                    .sum::<u32>()
+            }
             Stmt::Ref(_) => 1,
             Stmt::Block(children) => 1 + children.iter().map(|x| x.node_count()).sum::<u32>(),
             Stmt::Branch(stmt) => {
@@ -454,6 +1018,118 @@ impl Node {
             }
         }
     }
+
+    /// Copy the whole tree, so that the copy can be changed or analysed independently.
+    /// The (immutable) closures are shared with the original; a node that appears at several
+    /// places is copied once per place.
+    pub fn deep_clone(&self) -> Rc<Node> {
+        match &self.stmt {
+            Stmt::Ref(aref) => Node::new_node(Stmt::Ref(aref.clone())),
+            Stmt::Loop(aloop) => {
+                let mut copy = Node::new_node(Stmt::Loop(LoopStmt {
+                    iv: aloop.iv.clone(),
+                    lb: aloop.lb.clone(),
+                    ub: aloop.ub.clone(),
+                    test: Rc::clone(&aloop.test),
+                    step: Rc::clone(&aloop.step),
+                    cmp: aloop.cmp,
+                    stride: aloop.stride,
+                    body: RefCell::new(vec![]),
+                }));
+                for child in aloop.body.borrow().iter() {
+                    Node::extend_loop_body(&mut copy, &mut child.deep_clone());
+                }
+                copy
+            }
+            Stmt::Block(children) => Node::adopt_children(Node::new_node(Stmt::Block(
+                children.iter().map(|child| child.deep_clone()).collect(),
+            ))),
+            Stmt::Branch(branch) => {
+                Node::adopt_children(Node::new_node(Stmt::Branch(BranchStmt {
+                    cond: Rc::clone(&branch.cond),
+                    pred: branch.pred.clone(),
+                    then_body: branch.then_body.deep_clone(),
+                    else_body: branch.else_body.as_ref().map(|e| e.deep_clone()),
+                })))
+            }
+        }
+    }
+
+    /// Points the children of a block, or the arms of a branch, back at `node`. A loop's
+    /// children get theirs from `extend_loop_body`.
+    pub(crate) fn adopt_children(node: Rc<Node>) -> Rc<Node> {
+        let children: Vec<&Rc<Node>> = match &node.stmt {
+            Stmt::Block(children) => children.iter().collect(),
+            Stmt::Branch(branch) => std::iter::once(&branch.then_body)
+                .chain(branch.else_body.as_ref())
+                .collect(),
+            Stmt::Loop(_) | Stmt::Ref(_) => vec![],
+        };
+        for child in children {
+            *child.parent.borrow_mut() = Rc::downgrade(&node);
+        }
+        node
+    }
+
+    /// Whether two trees have the same loop structure, bounds and references.
+    ///
+    /// Subscripts, bounds and loop tests are compared by their symbolic forms; closures that
+    /// have none are equal only if they are the same closure, as in a `deep_clone`.
+    pub fn structural_eq(&self, other: &Node) -> bool {
+        fn same<T: ?Sized>(a: &Rc<T>, b: &Rc<T>) -> bool {
+            Rc::ptr_eq(a, b)
+        }
+        fn all_eq(a: &[Rc<Node>], b: &[Rc<Node>]) -> bool {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.structural_eq(y))
+        }
+        match (&self.stmt, &other.stmt) {
+            (Stmt::Ref(a), Stmt::Ref(b)) => {
+                a.name == b.name
+                    && a.kind == b.kind
+                    && a.elem == b.elem
+                    && a.layout == b.layout
+                    && a.dim == b.dim
+                    && a.indices == b.indices
+                    && match (&a.sub_affine, &b.sub_affine) {
+                        (Some(x), Some(y)) => x == y,
+                        (None, None) => same(&a.sub, &b.sub),
+                        _ => false,
+                    }
+            }
+            (Stmt::Loop(a), Stmt::Loop(b)) => {
+                a.iv == b.iv
+                    && a.lb == b.lb
+                    && a.ub == b.ub
+                    && match (a.cmp, b.cmp) {
+                        (Some(x), Some(y)) => x == y,
+                        (None, None) => same(&a.test, &b.test),
+                        _ => false,
+                    }
+                    && match (a.stride, b.stride) {
+                        (Some(x), Some(y)) => x == y,
+                        (None, None) => same(&a.step, &b.step),
+                        _ => false,
+                    }
+                    && all_eq(&a.body.borrow(), &b.body.borrow())
+            }
+            (Stmt::Block(a), Stmt::Block(b)) => all_eq(a, b),
+            (Stmt::Branch(a), Stmt::Branch(b)) => {
+                let same_cond = match (&a.pred, &b.pred) {
+                    (Some(x), Some(y)) => x == y,
+                    (None, None) => same(&a.cond, &b.cond),
+                    _ => false,
+                };
+                same_cond
+                    && a.then_body.structural_eq(&b.then_body)
+                    && match (&a.else_body, &b.else_body) {
+                        (Some(x), Some(y)) => x.structural_eq(y),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -468,11 +1144,12 @@ mod tests {
             name: "X".to_string(),
             dim: vec![10],
             indices: vec![],
-            sub: Box::new(|iv| vec![(iv[0] as usize) + 1]),
-            base: None,
-            ref_id: None,
-            access_matrix: vec![],
+            sub: Rc::new(|iv| vec![(iv[0] as usize) + 1]),
+            sub_affine: None,
             ri: vec![],
+            kind: AccessKind::Read,
+            elem: None,
+            layout: Layout::RowMajor,
         };
         assert_eq!((ar.sub)(&[1]), [2]);
     }
@@ -483,15 +1160,199 @@ mod tests {
             name: "A".to_string(),
             dim: vec![10, 10],
             indices: vec![],
-            sub: Box::new(|ijk| vec![ijk[0] as usize, ijk[1] as usize]),
-            base: None,
-            ref_id: None,
-            access_matrix: vec![],
+            sub: Rc::new(|ijk| vec![ijk[0] as usize, ijk[1] as usize]),
+            sub_affine: None,
             ri: vec![],
+            kind: AccessKind::Read,
+            elem: None,
+            layout: Layout::RowMajor,
         };
         assert_eq!((ar.sub)(&[1, 2, 3]), [1, 2]);
     }
 
+    #[test]
+    fn deep_clone() {
+        // i { j { A[i][j] }; B[i] }
+        let mut iloop = loop_node!("i", 0 => 10);
+        let mut jloop = loop_node!("j", 0 => |iv: &[i32]| iv[0]);
+        let mut aref = Node::new_affine_ref(
+            "A",
+            vec![10, 10],
+            vec![AffineExpr::var(0), AffineExpr::var(1)],
+        );
+        let mut bref = Node::new_ref("B", vec![10], |iv| vec![iv[0] as usize]);
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        Node::extend_loop_body(&mut iloop, &mut bref);
+
+        let mut copy = iloop.deep_clone();
+        assert!(!Rc::ptr_eq(&copy, &iloop));
+        assert!(copy.structural_eq(&iloop));
+        let copied_j = copy
+            .loop_only(|lp| Rc::clone(&lp.body.borrow()[0]))
+            .unwrap();
+        assert!(!Rc::ptr_eq(&copied_j, &jloop));
+        assert!(Rc::ptr_eq(
+            &copied_j.parent.borrow().upgrade().unwrap(),
+            &copy
+        ));
+
+        // changing the copy leaves the original alone
+        Node::extend_loop_body(&mut copy, &mut Node::new_ref("C", vec![1], |_| vec![0]));
+        assert_eq!(iloop.node_count(), 4);
+        assert_eq!(copy.node_count(), 5);
+        assert!(!copy.structural_eq(&iloop));
+    }
+
+    #[test]
+    fn deep_clone_parents() {
+        // i { if (i < 5) { A[i]; B[i] } else C[i] }
+        let mut iloop = loop_node!("i", 0 => 10);
+        let block = Node::new_node(Stmt::Block(vec![
+            Node::new_ref("A", vec![10], |iv| vec![iv[0] as usize]),
+            Node::new_ref("B", vec![10], |iv| vec![iv[0] as usize]),
+        ]));
+        let cref = Node::new_ref("C", vec![10], |iv| vec![iv[0] as usize]);
+        let mut branch = branch_node!(if (|iv: &[i32]| iv[0] < 5) {
+            block
+        } else {
+            cref
+        });
+        Node::extend_loop_body(&mut iloop, &mut branch);
+
+        let copy = iloop.deep_clone();
+        assert!(copy.structural_eq(&iloop));
+        let parent = |node: &Rc<Node>| node.parent.borrow().upgrade().unwrap();
+        let copied_branch = copy
+            .loop_only(|lp| Rc::clone(&lp.body.borrow()[0]))
+            .unwrap();
+        assert!(Rc::ptr_eq(&parent(&copied_branch), &copy));
+        let Stmt::Branch(arms) = &copied_branch.stmt else {
+            panic!("the branch is not copied")
+        };
+        assert!(Rc::ptr_eq(&parent(&arms.then_body), &copied_branch));
+        let else_body = arms.else_body.as_ref().unwrap();
+        assert!(Rc::ptr_eq(&parent(else_body), &copied_branch));
+        let Stmt::Block(refs) = &arms.then_body.stmt else {
+            panic!("the block is not copied")
+        };
+        assert!(refs.iter().all(|r| Rc::ptr_eq(&parent(r), &arms.then_body)));
+    }
+
+    #[test]
+    fn structural_eq() {
+        let src = "param N = 8; double A[N][N];
+                   for (i = 0; i < N; i++) for (j = i; j <= min(i + 4, N - 1); j += 2) A[i][j];";
+        let a = crate::parse::parse(src, &[]).unwrap();
+        let b = crate::parse::parse(src, &[]).unwrap();
+        assert!(a.structural_eq(&b));
+        // different bound, loop test and subscript
+        let c = crate::parse::parse(src, &[("N", 9)]).unwrap();
+        assert!(!a.structural_eq(&c));
+        let d = crate::parse::parse(&src.replace("<=", "<"), &[]).unwrap();
+        assert!(!a.structural_eq(&d));
+        let e = crate::parse::parse(&src.replace("A[i][j]", "A[j][i]"), &[]).unwrap();
+        assert!(!a.structural_eq(&e));
+        // closures without a symbolic form only equal themselves
+        let f = loop_node!("i", 0 => 10, step: |i| i + 1);
+        let g = loop_node!("i", 0 => 10, step: |i| i + 1);
+        assert!(!f.structural_eq(&g));
+        assert!(f.structural_eq(&f.deep_clone()));
+    }
+
+    #[test]
+    fn access_kind() {
+        let sub = |iv: &[i32]| vec![iv[0] as usize];
+        let read = Node::new_ref("C", vec![10], sub);
+        let write = Node::new_ref("C", vec![10], sub).with_kind(AccessKind::Write);
+        assert_eq!(read.ref_only(|r| r.kind), Some(AccessKind::Read));
+        assert_eq!(write.ref_only(|r| r.kind), Some(AccessKind::Write));
+        assert!(!read.structural_eq(&write));
+        assert!(write.structural_eq(&write.deep_clone()));
+        assert!(AccessKind::ReadWrite.reads() && AccessKind::ReadWrite.writes());
+        assert!(!AccessKind::Write.reads() && !AccessKind::Read.writes());
+
+        // an index-named reference keeps its kind when inserted into a loop
+        let mut iloop = loop_node!("i", 0 => 10);
+        let mut update =
+            crate::construct::a_ref("C", vec![10], vec!["i"]).with_kind(AccessKind::ReadWrite);
+        crate::construct::insert_node(&mut iloop, &mut update);
+        assert_eq!(update.ref_only(|r| r.kind), Some(AccessKind::ReadWrite));
+    }
+
+    #[test]
+    #[should_panic(expected = "already shared")]
+    fn access_kind_of_shared_ref() {
+        let aref = Node::new_ref("A", vec![1], |_| vec![0]);
+        let _alias = Rc::clone(&aref);
+        aref.with_kind(AccessKind::Write);
+    }
+
+    #[test]
+    fn loopbound_arithmetic() {
+        let i = || LoopBound::from(AffineExpr::var(0));
+        // Fixed and affine operands stay symbolic.
+        let b = (i() + 2.into()) * 3.into() - i();
+        assert_eq!(format!("{:?}", b), "Affine([2], 6)");
+        assert_eq!(format!("{:?}", LoopBound::from(4) * i()), "Affine([4], 0)");
+        assert_eq!(
+            format!("{:?}", (i() * 4.into()) / 2.into()),
+            "Affine([2], 0)"
+        );
+        assert_eq!(format!("{:?}", LoopBound::from(7) / 2.into()), "Fixed(3)");
+        // Non-affine results fall back to closures.
+        let sq = i() * i();
+        assert!(!sq.is_symbolic());
+        assert_eq!(sq.eval(&[5]), 25);
+        assert_eq!((i() / 2.into()).eval(&[5]), 2);
+    }
+
+    #[test]
+    fn loopbound_composites() {
+        let names = ["ii".to_string()];
+        let ii = || LoopBound::from(AffineExpr::var(0));
+        let ub = (ii() + 32.into()).min(100.into());
+        assert_eq!(ub.to_string_with(&names), "min(ii+32, 100)");
+        assert_eq!(ub.eval(&[64]), 96);
+        assert_eq!(ub.eval(&[96]), 100);
+        // Bounds differing by a constant fold.
+        assert_eq!(format!("{:?}", ii().min(ii() + 1.into())), "Affine([1], 0)");
+        assert_eq!(
+            format!("{:?}", LoopBound::from(3).max(5.into())),
+            "Fixed(5)"
+        );
+        // Arithmetic distributes over min/max, flipping it under negation.
+        let shifted = ub + 1.into();
+        assert_eq!(shifted.to_string_with(&names), "min(ii+33, 101)");
+        let flipped = LoopBound::from(0) - shifted;
+        assert_eq!(flipped.to_string_with(&names), "max(-ii-33, -101)");
+        assert!(flipped.is_symbolic());
+        // Division rounds toward the requested side and stays affine when exact.
+        assert_eq!(LoopBound::from(-7).floor_div(2).eval(&[]), -4);
+        assert_eq!(LoopBound::from(-7).ceil_div(2).eval(&[]), -3);
+        assert_eq!(
+            format!("{:?}", (ii() * 4.into() + 3.into()).floor_div(4)),
+            "Affine([1], 0)"
+        );
+        let tiles = (ii() + 1.into()).ceil_div(4);
+        assert_eq!(tiles.to_string_with(&names), "ceild(ii+1, 4)");
+        assert_eq!(tiles.eval(&[8]), 3);
+    }
+
+    #[test]
+    fn affine_ref() {
+        // A[i+1][2*j-k]
+        let subs = vec![
+            AffineExpr::var(0) + 1.into(),
+            AffineExpr::var(1) * 2 - AffineExpr::var(2),
+        ];
+        let node = Node::new_affine_ref("A", vec![10, 10], subs.clone());
+        node.ref_only(|ar| {
+            assert_eq!((ar.sub)(&[1, 2, 3]), [2, 1]);
+            assert_eq!(ar.sub_affine.as_ref(), Some(&subs));
+        });
+    }
+
     #[test]
     fn matmul() {
         let n: usize = 100; // array dim
@@ -646,6 +1507,25 @@ mod tests {
         assert_eq!(i_loop.node_count(), 5);
     }
 
+    #[test]
+    fn predicates() {
+        // i - j != 0 && !(j == 0)
+        let i_j = AffineExpr::var(0) - AffineExpr::var(1);
+        let pred = Predicate::And(
+            Box::new(Predicate::Cmp(Cmp::Ne, i_j)),
+            Box::new(Predicate::Not(Box::new(Predicate::Cmp(
+                Cmp::Eq,
+                AffineExpr::var(1),
+            )))),
+        );
+        assert!(pred.eval(&[2, 1]));
+        assert!(!pred.eval(&[1, 1]) && !pred.eval(&[1, 0]));
+        let names = ["i".to_string(), "j".to_string()];
+        assert_eq!(pred.to_string_with(&names), "i-j != 0 && !(j == 0)");
+        assert_eq!(Cmp::from_op("<="), Some(Cmp::Le));
+        assert_eq!(Cmp::from_op("=<"), None);
+    }
+
     // #[test]
     // fn mat_transpose2() {
     //     let n: usize = 1024;
@@ -0,0 +1,130 @@
+//! Subscript checking for the tracers' checked mode.
+//!
+//! A subscript outside its array's dimension silently aliases another element, or another
+//! array, and a negative one wraps around when cast to `usize`. In checked mode a tracer
+//! validates every access with `checked_sub` and stops at the first bad one.
+
+use std::fmt;
+
+use crate::ast::AryRef;
+use crate::types::AryAcc;
+
+/// An access outside of its array, with where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundsError {
+    /// Name of the kernel being traced.
+    pub kernel: String,
+    /// Name of the array.
+    pub array: String,
+    /// Id of the reference site, see `Program::ref_id`.
+    pub ref_id: usize,
+    /// The iteration vector of the access.
+    pub ivec: Vec<i32>,
+    /// The subscripts of the access, negative ones as such.
+    pub index: Vec<isize>,
+    /// The first subscript out of range, or, if there are not as many subscripts as
+    /// dimensions, the first one of either that has no counterpart.
+    pub dim: usize,
+    /// The extent of that dimension, 0 if the array has no such dimension.
+    pub extent: usize,
+    /// The number of dimensions of the array.
+    pub rank: usize,
+}
+
+impl fmt::Display for BoundsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subs: Vec<String> = self.index.iter().map(|i| i.to_string()).collect();
+        write!(
+            f,
+            "{}: reference {} accesses {}[{}] at iteration {:?}, but ",
+            self.kernel,
+            self.ref_id,
+            self.array,
+            subs.join("]["),
+            self.ivec
+        )?;
+        if self.index.len() != self.rank {
+            write!(f, "the array has {} dimensions", self.rank)
+        } else {
+            write!(f, "subscript {} is outside 0..{}", self.dim, self.extent)
+        }
+    }
+}
+
+impl std::error::Error for BoundsError {}
+
+/// The subscripts of `aref` at `ivec`, or the error if one is out of range or their number
+/// is not that of the dimensions. The error is boxed to keep the `Ok` path small.
+pub fn checked_sub(
+    kernel: &str,
+    ref_id: usize,
+    aref: &AryRef,
+    ivec: &[i32],
+) -> Result<AryAcc, Box<BoundsError>> {
+    let index = (aref.sub)(ivec);
+    let bad = index.iter().zip(&aref.dim).position(|(&i, &d)| i >= d);
+    let bad = bad.or((index.len() != aref.dim.len()).then(|| index.len().min(aref.dim.len())));
+    match bad {
+        None => Ok(index),
+        Some(dim) => Err(Box::new(BoundsError {
+            kernel: kernel.to_string(),
+            array: aref.name.clone(),
+            ref_id,
+            ivec: ivec.to_vec(),
+            // a negative subscript was cast from an `i32`, so it casts back
+            index: index.iter().map(|&i| i as isize).collect(),
+            dim,
+            extent: aref.dim.get(dim).copied().unwrap_or(0),
+            rank: aref.dim.len(),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Node, Stmt};
+
+    #[test]
+    fn out_of_range() {
+        let node = Node::new_ref("A", vec![4, 4], |ij| {
+            vec![ij[0] as usize, (ij[1] - 1) as usize]
+        });
+        let Stmt::Ref(aref) = &node.stmt else {
+            unreachable!()
+        };
+        assert_eq!(checked_sub("k", 0, aref, &[3, 4]), Ok(vec![3, 3]));
+        let err = checked_sub("k", 0, aref, &[3, 0]).unwrap_err();
+        assert_eq!((err.dim, err.extent), (1, 4));
+        assert_eq!(err.index, [3, -1]);
+        assert_eq!(
+            err.to_string(),
+            "k: reference 0 accesses A[3][-1] at iteration [3, 0], but subscript 1 is outside 0..4"
+        );
+        assert_eq!(checked_sub("k", 0, aref, &[4, 1]).unwrap_err().dim, 0);
+    }
+
+    #[test]
+    fn rank_mismatch() {
+        let node = Node::new_ref("A", vec![4, 4], |ij| vec![ij[0] as usize]);
+        let Stmt::Ref(aref) = &node.stmt else {
+            unreachable!()
+        };
+        let err = checked_sub("k", 2, aref, &[1]).unwrap_err();
+        assert_eq!((err.dim, err.extent, err.rank), (1, 4, 2));
+        assert_eq!(
+            err.to_string(),
+            "k: reference 2 accesses A[1] at iteration [1], but the array has 2 dimensions"
+        );
+
+        let node = Node::new_ref("B", vec![4], |ij| vec![ij[0] as usize, 0]);
+        let Stmt::Ref(aref) = &node.stmt else {
+            unreachable!()
+        };
+        let err = checked_sub("k", 0, aref, &[1]).unwrap_err();
+        assert_eq!((err.dim, err.extent, err.rank), (1, 0, 1));
+        assert!(err.to_string().ends_with("but the array has 1 dimensions"));
+        // a subscript out of range comes first
+        assert_eq!(checked_sub("k", 0, aref, &[5]).unwrap_err().dim, 0);
+    }
+}
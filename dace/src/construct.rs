@@ -1,7 +1,9 @@
 use std::{collections::HashMap, rc::Rc};
 
+use crate::affine::{compile_sub, AffineExpr};
 use crate::ast;
 use crate::ast::{Node, Stmt};
+use crate::layout::Layout;
 use crate::types;
 
 //Giordan's method for nest_loops that Woody (had already) made below.
@@ -43,26 +45,27 @@ pub fn generate_subscript(indices: &[&str], loop_index: &str) -> Box<types::Dyna
         .expect("loop_index does not belong in indices!")
 }
 
-pub fn generate_sub(indices: &[String], loops: &[String]) -> Box<types::DynFunc> {
-    //println!("indices: {:?}", indices);
-    //println!("loops: {:?}", loops);
-
+/// Subscripts naming one loop index per array dimension, as affine expressions over the
+/// loop nest `loops` (outermost first).
+pub fn affine_sub<S: AsRef<str>, T: AsRef<str>>(indices: &[S], loops: &[T]) -> Vec<AffineExpr> {
     let index_map = loops
         .iter()
         .enumerate()
         .fold(HashMap::new(), |mut acc, (index, value)| {
-            acc.insert(value, index);
+            acc.insert(value.as_ref(), index);
             acc
         });
-    let rank_indices: Vec<usize> = indices
+    indices
         .iter()
-        .map(move |loop_index| *index_map.get(loop_index).unwrap())
-        .collect();
+        .map(|loop_index| AffineExpr::var(*index_map.get(loop_index.as_ref()).unwrap()))
+        .collect()
+}
 
-    Box::new(move |ivec: &[i32]| rank_indices.iter().map(|&pos| ivec[pos] as usize).collect())
+pub fn generate_sub(indices: &[String], loops: &[String]) -> Rc<types::DynFunc> {
+    compile_sub(&affine_sub(indices, loops))
 }
 
-pub fn generate_sub_2(indices: &[&str], loops: &[&str]) -> Box<types::DynFunc> {
+pub fn generate_sub_2(indices: &[&str], loops: &[&str]) -> Rc<types::DynFunc> {
     // println!("indices: {:?}", indices);
     // println!("loops: {:?}", loops);
 
@@ -79,7 +82,7 @@ pub fn generate_sub_2(indices: &[&str], loops: &[&str]) -> Box<types::DynFunc> {
         .map(move |loop_index| *index_map.get(loop_index).unwrap())
         .collect();
     // println!("rank_indices: {:?}", rank_indices);
-    Box::new(move |ivec: &[i32]| rank_indices.iter().map(|&pos| ivec[pos] as usize).collect())
+    Rc::new(move |ivec: &[i32]| rank_indices.iter().map(|&pos| ivec[pos] as usize).collect())
 }
 
 // Get the loop indices for a given node.
@@ -90,7 +93,7 @@ pub fn get_loops_indices(node: Rc<Node>) -> Vec<String> {
         if let ast::Stmt::Loop(loop_stmt) = &node.stmt {
             loops.push(loop_stmt.iv.clone());
         }
-        current_node = node.parent.upgrade();
+        current_node = node.parent.borrow().upgrade();
     }
     loops.reverse();
     loops
@@ -108,7 +111,8 @@ pub fn get_loops_indices(node: Rc<Node>) -> Vec<String> {
 /// - `ind`: A vector of string slices, each representing an index used for array referencing.
 ///
 /// # Returns
-/// Returns a `Rc<Node>` pointing to the newly created reference node.
+/// Returns a `Rc<Node>` pointing to the newly created reference node. It is a read; use
+/// `Node::with_kind` to make it a store or an update.
 ///
 /// # Examples
 /// ```rust
@@ -120,11 +124,12 @@ pub fn a_ref(nm: &str, dim: Vec<usize>, ind: Vec<&str>) -> Rc<Node> {
         name: nm.to_string(),
         dim,
         indices: ind.iter().map(|s| s.to_string()).collect(),
-        sub: Box::new(|_i| vec![0]),
-        base: None,
-        ref_id: None,
-        access_matrix: vec![],
+        sub: Rc::new(|_i| vec![0]),
+        sub_affine: None,
         ri: vec![],
+        kind: ast::AccessKind::Read,
+        elem: None,
+        layout: Layout::RowMajor,
     };
     Node::new_node(ast::Stmt::Ref(ref_stmt))
 }
@@ -154,30 +159,27 @@ pub fn squ_ref(nm: &str, dim: i32, ind: Vec<&str>) -> Rc<Node> {
     a_ref(nm, dimensions, ind)
 }
 
+/// Inserts `node` at the end of the body of the loop with index `iv`, searching from `head`.
+///
+/// An index-named reference (see `a_ref`) is inserted as a fresh node whose subscripts are
+/// derived from the loops enclosing the insertion point, and `node` is updated to it. The
+/// reference passed in is left untouched, so it can be inserted into several loop nests.
 pub fn insert_at(node: &mut Rc<Node>, head: &mut Rc<Node>, iv: &str) -> bool {
-    let stmt = unsafe { Rc::get_mut_unchecked(head) };
-    match &mut stmt.stmt {
+    match &head.stmt {
         ast::Stmt::Loop(loop_stmt) => {
             if loop_stmt.iv == iv {
                 insert_node(head, node);
                 true
             } else {
-                for child in &mut loop_stmt.body {
-                    if insert_at(node, child, iv) {
-                        return true;
-                    }
-                }
-                false
+                let children = loop_stmt.body.borrow().clone();
+                children
+                    .into_iter()
+                    .any(|mut child| insert_at(node, &mut child, iv))
             }
         }
-        ast::Stmt::Block(children) => {
-            for child in children.iter_mut() {
-                if insert_at(node, child, iv) {
-                    return true;
-                }
-            }
-            false
-        }
+        ast::Stmt::Block(children) => children
+            .iter()
+            .any(|child| insert_at(node, &mut Rc::clone(child), iv)),
         _ => {
             panic!("Don't support branching yet!");
         }
@@ -185,7 +187,7 @@ pub fn insert_at(node: &mut Rc<Node>, head: &mut Rc<Node>, iv: &str) -> bool {
 }
 
 pub fn insert_at_innermost(node: &mut Rc<Node>, loops: &mut Rc<Node>) -> String {
-    let target = assign_ranks(loops, 0);
+    let target = innermost_loop(loops);
     let b = insert_at(node, loops, &target.loop_only(|lp| lp.iv.clone()).unwrap());
     // head.print_structure(0);
     if b {
@@ -195,83 +197,53 @@ pub fn insert_at_innermost(node: &mut Rc<Node>, loops: &mut Rc<Node>) -> String
     }
 }
 
-#[allow(dead_code)]
-pub fn assign_ranks_reverse(node: &mut Rc<Node>) -> i32 {
-    let node = unsafe { Rc::get_mut_unchecked(node) };
-    match &mut node.stmt {
-        Stmt::Loop(loop_stmt) => {
-            // Recursively assign ranks to the loops in the body
-            let max_inner_rank = loop_stmt
-                .body
-                .iter_mut()
-                .map(assign_ranks_reverse)
-                .max()
-                .unwrap_or(0);
-
-            // Assign the current rank to this loop
-            loop_stmt.rank = max_inner_rank;
-
-            max_inner_rank + 1
-        }
-        Stmt::Block(block_stmt) => {
-            // Recursively assign ranks to the loops in the body
-            let max_inner_rank = block_stmt
-                .iter_mut()
-                .map(assign_ranks_reverse)
-                .max()
-                .unwrap_or(0);
-
-            max_inner_rank
-        }
-        _ => {
-            // println!("Not loop {:?}", node.stmt);
-            0
-        }
-    }
+/// Returns the loop with the highest rank (nesting depth) in the AST; later loops win ties.
+/// The ranks themselves are kept by `Program::rank`.
+pub fn innermost_loop(node: &Rc<Node>) -> Rc<Node> {
+    deepest_loop(node, 0).1
 }
 
-/// Assign ranks to the loops in the AST. and return the node with the highest rank.
-pub fn assign_ranks(node: &mut Rc<Node>, current_rank: i32) -> Rc<Node> {
-    let cur = Rc::clone(node);
-    let node_ptr = unsafe { Rc::get_mut_unchecked(node) };
-    match &mut node_ptr.stmt {
+/// The deepest loop under `node` and its rank, or `node` itself with rank -1 if it is not a
+/// loop and contains none.
+fn deepest_loop(node: &Rc<Node>, current_rank: i32) -> (i32, Rc<Node>) {
+    match &node.stmt {
         Stmt::Loop(loop_stmt) => {
-            // Assign the current rank to this loop
-            loop_stmt.rank = current_rank;
-
-            // Recursively assign ranks to the loops in the body and find the node with the highest rank
-            // let mut max_rank_node = Rc::clone(&noe);
-            let max_inner_rank = loop_stmt
+            let max_inner = loop_stmt
                 .body
-                .iter_mut()
-                .map(|child| assign_ranks(child, current_rank + 1))
-                .max_by_key(|n| n.rank().unwrap_or(-1))
-                .unwrap_or(Rc::clone(&cur));
-
-            // Compare the rank of the current node with the highest rank node found in its body
-            if max_inner_rank.rank().unwrap_or(-1) > loop_stmt.rank {
-                max_inner_rank
-            } else {
-                Rc::clone(node)
+                .borrow()
+                .iter()
+                .map(|child| deepest_loop(child, current_rank + 1))
+                .max_by_key(|(rank, _)| *rank);
+            match max_inner {
+                // Compare the rank of the current node with the highest rank node found in its body
+                Some((rank, inner)) if rank > current_rank => (rank, inner),
+                _ => (current_rank, Rc::clone(node)),
             }
         }
-        Stmt::Block(block_stmt) => {
-            let max_inner_rank = block_stmt
-                .iter_mut()
-                .map(|child| assign_ranks(child, current_rank))
-                .max_by_key(|n| n.rank())
-                .unwrap_or(Rc::clone(&cur));
-
-            max_inner_rank
-        }
-        _ => Rc::clone(node),
+        Stmt::Block(block_stmt) => block_stmt
+            .iter()
+            .map(|child| deepest_loop(child, current_rank))
+            .max_by_key(|(rank, _)| *rank)
+            .unwrap_or((-1, Rc::clone(node))),
+        _ => (-1, Rc::clone(node)),
     }
 }
 
 pub fn insert_node(a_loop: &mut Rc<Node>, node: &mut Rc<Node>) {
-    Node::extend_loop_body(a_loop, node);
-    let node = unsafe { Rc::get_mut_unchecked(node) };
-    if let ast::Stmt::Ref(ref_stmt) = &mut node.stmt {
-        ref_stmt.sub = generate_sub(&ref_stmt.indices, &get_loops_indices(Rc::clone(a_loop)));
+    let fresh = match &node.stmt {
+        // References built from a closure have no index names to re-derive the subscripts from.
+        ast::Stmt::Ref(ref_stmt) if !ref_stmt.indices.is_empty() => {
+            let subs = affine_sub(&ref_stmt.indices, &get_loops_indices(Rc::clone(a_loop)));
+            Some(Node::new_node(ast::Stmt::Ref(ast::AryRef {
+                sub: compile_sub(&subs),
+                sub_affine: Some(subs),
+                ..ref_stmt.clone()
+            })))
+        }
+        _ => None,
+    };
+    if let Some(fresh) = fresh {
+        *node = fresh;
     }
+    Node::extend_loop_body(a_loop, node);
 }
@@ -0,0 +1,575 @@
+//! Data dependences between array references.
+//!
+//! Two references depend on each other if they may touch the same element. For references
+//! with affine subscripts, `dependences` finds the direction vectors over the loops enclosing
+//! both under which they can, testing each subscript with the GCD test and Banerjee's
+//! inequalities over the loop ranges, and computes exact distances for subscripts that use a
+//! single loop index in the same way on both sides. References without symbolic subscripts are
+//! assumed to depend on each other in every direction.
+
+use std::fmt;
+
+use crate::affine::AffineExpr;
+use crate::ast::{Cmp, LoopBound};
+use crate::program::{NodeId, Program};
+
+/// What the two accesses of a dependence do, source first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepKind {
+    /// Write, then read (true dependence).
+    Flow,
+    /// Read, then write.
+    Anti,
+    /// Write, then write.
+    Output,
+    /// Read, then read: no ordering constraint, but a reuse.
+    Input,
+}
+
+impl DepKind {
+    pub fn new(src_writes: bool, snk_writes: bool) -> DepKind {
+        match (src_writes, snk_writes) {
+            (true, false) => DepKind::Flow,
+            (false, true) => DepKind::Anti,
+            (true, true) => DepKind::Output,
+            (false, false) => DepKind::Input,
+        }
+    }
+}
+
+/// How the iteration of the sink compares with that of the source at one loop level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The sink is at a later iteration.
+    Lt,
+    Eq,
+    /// The sink is at an earlier iteration (possible below a level that carries the dependence).
+    Gt,
+    /// Unknown.
+    Star,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Lt => "<",
+            Direction::Eq => "=",
+            Direction::Gt => ">",
+            Direction::Star => "*",
+        }
+    }
+
+    fn reversed(self) -> Direction {
+        match self {
+            Direction::Lt => Direction::Gt,
+            Direction::Gt => Direction::Lt,
+            d => d,
+        }
+    }
+}
+
+/// The source reference may access an element that the sink accesses later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependence {
+    pub src: NodeId,
+    pub snk: NodeId,
+    pub kind: DepKind,
+    /// One entry per loop enclosing both references, outermost first.
+    pub direction: Vec<Direction>,
+    /// The iteration of the sink minus that of the source at each level, when it is a known
+    /// constant.
+    pub distance: Vec<Option<i32>>,
+}
+
+impl Dependence {
+    /// The loop level, counted from 0 for the outermost common loop, that carries the
+    /// dependence, or `None` if it is between two accesses in the same iteration.
+    pub fn level(&self) -> Option<usize> {
+        self.direction.iter().position(|&d| d != Direction::Eq)
+    }
+}
+
+impl fmt::Display for Dependence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dirs: Vec<&str> = self.direction.iter().map(|d| d.as_str()).collect();
+        let dists: Vec<String> = self
+            .distance
+            .iter()
+            .map(|d| d.map_or("*".to_string(), |d| d.to_string()))
+            .collect();
+        write!(
+            f,
+            "{:?} {} -> {} ({}) ({})",
+            self.kind,
+            self.src.index(),
+            self.snk.index(),
+            dirs.join(", "),
+            dists.join(", ")
+        )
+    }
+}
+
+/// The dependences between every pair of references to the same array, including a
+/// reference and itself, one for each direction vector under which they may touch the same
+/// element. `is_write` tells which reference sites store, usually from their `AccessKind`.
+///
+/// # Examples
+/// ```rust
+/// use dace::deps::{dependences, DepKind, Direction};
+/// use dace::program::Program;
+/// let code = dace::parse::parse(
+///     "double A[10][10]; for (i = 1; i < 10; i++) for (j = 0; j < 9; j++) A[i][j] = A[i - 1][j + 1];",
+///     &[],
+/// )
+/// .unwrap();
+/// let program = Program::new(&code);
+/// let deps = dependences(&program, |id| program.ary_ref(id).unwrap().kind.writes());
+/// assert_eq!(deps.len(), 1);
+/// assert_eq!(deps[0].kind, DepKind::Flow);
+/// assert_eq!(deps[0].direction, [Direction::Lt, Direction::Gt]);
+/// assert_eq!(deps[0].distance, [Some(1), Some(-1)]);
+/// assert_eq!(deps[0].level(), Some(0));
+/// ```
+pub fn dependences(program: &Program, is_write: impl Fn(NodeId) -> bool) -> Vec<Dependence> {
+    let refs: Vec<NodeId> = program.refs().collect();
+    let mut deps = vec![];
+    for (n, &r1) in refs.iter().enumerate() {
+        for &r2 in &refs[n..] {
+            if program.ary_ref(r1).unwrap().name != program.ary_ref(r2).unwrap().name {
+                continue;
+            }
+            for (direction, distance) in pair_directions(program, r1, r2) {
+                // orient each vector from the earlier access to the later one; in the same
+                // iteration the earlier is the first in textual order
+                let leading = direction.iter().copied().find(|&d| d != Direction::Eq);
+                let (src, snk, direction, distance) = match leading {
+                    // a reference against itself shows each vector twice, once reversed
+                    Some(Direction::Gt) | None if r1 == r2 => continue,
+                    Some(Direction::Gt) => (
+                        r2,
+                        r1,
+                        direction.iter().map(|d| d.reversed()).collect(),
+                        distance.iter().map(|d| d.map(|d| -d)).collect(),
+                    ),
+                    _ => (r1, r2, direction, distance),
+                };
+                deps.push(Dependence {
+                    src,
+                    snk,
+                    kind: DepKind::new(is_write(src), is_write(snk)),
+                    direction,
+                    distance,
+                });
+            }
+        }
+    }
+    deps
+}
+
+/// The direction vectors, with distances, under which `r1` (at iteration `I`) and `r2` (at
+/// `I'`) may touch the same element; a direction compares `I'` with `I`.
+fn pair_directions(
+    program: &Program,
+    r1: NodeId,
+    r2: NodeId,
+) -> Vec<(Vec<Direction>, Vec<Option<i32>>)> {
+    let loops1 = program.enclosing_loops(r1);
+    let loops2 = program.enclosing_loops(r2);
+    let common = loops1
+        .iter()
+        .zip(&loops2)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (Some(subs1), Some(subs2)) = (
+        &program.ary_ref(r1).unwrap().sub_affine,
+        &program.ary_ref(r2).unwrap().sub_affine,
+    ) else {
+        return vec![(vec![Direction::Star; common], vec![None; common])];
+    };
+    if subs1.len() != subs2.len() {
+        return vec![(vec![Direction::Star; common], vec![None; common])];
+    }
+    let Some(exact) = exact_distances(subs1, subs2, common, loops1.len(), loops2.len()) else {
+        return vec![];
+    };
+    let ranges1 = loop_ranges(program, &loops1);
+    let ranges2 = loop_ranges(program, &loops2);
+    let test = Test {
+        subs1,
+        subs2,
+        ranges1: &ranges1,
+        ranges2: &ranges2,
+        common,
+    };
+
+    // refine the direction vector level by level, dropping the infeasible prefixes
+    let mut found = vec![];
+    let mut stack = vec![vec![]];
+    while let Some(prefix) = stack.pop() {
+        if prefix.len() == common {
+            let distance = exact
+                .iter()
+                .zip(&prefix)
+                .map(|(&d, &dir)| d.or((dir == Direction::Eq).then_some(0)))
+                .collect();
+            found.push((prefix, distance));
+            continue;
+        }
+        let level = prefix.len();
+        for dir in [Direction::Gt, Direction::Eq, Direction::Lt] {
+            let agrees = match exact[level] {
+                Some(d) => {
+                    dir == [Direction::Gt, Direction::Eq, Direction::Lt][(d.signum() + 1) as usize]
+                }
+                None => true,
+            };
+            let mut vector = prefix.clone();
+            vector.push(dir);
+            if agrees && test.feasible(&vector) {
+                stack.push(vector);
+            }
+        }
+    }
+    found.reverse();
+    found
+}
+
+/// The distance `I'[k] - I[k]` at each common level `k` that a subscript fixes: one that uses
+/// only the index of that loop, with the same coefficient on both sides. `None` overall if
+/// the subscripts can never be equal.
+fn exact_distances(
+    subs1: &[AffineExpr],
+    subs2: &[AffineExpr],
+    common: usize,
+    depth1: usize,
+    depth2: usize,
+) -> Option<Vec<Option<i32>>> {
+    let mut distance = vec![None; common];
+    for (e, f) in subs1.iter().zip(subs2) {
+        let used1: Vec<usize> = (0..depth1).filter(|&k| e.coeff(k) != 0).collect();
+        let used2: Vec<usize> = (0..depth2).filter(|&k| f.coeff(k) != 0).collect();
+        if used1 != used2
+            || used1
+                .iter()
+                .any(|&k| k >= common || e.coeff(k) != f.coeff(k))
+        {
+            continue;
+        }
+        // e.coeff(k) * (I'[k] - I[k]) = e.constant - f.constant
+        let delta = e.constant - f.constant;
+        match used1[..] {
+            [] if delta != 0 => return None,
+            [k] => {
+                let c = e.coeff(k);
+                if delta % c != 0 {
+                    return None;
+                }
+                match distance[k] {
+                    Some(d) if d != delta / c => return None,
+                    _ => distance[k] = Some(delta / c),
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(distance)
+}
+
+/// The inequalities of one pair of references.
+struct Test<'a> {
+    subs1: &'a [AffineExpr],
+    subs2: &'a [AffineExpr],
+    ranges1: &'a [Option<(i64, i64)>],
+    ranges2: &'a [Option<(i64, i64)>],
+    common: usize,
+}
+
+impl Test<'_> {
+    /// Whether every subscript may be equal under the directions in `vector` (for the outer
+    /// common loops, the rest being unknown).
+    fn feasible(&self, vector: &[Direction]) -> bool {
+        self.subs1
+            .iter()
+            .zip(self.subs2)
+            .all(|(e, f)| self.gcd_test(e, f, vector) && self.banerjee_test(e, f, vector))
+    }
+
+    /// Whether the GCD of the coefficients of `e(I) - f(I')` divides `f.constant - e.constant`.
+    fn gcd_test(&self, e: &AffineExpr, f: &AffineExpr, vector: &[Direction]) -> bool {
+        let mut coeffs = vec![];
+        for k in 0..self.ranges1.len().max(self.ranges2.len()) {
+            match vector.get(k) {
+                Some(Direction::Eq) => coeffs.push(e.coeff(k) - f.coeff(k)),
+                _ => coeffs.extend([e.coeff(k), f.coeff(k)]),
+            }
+        }
+        let g = coeffs.iter().fold(0, |g, &c| gcd(g, c.abs()));
+        let delta = f.constant - e.constant;
+        if g == 0 {
+            delta == 0
+        } else {
+            delta % g == 0
+        }
+    }
+
+    /// Whether `f.constant - e.constant` lies between the least and the greatest value of
+    /// `e(I) - f(I') - constants` over the loop ranges, under the directions in `vector`.
+    fn banerjee_test(&self, e: &AffineExpr, f: &AffineExpr, vector: &[Direction]) -> bool {
+        let (mut lo, mut hi) = (Some(0i64), Some(0i64));
+        let mut add = |range: Option<(i64, i64)>| match range {
+            Some((l, h)) => {
+                lo = lo.map(|lo| lo + l);
+                hi = hi.map(|hi| hi + h);
+            }
+            None => (lo, hi) = (None, None),
+        };
+        for k in 0..self.ranges1.len().max(self.ranges2.len()) {
+            let (a, b) = (e.coeff(k) as i64, f.coeff(k) as i64);
+            if k < self.common {
+                let dir = vector.get(k).copied().unwrap_or(Direction::Star);
+                match term_range(a, b, self.ranges1[k], dir) {
+                    Ok(range) => add(range),
+                    Err(Empty) => return false,
+                }
+            } else {
+                for (c, ranges) in [(a, self.ranges1), (-b, self.ranges2)] {
+                    if c != 0 {
+                        add(ranges
+                            .get(k)
+                            .copied()
+                            .flatten()
+                            .map(|(l, h)| scale(c, l, h)));
+                    }
+                }
+            }
+        }
+        let delta = (f.constant - e.constant) as i64;
+        lo.is_none_or(|lo| lo <= delta) && hi.is_none_or(|hi| delta <= hi)
+    }
+}
+
+/// The directions rule out every pair of iterations.
+struct Empty;
+
+/// The range of `a*x - b*y` for `x` and `y` in `range` and related by `dir` (`y` compared with
+/// `x`), or `None` if unbounded.
+fn term_range(
+    a: i64,
+    b: i64,
+    range: Option<(i64, i64)>,
+    dir: Direction,
+) -> Result<Option<(i64, i64)>, Empty> {
+    let Some((l, u)) = range else {
+        return Ok((a == 0 && b == 0).then_some((0, 0)));
+    };
+    // the corners of the region, a linear function is extreme at one of them
+    let corners = match dir {
+        _ if l > u => return Err(Empty),
+        Direction::Eq => vec![(l, l), (u, u)],
+        Direction::Lt | Direction::Gt if l == u => return Err(Empty),
+        Direction::Lt => vec![(l, l + 1), (l, u), (u - 1, u)],
+        Direction::Gt => vec![(l + 1, l), (u, l), (u, u - 1)],
+        Direction::Star => vec![(l, l), (l, u), (u, l), (u, u)],
+    };
+    let values = corners.iter().map(|&(x, y)| a * x - b * y);
+    Ok(Some((values.clone().min().unwrap(), values.max().unwrap())))
+}
+
+fn scale(c: i64, l: i64, h: i64) -> (i64, i64) {
+    let (x, y) = (c * l, c * h);
+    (x.min(y), x.max(y))
+}
+
+fn floor_div(v: i64, k: i64) -> i64 {
+    let q = v / k;
+    if v % k != 0 && (v < 0) != (k < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The least and greatest value each of `loops` (a nest, outermost first) takes, or `None` when
+/// its bounds or step are not known symbolically.
+fn loop_ranges(program: &Program, loops: &[NodeId]) -> Vec<Option<(i64, i64)>> {
+    let mut ranges: Vec<Option<(i64, i64)>> = vec![];
+    for &id in loops {
+        let lp = program.loop_stmt(id).unwrap();
+        let range = interval(&lp.lb, &ranges)
+            .zip(interval(&lp.ub, &ranges))
+            .and_then(
+                |((lb_lo, lb_hi), (ub_lo, ub_hi))| match (lp.cmp?, lp.stride?) {
+                    (Cmp::Lt, s) if s > 0 => Some((lb_lo, ub_hi - 1)),
+                    (Cmp::Le, s) if s > 0 => Some((lb_lo, ub_hi)),
+                    (Cmp::Gt, s) if s < 0 => Some((ub_lo + 1, lb_hi)),
+                    (Cmp::Ge, s) if s < 0 => Some((ub_lo, lb_hi)),
+                    _ => None,
+                },
+            );
+        ranges.push(range);
+    }
+    ranges
+}
+
+/// The least and greatest value of a bound when the enclosing indices are in `ranges`.
+fn interval(bound: &LoopBound, ranges: &[Option<(i64, i64)>]) -> Option<(i64, i64)> {
+    match bound {
+        LoopBound::Fixed(x) => Some((*x as i64, *x as i64)),
+        LoopBound::Affine { a, b } => a.iter().enumerate().filter(|(_, &c)| c != 0).try_fold(
+            (*b as i64, *b as i64),
+            |(lo, hi), (k, &c)| {
+                let (l, h) = (*ranges.get(k)?)?;
+                let (l, h) = scale(c as i64, l, h);
+                Some((lo + l, hi + h))
+            },
+        ),
+        LoopBound::Dynamic(_) => None,
+        LoopBound::Min(x, y) => {
+            let ((xl, xh), (yl, yh)) = (interval(x, ranges)?, interval(y, ranges)?);
+            Some((xl.min(yl), xh.min(yh)))
+        }
+        LoopBound::Max(x, y) => {
+            let ((xl, xh), (yl, yh)) = (interval(x, ranges)?, interval(y, ranges)?);
+            Some((xl.max(yl), xh.max(yh)))
+        }
+        LoopBound::FloorDiv(x, k) | LoopBound::CeilDiv(x, k) => {
+            let (l, h) = interval(x, ranges)?;
+            let k = *k as i64;
+            let div = |v: i64| match bound {
+                LoopBound::FloorDiv(..) => floor_div(v, k),
+                _ => -floor_div(-v, k),
+            };
+            Some((div(l).min(div(h)), div(l).max(div(h))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Node;
+    use crate::parse::parse;
+
+    /// The dependences of a parsed kernel whose references with ids in `writes` store, as
+    /// (kind, src ref id, snk ref id, direction).
+    fn deps_of(src: &str, writes: &[usize]) -> Vec<(DepKind, usize, usize, String)> {
+        let program = Program::new(&parse(src, &[("N", 10)]).unwrap());
+        let is_write = |id: NodeId| writes.contains(&program.ref_id(id).unwrap());
+        dependences(&program, is_write)
+            .iter()
+            .map(|d| {
+                let dirs: String = d.direction.iter().map(|d| d.as_str()).collect();
+                let (src, snk) = (
+                    program.ref_id(d.src).unwrap(),
+                    program.ref_id(d.snk).unwrap(),
+                );
+                (d.kind, src, snk, dirs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gemm() {
+        // refs: 0 A, 1 B, 2 C (read), 3 C (written)
+        let deps = deps_of(
+            "param N; double A[N][N]; double B[N][N]; double C[N][N];
+             for (i = 0; i < N; i++) for (j = 0; j < N; j++) for (k = 0; k < N; k++)
+               C[i][j] += A[i][k] * B[k][j];",
+            &[3],
+        );
+        use DepKind::*;
+        let expected = [
+            (Input, 0, 0, "=<="),
+            (Input, 1, 1, "<=="),
+            (Input, 2, 2, "==<"),
+            (Flow, 3, 2, "==<"),
+            (Anti, 2, 3, "==<"),
+            (Anti, 2, 3, "==="),
+            (Output, 3, 3, "==<"),
+        ];
+        assert_eq!(deps.len(), expected.len(), "{:?}", deps);
+        for (kind, src, snk, dirs) in expected {
+            assert!(
+                deps.contains(&(kind, src, snk, dirs.to_string())),
+                "missing {:?} {} -> {} {}",
+                kind,
+                src,
+                snk,
+                dirs
+            );
+        }
+    }
+
+    #[test]
+    fn distances() {
+        let program = Program::new(
+            &parse(
+                "double A[20][20]; for (i = 2; i < 20; i++) for (j = 0; j < 18; j++)
+                 A[i][j] = A[i - 2][j + 1] + A[i][j + 1];",
+                &[],
+            )
+            .unwrap(),
+        );
+        let refs: Vec<NodeId> = program.refs().collect();
+        let deps = dependences(&program, |id| id == refs[2]);
+        let dist = |src: usize, snk: usize| {
+            deps.iter()
+                .filter(|d| d.src == refs[src] && d.snk == refs[snk])
+                .map(|d| d.distance.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(dist(2, 0), [vec![Some(2), Some(-1)]]);
+        // A[i][j + 1] is read one iteration of j before it is written
+        assert_eq!(dist(1, 2), [vec![Some(0), Some(1)]]);
+        assert!(dist(0, 2).is_empty());
+        assert!(deps
+            .iter()
+            .all(|d| d.level() == Some(0) || d.level() == Some(1)));
+    }
+
+    #[test]
+    fn independent() {
+        // GCD test: even and odd elements
+        assert!(deps_of(
+            "param N; double A[2 * N]; for (i = 0; i < N; i++) A[2 * i] = A[2 * i + 1];",
+            &[1]
+        )
+        .is_empty());
+        // Banerjee: the two halves of the array
+        assert!(deps_of(
+            "param N; double A[2 * N]; for (i = 0; i < N; i++) A[i] = A[i + N];",
+            &[1]
+        )
+        .is_empty());
+        // a triangle and its transpose meet on the diagonal
+        let deps = deps_of(
+            "param N; double A[N][N]; for (i = 0; i < N; i++) for (j = 0; j <= i; j++)
+             A[i][j] = A[j][i];",
+            &[1],
+        );
+        assert!(deps.contains(&(DepKind::Anti, 0, 1, "==".to_string())));
+    }
+
+    #[test]
+    fn closure_subscripts() {
+        // i { A[i] (closure); A[i] } depend on each other in every way
+        let mut aref = Node::new_ref("A", vec![10], |iv| vec![iv[0] as usize]);
+        let mut bref = Node::new_affine_ref("A", vec![10], vec![AffineExpr::var(0)]);
+        let mut aloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        Node::extend_loop_body(&mut aloop, &mut bref);
+        let program = Program::new(&aloop);
+        let deps = dependences(&program, |_| true);
+        assert_eq!(deps.len(), 2);
+        assert!(deps.iter().all(|d| d.direction == [Direction::Star]));
+        assert!(deps.iter().all(|d| d.kind == DepKind::Output));
+    }
+}
@@ -0,0 +1,463 @@
+//! Writes a loop tree back out as source text: a kernel in the loop language of
+//! `parse`, or a complete C program to compile and run under an external cache simulator.
+//!
+//! Only the memory accesses of a statement are kept in the tree, so a statement is
+//! written as the sum of the elements it reads, stored to the element it writes. The
+//! references come out in the same order, and so have the same ids in `Program`:
+//! - consecutive reads followed by a write are one assignment, `A[i] = B[i] + C[i];`;
+//! - if the last of the reads is the written element, the assignment is compound,
+//!   `C[i][j] += A[i][k] + B[k][j];`;
+//! - a `AccessKind::ReadWrite` is written as a compound assignment, and so parses back as a
+//!   `Read` followed by a `Write`;
+//! - reads that no write follows stand alone, `A[i];`.
+//!
+//! Parameters are folded into the tree when a kernel is parsed, so the emitted text has
+//! only numbers. For a kernel that uses none of the cases above, `to_dsl` is the inverse of
+//! `parse::parse` up to this normal form: parsing its output and emitting again gives the
+//! same text, and the same references, bounds and branches as the original.
+//!
+//! The emitter needs the symbolic forms of subscripts, bounds, loop tests and steps, and
+//! branch conditions. Closures have no text, and neither do layouts other than
+//! `Layout::RowMajor` or element types that are not a plain C scalar.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::arybase::DEFAULT_ELEM_SIZE;
+use crate::ast::{AccessKind, AryRef, CType, Node, Stmt};
+use crate::iter::Walk;
+use crate::layout::Layout;
+use crate::validate::{validate, Severity};
+
+/// Helpers for the bounds of tiled loops, with the rounding of `LoopBound::floor_div`.
+const C_PRELUDE: &str = "\
+static inline int min(int a, int b) { return a < b ? a : b; }
+static inline int max(int a, int b) { return a > b ? a : b; }
+static inline int floord(int a, int b) { return a >= 0 ? a / b : -((-a + b - 1) / b); }
+static inline int ceild(int a, int b) { return a > 0 ? (a + b - 1) / b : -(-a / b); }
+";
+
+/// A part of the tree that has no textual form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmitError {
+    pub message: String,
+}
+
+impl EmitError {
+    fn new(message: impl Into<String>) -> Self {
+        EmitError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for EmitError {}
+
+type Result<T> = std::result::Result<T, EmitError>;
+
+/// The kernel in the loop language of `parse`: the array declarations, in order of first
+/// reference, followed by the loop nest.
+///
+/// # Examples
+/// ```rust
+/// let src = "double A[8]; for (i = 1; i < 8; i++) A[i] += A[i - 1];";
+/// let text = dace::emit::to_dsl(&dace::parse::parse(src, &[]).unwrap()).unwrap();
+/// assert_eq!(
+///     text,
+///     "double A[8];\n\nfor (int i = 1; i < 8; i++)\n  A[i] += A[i-1];\n"
+/// );
+/// ```
+pub fn to_dsl(code: &Node) -> Result<String> {
+    let decls = declarations(code)?;
+    let mut out: String = decls.iter().map(|d| format!("{};\n", d)).collect();
+    out.push('\n');
+    out.push_str(&statements(code, 0)?);
+    Ok(out)
+}
+
+/// A C program that runs the kernel once: the arrays are globals and the loop nest is the
+/// body of `kernel()`, called from `main`.
+///
+/// The arrays are `volatile`, so that the compiler keeps every access of the tree and adds
+/// none. C leaves the order of the reads within one statement unspecified, so only the
+/// order of the statements is fixed.
+pub fn to_c(code: &Node) -> Result<String> {
+    let decls = declarations(code)?;
+    let mut out = String::from("// Generated from a DACE loop tree.\n\n");
+    out.push_str(C_PRELUDE);
+    out.push('\n');
+    for decl in decls {
+        out.push_str(&format!("volatile {};\n", decl));
+    }
+    out.push_str("\nvoid kernel(void) {\n");
+    out.push_str(&statements(code, 1)?);
+    out.push_str("}\n\nint main(void) {\n  kernel();\n  return 0;\n}\n");
+    Ok(out)
+}
+
+/// One declaration per array, e.g. `double A[4][4]`, after checking that the program is
+/// valid and its arrays can be written in C.
+fn declarations(code: &Node) -> Result<Vec<String>> {
+    if let Some(diag) = validate(code)
+        .into_iter()
+        .find(|d| d.severity == Severity::Error)
+    {
+        return Err(EmitError::new(diag.to_string()));
+    }
+    let root = code.deep_clone();
+    let mut seen = HashSet::new();
+    let mut decls = vec![];
+    for node in Walk::new(&root) {
+        let Stmt::Ref(aref) = &node.stmt else {
+            continue;
+        };
+        if !seen.insert(aref.name.clone()) {
+            continue;
+        }
+        if aref.layout != Layout::RowMajor {
+            return Err(EmitError::new(format!(
+                "`{}` has layout {:?}, but C arrays are row-major",
+                aref.name, aref.layout
+            )));
+        }
+        let dims: String = aref.dim.iter().map(|d| format!("[{}]", d)).collect();
+        decls.push(format!("{} {}{}", type_name(aref)?, aref.name, dims));
+    }
+    Ok(decls)
+}
+
+/// The C type of the elements of `aref`: the type it was declared with, or else the first of
+/// `CType::ALL` with their size.
+fn type_name(aref: &AryRef) -> Result<&'static str> {
+    let size = aref.elem.map_or(DEFAULT_ELEM_SIZE, |e| e.size);
+    let plain = aref.elem.is_none_or(|e| e.align == e.size && e.pad == 0);
+    let declared = aref.elem.and_then(|e| e.c_type);
+    declared
+        .or_else(|| CType::ALL.into_iter().find(|ty| ty.size() == size))
+        .filter(|_| plain)
+        .map(CType::name)
+        .ok_or_else(|| {
+            EmitError::new(format!(
+                "the elements of `{}`, {:?}, are not a C scalar type",
+                aref.name, aref.elem
+            ))
+        })
+}
+
+/// The statements of the tree, indented by `level` steps.
+fn statements(code: &Node, level: usize) -> Result<String> {
+    let mut emitter = Emitter::new(vec![], level);
+    emitter.node(code)?;
+    emitter.flush();
+    Ok(emitter.lines.concat())
+}
+
+/// Writes the statements of one body, see the module documentation for how references are
+/// grouped into statements.
+struct Emitter {
+    /// Indices of the enclosing loops, outermost first.
+    ivs: Vec<String>,
+    level: usize,
+    lines: Vec<String>,
+    /// The number of statements written, not counting those nested in them.
+    count: usize,
+    /// Elements read since the last statement.
+    reads: Vec<String>,
+}
+
+impl Emitter {
+    fn new(ivs: Vec<String>, level: usize) -> Self {
+        Emitter {
+            ivs,
+            level,
+            lines: vec![],
+            count: 0,
+            reads: vec![],
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        self.lines
+            .push(format!("{}{}\n", "  ".repeat(self.level), text));
+    }
+
+    fn statement(&mut self, text: &str) {
+        self.line(text);
+        self.count += 1;
+    }
+
+    /// Writes the reads no write followed.
+    fn flush(&mut self) {
+        for read in std::mem::take(&mut self.reads) {
+            self.statement(&format!("{};", read));
+        }
+    }
+
+    fn node(&mut self, node: &Node) -> Result<()> {
+        match &node.stmt {
+            Stmt::Ref(aref) => {
+                let element = self.element(aref)?;
+                if aref.kind == AccessKind::Read {
+                    self.reads.push(element);
+                    return Ok(());
+                }
+                let compound =
+                    aref.kind == AccessKind::ReadWrite || self.reads.last() == Some(&element);
+                if compound && aref.kind == AccessKind::Write {
+                    self.reads.pop();
+                }
+                let rhs = match self.reads.is_empty() {
+                    true => "0".to_string(),
+                    false => std::mem::take(&mut self.reads).join(" + "),
+                };
+                let op = if compound { "+=" } else { "=" };
+                self.statement(&format!("{} {} {};", element, op, rhs));
+            }
+            Stmt::Block(children) => {
+                for child in children {
+                    self.node(child)?;
+                }
+            }
+            Stmt::Loop(aloop) => {
+                self.flush();
+                let (Some(cmp), Some(stride)) = (aloop.cmp, aloop.stride) else {
+                    return Err(self.error(format!(
+                        "loop `{}` has a test or step without a symbolic form",
+                        aloop.iv
+                    )));
+                };
+                if !aloop.lb.is_symbolic() || !aloop.ub.is_symbolic() {
+                    return Err(self.error(format!("loop `{}` has a dynamic bound", aloop.iv)));
+                }
+                let iv = &aloop.iv;
+                let step = match stride {
+                    1 => format!("{}++", iv),
+                    -1 => format!("{}--", iv),
+                    s if s > 0 => format!("{} += {}", iv, s),
+                    s => format!("{} -= {}", iv, -s),
+                };
+                let header = format!(
+                    "for (int {} = {}; {} {} {}; {})",
+                    iv,
+                    aloop.lb.to_string_with(&self.ivs),
+                    iv,
+                    cmp.as_str(),
+                    aloop.ub.to_string_with(&self.ivs),
+                    step
+                );
+                let mut ivs = self.ivs.clone();
+                ivs.push(iv.clone());
+                let mut body = Emitter::new(ivs, self.level + 1);
+                for child in aloop.body.borrow().iter() {
+                    body.node(child)?;
+                }
+                body.flush();
+                self.nested(&header, body);
+            }
+            Stmt::Branch(branch) => {
+                self.flush();
+                let Some(pred) = &branch.pred else {
+                    return Err(self.error("a branch condition has no symbolic form".into()));
+                };
+                let header = format!("if ({})", pred.to_string_with(&self.ivs));
+                let arm = |node: &Node| -> Result<Emitter> {
+                    let mut arm = Emitter::new(self.ivs.clone(), self.level + 1);
+                    arm.node(node)?;
+                    arm.flush();
+                    Ok(arm)
+                };
+                let then_arm = arm(&branch.then_body)?;
+                let else_arm = branch.else_body.as_deref().map(arm).transpose()?;
+                // the arms are always braced, so that an `else` can't attach to a nested `if`
+                self.statement(&format!("{} {{", header));
+                self.lines.extend(then_arm.lines);
+                if let Some(else_arm) = else_arm {
+                    self.line("} else {");
+                    self.lines.extend(else_arm.lines);
+                }
+                self.line("}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `header` followed by the statements of `body`, in braces unless it is a single
+    /// statement.
+    fn nested(&mut self, header: &str, body: Emitter) {
+        if body.count == 1 {
+            self.statement(header);
+            self.lines.extend(body.lines);
+        } else if body.count == 0 {
+            self.statement(&format!("{} {{}}", header));
+        } else {
+            self.statement(&format!("{} {{", header));
+            self.lines.extend(body.lines);
+            self.line("}");
+        }
+    }
+
+    /// The element `aref` accesses, e.g. `A[i][j-1]`.
+    fn element(&self, aref: &AryRef) -> Result<String> {
+        let subs = aref.sub_affine.as_ref().ok_or_else(|| {
+            self.error(format!(
+                "the subscripts of `{}` have no symbolic form",
+                aref.name
+            ))
+        })?;
+        let subs: String = subs
+            .iter()
+            .map(|e| format!("[{}]", e.to_string_with(&self.ivs)))
+            .collect();
+        Ok(format!("{}{}", aref.name, subs))
+    }
+
+    fn error(&self, message: String) -> EmitError {
+        match self.ivs.is_empty() {
+            true => EmitError::new(message),
+            false => EmitError::new(format!("in loop {}: {}", self.ivs.join(" > "), message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::affine::AffineExpr;
+    use crate::parse::parse;
+    use crate::program::Program;
+    use crate::transform::tile;
+
+    /// Emits `code`, parses the text back and checks that it emits the same text again.
+    fn round_trip(code: &Node) -> String {
+        let text = to_dsl(code).unwrap();
+        let again = parse(&text, &[]).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+        assert_eq!(to_dsl(&again).unwrap(), text);
+        assert_eq!(again.node_count(), code.node_count());
+        text
+    }
+
+    #[test]
+    fn statements() {
+        let code = parse(
+            "param N = 4; double A[N][N]; int x[N]; char c[2 * N];
+             for (i = 0; i < N; i++) {
+               A[i][i] *= 2;
+               x[i] = A[i][0] + A[0][i];
+               for (j = i + 1; j <= N - 1; j += 2)
+                 c[2 * j - 1];
+               x[N - 1 - i] = 0;
+             }",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            round_trip(&code),
+            "double A[4][4];
+int x[4];
+char c[8];
+
+for (int i = 0; i < 4; i++) {
+  A[i][i] += 0;
+  x[i] = A[i][0] + A[0][i];
+  for (int j = i+1; j <= 3; j += 2)
+    c[2*j-1];
+  x[-i+3] = 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn branches() {
+        let code = parse(
+            "double A[8][8];
+             for (i = 0; i < 8; i++)
+               for (j = 7; j >= 0; j--)
+                 if (i < j && !(j == 4 || i + j > 10)) A[i][j] = A[j][i];
+                 else if (i == j) A[i][j] += 1;",
+            &[],
+        )
+        .unwrap();
+        let text = round_trip(&code);
+        assert!(text.contains("if (i-j < 0 && !(j-4 == 0 || i+j-10 > 0)) {"));
+        assert!(text.contains("} else {\n      if (i-j == 0) {"));
+    }
+
+    #[test]
+    fn tiled() {
+        let code = parse(
+            "double A[10][10]; for (i = 0; i < 10; i++) for (j = 0; j <= i; j++) A[i][j] = 0;",
+            &[],
+        )
+        .unwrap();
+        let tiled = tile(&Program::new(&code), &[("i", 4)]).unwrap();
+        let text = round_trip(tiled.root_node());
+        assert!(
+            text.contains("for (int i = ii; i < min(ii+4, 10); i++)"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn read_write() {
+        let mut aloop = Node::new_single_loop("i", 0, 4);
+        let mut aref = Node::new_affine_ref("A", vec![4], vec![AffineExpr::var(0)])
+            .with_kind(AccessKind::ReadWrite);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        let text = to_dsl(&aloop).unwrap();
+        assert!(text.ends_with("for (int i = 0; i < 4; i++)\n  A[i] += 0;\n"));
+        // an update comes back as a read and a write
+        assert_eq!(parse(&text, &[]).unwrap().node_count(), 3);
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut aloop = Node::new_single_loop("i", 0, 4);
+        let mut aref = Node::new_ref("A", vec![4], |i| vec![i[0] as usize]);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        assert_eq!(
+            to_dsl(&aloop).unwrap_err().to_string(),
+            "in loop i: the subscripts of `A` have no symbolic form"
+        );
+
+        let mut aloop = Node::new_single_loop_dyn_ub("i", 0, Box::new(|_| 4));
+        let mut aref = Node::new_affine_ref("A", vec![4], vec![AffineExpr::var(0)]);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        assert_eq!(
+            to_c(&aloop).unwrap_err().to_string(),
+            "loop `i` has a dynamic bound"
+        );
+
+        let mut aloop = Node::new_single_loop("i", 0, 4);
+        let mut aref = Node::new_affine_ref("A", vec![4], vec![AffineExpr::var(0)])
+            .with_layout(Layout::Morton);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        assert!(to_dsl(&aloop).unwrap_err().message.contains("row-major"));
+    }
+
+    #[test]
+    fn c_program() {
+        let code = parse(
+            "short S[5][5]; for (i = 0; i < 3; i++) for (j = 0; j < 5; j++) S[i][j] = S[j][i];",
+            &[],
+        )
+        .unwrap();
+        let c = to_c(&code).unwrap();
+        assert!(c.contains("volatile short S[5][5];\n"));
+        assert!(c.contains(
+            "void kernel(void) {
+  for (int i = 0; i < 3; i++)
+    for (int j = 0; j < 5; j++)
+      S[i][j] = S[j][i];
+}"
+        ));
+        assert!(c.ends_with("int main(void) {\n  kernel();\n  return 0;\n}\n"));
+    }
+}
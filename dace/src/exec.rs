@@ -0,0 +1,369 @@
+//! Runs a program: the accesses it makes, in program order.
+//!
+//! `AccessStream` is the one interpreter of loop trees that the tracers share. It evaluates
+//! every loop bound when the loop is entered, with the indices of the enclosing loops, so
+//! fixed, affine and dynamic bounds behave alike, steps the index with the loop's own step
+//! and test, and runs the arm of each branch its condition picks. Accesses are produced one
+//! at a time, so a trace is never held in memory unless the consumer collects it. Nothing is
+//! allocated per access: the loop indices of the last one are borrowed from the stream with
+//! `AccessStream::ivec`.
+//!
+//! # Examples
+//! ```rust
+//! use dace::exec::AccessStream;
+//! use dace::program::Program;
+//!
+//! let code = dace::parse::parse(
+//!     "double A[4]; for (i = 3; i >= 0; i -= 2) A[i] = 0;",
+//!     &[],
+//! )
+//! .unwrap();
+//! let program = Program::new(&code);
+//! let addrs: Vec<usize> = AccessStream::new(&program).map(|a| a.addr).collect();
+//! assert_eq!(addrs, [24, 8]);
+//! ```
+
+use crate::ast::{AryRef, Stmt};
+use crate::bounds::{checked_sub, BoundsError};
+use crate::program::{NodeId, Program};
+
+/// The byte address of the element `aref` accesses at `ivec`, given the base byte address
+/// and the element size of its array.
+pub fn access_addr(aref: &AryRef, base: usize, elem_size: usize, ivec: &[i32]) -> usize {
+    let index = (aref.sub)(ivec);
+    if index.len() != aref.dim.len() {
+        panic!("array index and dimension do not match");
+    }
+
+    base + aref.offset(&index) * elem_size
+}
+
+/// One access of a run. Its loop indices are `AccessStream::ivec` until the next one.
+#[derive(Debug, Clone, Copy)]
+pub struct Access<'a> {
+    /// See `Program::ref_id`.
+    pub ref_id: usize,
+    pub aref: &'a AryRef,
+    /// Byte address of the element.
+    pub addr: usize,
+}
+
+/// A loop or block being run, with the position of its next child.
+enum Frame<'a> {
+    /// `ub` is evaluated once, when the loop is entered. The index is the last of `ivec`.
+    Loop {
+        id: NodeId,
+        ub: i32,
+        next: usize,
+    },
+    Block {
+        children: &'a [NodeId],
+        next: usize,
+    },
+}
+
+/// The accesses of `program`, see the module documentation.
+pub struct AccessStream<'a> {
+    program: &'a Program,
+    stack: Vec<Frame<'a>>,
+    ivec: Vec<i32>,
+    started: bool,
+}
+
+impl<'a> AccessStream<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        AccessStream {
+            program,
+            stack: vec![],
+            ivec: vec![],
+            started: false,
+        }
+    }
+
+    /// The indices of the loops enclosing the last access, outermost first.
+    pub fn ivec(&self) -> &[i32] {
+        &self.ivec
+    }
+
+    /// Like the stream, but checks every subscript against its array's dimensions, yielding
+    /// an error naming `kernel` at the first one out of range, and nothing after it.
+    pub fn checked(self, kernel: &'a str) -> Checked<'a> {
+        Checked {
+            stream: self,
+            kernel,
+            failed: false,
+        }
+    }
+
+    /// Runs up to the next reference, returning it. `ivec` then holds its loop indices.
+    fn next_ref(&mut self) -> Option<NodeId> {
+        if !self.started {
+            self.started = true;
+            if let Some(id) = self.enter(self.program.root()) {
+                return Some(id);
+            }
+        }
+        loop {
+            let program = self.program;
+            let child = match self.stack.last_mut()? {
+                Frame::Loop { id, ub, next } => match program.children(*id).get(*next) {
+                    Some(&child) => {
+                        *next += 1;
+                        Some(child)
+                    }
+                    None => {
+                        let aloop = program.loop_stmt(*id).unwrap();
+                        let iv = self.ivec.last_mut().unwrap();
+                        *iv = (aloop.step)(*iv);
+                        if (aloop.test)(*iv, *ub) {
+                            *next = 0;
+                        } else {
+                            self.ivec.pop();
+                            self.stack.pop();
+                        }
+                        None
+                    }
+                },
+                Frame::Block { children, next } => match children.get(*next) {
+                    Some(&child) => {
+                        *next += 1;
+                        Some(child)
+                    }
+                    None => {
+                        self.stack.pop();
+                        None
+                    }
+                },
+            };
+            if let Some(id) = child.and_then(|child| self.enter(child)) {
+                return Some(id);
+            }
+        }
+    }
+
+    /// Starts running `id`. Returns it if it's a reference, or the reference the branch
+    /// arms it picks lead to.
+    fn enter(&mut self, id: NodeId) -> Option<NodeId> {
+        let program = self.program;
+        match program.stmt(id) {
+            Stmt::Ref(_) => Some(id),
+            Stmt::Loop(aloop) => {
+                let lb = aloop.lb.eval(&self.ivec);
+                let ub = aloop.ub.eval(&self.ivec);
+                if (aloop.test)(lb, ub) {
+                    self.ivec.push(lb);
+                    self.stack.push(Frame::Loop { id, ub, next: 0 });
+                }
+                None
+            }
+            Stmt::Block(_) => {
+                self.stack.push(Frame::Block {
+                    children: program.children(id),
+                    next: 0,
+                });
+                None
+            }
+            Stmt::Branch(branch) => {
+                // the then part is the first child, the else part (if any) the second
+                let taken = if (branch.cond)(&self.ivec) { 0 } else { 1 };
+                let &arm = program.children(id).get(taken)?;
+                self.enter(arm)
+            }
+        }
+    }
+
+    fn access(&self, id: NodeId) -> Access<'a> {
+        let program = self.program;
+        let aref = program.ary_ref(id).unwrap();
+        Access {
+            ref_id: program.ref_id(id).unwrap(),
+            aref,
+            addr: access_addr(
+                aref,
+                program.base(id).unwrap(),
+                program.elem_size(id).unwrap(),
+                &self.ivec,
+            ),
+        }
+    }
+}
+
+impl<'a> Iterator for AccessStream<'a> {
+    type Item = Access<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.next_ref()?;
+        Some(self.access(id))
+    }
+}
+
+/// See `AccessStream::checked`.
+pub struct Checked<'a> {
+    stream: AccessStream<'a>,
+    kernel: &'a str,
+    failed: bool,
+}
+
+impl<'a> Iterator for Checked<'a> {
+    type Item = Result<Access<'a>, Box<BoundsError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let stream = &mut self.stream;
+        let id = stream.next_ref()?;
+        let program = stream.program;
+        let ref_id = program.ref_id(id).unwrap();
+        match checked_sub(
+            self.kernel,
+            ref_id,
+            program.ary_ref(id).unwrap(),
+            &stream.ivec,
+        ) {
+            Ok(_) => Some(Ok(stream.access(id))),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// The options both tracers take, `static_rd::trace::TraceConfig` and
+/// `static_ri::TraceConfig`, which add their own. By default a trace keeps only the
+/// histogram; the splits can be combined in one run.
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    /// Bytes per block: a block is a byte address divided by this.
+    pub cache_line_size: usize,
+    pub by_ref: bool,
+    pub by_array: bool,
+    pub by_kind: bool,
+    /// The kernel name in checked mode, see `crate::bounds`.
+    pub check: Option<String>,
+}
+
+impl TraceOptions {
+    /// Only the histogram, with blocks of `cache_line_size` bytes.
+    pub fn new(cache_line_size: usize) -> Self {
+        TraceOptions {
+            cache_line_size,
+            by_ref: false,
+            by_array: false,
+            by_kind: false,
+            check: None,
+        }
+    }
+
+    /// Also the histogram of each reference, by `Program::ref_id`.
+    pub fn with_by_ref(mut self) -> Self {
+        self.by_ref = true;
+        self
+    }
+
+    /// Also the histogram of each array, by name, of the accesses of all its references.
+    pub fn with_by_array(mut self) -> Self {
+        self.by_array = true;
+        self
+    }
+
+    /// Also the histogram of each access kind.
+    pub fn with_by_kind(mut self) -> Self {
+        self.by_kind = true;
+        self
+    }
+
+    /// Checks every subscript against its array's dimensions and stops at the first one out
+    /// of range, naming `kernel` in the error.
+    pub fn with_check(mut self, kernel: &str) -> Self {
+        self.check = Some(kernel.to_string());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Node;
+    use crate::parse::parse;
+
+    #[test]
+    fn addresses() {
+        let aij_node = Node::new_ref("x", vec![10, 10], |ij| vec![ij[0] as usize, ij[1] as usize]);
+        let Stmt::Ref(aij) = &aij_node.stmt else {
+            unreachable!()
+        };
+        assert_eq!(access_addr(aij, 0, 1, &[0, 0]), 0);
+        assert_eq!(access_addr(aij, 0, 1, &[9, 9]), 99);
+        assert_eq!(access_addr(aij, 100, 1, &[9, 9]), 199);
+        assert_eq!(access_addr(aij, 800, 8, &[9, 9]), 1592);
+    }
+
+    fn run(program: &Program) -> Vec<(usize, Vec<i32>, usize)> {
+        let mut stream = AccessStream::new(program);
+        let mut run = vec![];
+        while let Some(a) = stream.next() {
+            run.push((a.ref_id, stream.ivec().to_vec(), a.addr));
+        }
+        run
+    }
+
+    #[test]
+    fn bounds_steps_and_branches() {
+        let code = parse(
+            "double A[8]; int x[8];
+             for (i = 5; i > 1; i -= 2)
+               for (j = i - 2; j <= min(3, i); j++)
+                 if (i - 2 == j) x[j] = 0; else A[i] = x[j];",
+            &[],
+        )
+        .unwrap();
+        let program = Program::new(&code);
+        // x is at 0 and A at 32, the then arm writes x[j] and the else arm reads x[j]
+        // and writes A[i]
+        assert_eq!(
+            run(&program),
+            [
+                (0, vec![5, 3], 12),
+                (0, vec![3, 1], 4),
+                (1, vec![3, 2], 8),
+                (2, vec![3, 2], 56),
+                (1, vec![3, 3], 12),
+                (2, vec![3, 3], 56),
+            ]
+        );
+    }
+
+    #[test]
+    fn dynamic_bounds() {
+        // for i in 0..3 { for j in 0..i { A[i][j] } }, with closures
+        let mut aloop = Node::new_single_loop("i", 0, 3);
+        let mut inner = Node::new_single_loop_dyn_ub("j", 0, Box::new(|ivec| ivec[0]));
+        let mut aref = Node::new_ref("A", vec![3, 3], |ij| vec![ij[0] as usize, ij[1] as usize]);
+        Node::extend_loop_body(&mut inner, &mut aref);
+        Node::extend_loop_body(&mut aloop, &mut inner);
+        let program = Program::with_elem_size(&aloop, 4);
+        let ivecs: Vec<Vec<i32>> = run(&program).into_iter().map(|(_, v, _)| v).collect();
+        assert_eq!(ivecs, [vec![1, 0], vec![2, 0], vec![2, 1]]);
+        assert_eq!(run(&program)[2].2, 28);
+    }
+
+    #[test]
+    fn checked() {
+        let code = parse(
+            "double A[4]; for (i = 0; i < 4; i++) { A[i] = 0; A[i + 1] = 1; }",
+            &[],
+        )
+        .unwrap();
+        let program = Program::new(&code);
+        let accesses: Vec<_> = AccessStream::new(&program).checked("shift").collect();
+        // A[4] is reached at i = 3, after 7 accesses
+        assert_eq!(accesses.len(), 8);
+        assert!(accesses[..7].iter().all(Result::is_ok));
+        let Err(err) = &accesses[7] else {
+            panic!("the overrun is not caught")
+        };
+        assert_eq!((err.ref_id, err.ivec.as_slice()), (1, &[3][..]));
+    }
+}
@@ -0,0 +1,380 @@
+//! Exports a loop tree for review: to Graphviz DOT to look at, and to JSON to diff between
+//! commits or read from other tools.
+//!
+//! Both show each loop with its bounds and rank, each branch with its condition, blocks,
+//! and each reference with its id, array base, element size and rank (the number of loops
+//! enclosing it). Bounds, conditions and subscripts are written symbolically, using the
+//! loop index names, when they have a symbolic form; closures show as `dynamic` in DOT and
+//! as `null` in JSON.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::{AccessKind, LoopBound, Stmt};
+use crate::program::{NodeId, Program};
+
+/// The JSON form of a program, see `to_json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tree {
+    /// The arrays by increasing base address.
+    pub arrays: Vec<Array>,
+    /// Number of bytes from address 0 to the end of the last array.
+    pub data_size: usize,
+    pub root: TreeNode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Array {
+    pub name: String,
+    pub dim: Vec<usize>,
+    pub elem_size: usize,
+    /// Base byte address.
+    pub base: usize,
+    /// The `Layout`, in its `Debug` form.
+    pub layout: String,
+}
+
+/// One site of the program. `site` is its `NodeId::index`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TreeNode {
+    Loop {
+        site: usize,
+        iv: String,
+        rank: i32,
+        lb: Option<String>,
+        ub: Option<String>,
+        /// The comparison of the index against `ub`, e.g. `<`.
+        test: Option<String>,
+        step: Option<i32>,
+        body: Vec<TreeNode>,
+    },
+    Ref {
+        site: usize,
+        ref_id: usize,
+        array: String,
+        kind: AccessKind,
+        /// One expression per dimension, e.g. `["i", "k+1"]`.
+        subscripts: Option<Vec<String>>,
+        base: usize,
+        elem_size: usize,
+        rank: usize,
+    },
+    Block {
+        site: usize,
+        body: Vec<TreeNode>,
+    },
+    Branch {
+        site: usize,
+        cond: Option<String>,
+        then_body: Box<TreeNode>,
+        else_body: Option<Box<TreeNode>>,
+    },
+}
+
+/// The JSON form of `program`, see `Tree`.
+pub fn tree(program: &Program) -> Tree {
+    let mut arrays: Vec<Array> = vec![];
+    for id in program.refs() {
+        let aref = program.ary_ref(id).unwrap();
+        if arrays.iter().all(|a| a.name != aref.name) {
+            arrays.push(Array {
+                name: aref.name.clone(),
+                dim: aref.dim.clone(),
+                elem_size: program.elem_size(id).unwrap(),
+                base: program.base(id).unwrap(),
+                layout: format!("{:?}", aref.layout),
+            });
+        }
+    }
+    arrays.sort_by_key(|a| a.base);
+    Tree {
+        arrays,
+        data_size: program.data_size(),
+        root: tree_node(program, program.root(), &mut vec![]),
+    }
+}
+
+/// `ivs` holds the indices of the loops enclosing `id`, outermost first.
+fn tree_node(program: &Program, id: NodeId, ivs: &mut Vec<String>) -> TreeNode {
+    let site = id.index();
+    let children = program.children(id);
+    match program.stmt(id) {
+        Stmt::Loop(aloop) => {
+            let (lb, ub) = (bound(&aloop.lb, ivs), bound(&aloop.ub, ivs));
+            ivs.push(aloop.iv.clone());
+            let body = children
+                .iter()
+                .map(|&c| tree_node(program, c, ivs))
+                .collect();
+            ivs.pop();
+            TreeNode::Loop {
+                site,
+                iv: aloop.iv.clone(),
+                rank: program.rank(id).unwrap(),
+                lb,
+                ub,
+                test: aloop.cmp.map(|c| c.as_str().to_string()),
+                step: aloop.stride,
+                body,
+            }
+        }
+        Stmt::Ref(aref) => TreeNode::Ref {
+            site,
+            ref_id: program.ref_id(id).unwrap(),
+            array: aref.name.clone(),
+            kind: aref.kind,
+            subscripts: aref
+                .sub_affine
+                .as_ref()
+                .map(|subs| subs.iter().map(|e| e.to_string_with(ivs)).collect()),
+            base: program.base(id).unwrap(),
+            elem_size: program.elem_size(id).unwrap(),
+            rank: ivs.len(),
+        },
+        Stmt::Block(_) => TreeNode::Block {
+            site,
+            body: children
+                .iter()
+                .map(|&c| tree_node(program, c, ivs))
+                .collect(),
+        },
+        Stmt::Branch(branch) => TreeNode::Branch {
+            site,
+            cond: branch.pred.as_ref().map(|p| p.to_string_with(ivs)),
+            then_body: Box::new(tree_node(program, children[0], ivs)),
+            else_body: children
+                .get(1)
+                .map(|&c| Box::new(tree_node(program, c, ivs))),
+        },
+    }
+}
+
+fn bound(bound: &LoopBound, ivs: &[String]) -> Option<String> {
+    bound.is_symbolic().then(|| bound.to_string_with(ivs))
+}
+
+/// `program` as indented JSON, see `Tree` for the schema.
+pub fn to_json(program: &Program) -> String {
+    serde_json::to_string_pretty(&tree(program)).expect("a tree always serializes")
+}
+
+/// `program` as a Graphviz digraph named `name`, e.g. for `dot -Tsvg`. Nodes are named
+/// `n` followed by the site index.
+///
+/// # Examples
+/// ```rust
+/// use dace::program::Program;
+/// let code = dace::parse::parse("double A[4]; for (i = 0; i < 4; i++) A[i] = 0;", &[]).unwrap();
+/// let dot = dace::export::to_dot(&Program::new(&code), "zero");
+/// assert!(dot.starts_with("digraph \"zero\" {"));
+/// assert!(dot.contains("n0 -> n1;"));
+/// ```
+pub fn to_dot(program: &Program, name: &str) -> String {
+    let mut out = format!(
+        "digraph \"{}\" {{\n  node [shape=box, fontname=\"monospace\"];\n",
+        escape(name)
+    );
+    dot_node(&tree(program).root, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+fn dot_node(node: &TreeNode, out: &mut String) {
+    let or_dynamic = |s: &Option<String>| s.clone().unwrap_or_else(|| "dynamic".to_string());
+    let (site, attrs) = match node {
+        TreeNode::Loop {
+            site,
+            iv,
+            rank,
+            lb,
+            ub,
+            test,
+            step,
+            ..
+        } => {
+            let step = step.map_or("dynamic".to_string(), |s| s.to_string());
+            let label = format!(
+                "for {} = {}; {} {} {}; step {}\\nrank {}",
+                iv,
+                or_dynamic(lb),
+                iv,
+                test.as_deref().unwrap_or("?"),
+                or_dynamic(ub),
+                step,
+                rank
+            );
+            (site, format!("label=\"{}\"", escape(&label)))
+        }
+        TreeNode::Ref {
+            site,
+            ref_id,
+            array,
+            kind,
+            subscripts,
+            base,
+            rank,
+            ..
+        } => {
+            let subs = match subscripts {
+                Some(subs) => subs.iter().map(|s| format!("[{}]", s)).collect(),
+                None => "[dynamic]".to_string(),
+            };
+            let label = format!(
+                "#{} {} {}{}\\nbase {}, rank {}",
+                ref_id, kind, array, subs, base, rank
+            );
+            (site, format!("shape=ellipse, label=\"{}\"", escape(&label)))
+        }
+        TreeNode::Block { site, .. } => (site, "style=dashed, label=\"block\"".to_string()),
+        TreeNode::Branch { site, cond, .. } => {
+            let label = format!("if {}", or_dynamic(cond));
+            (site, format!("shape=diamond, label=\"{}\"", escape(&label)))
+        }
+    };
+    out.push_str(&format!("  n{} [{}];\n", site, attrs));
+    let edge = |out: &mut String, child: &TreeNode, label: Option<&str>| {
+        let child_site = match child {
+            TreeNode::Loop { site, .. }
+            | TreeNode::Ref { site, .. }
+            | TreeNode::Block { site, .. }
+            | TreeNode::Branch { site, .. } => site,
+        };
+        match label {
+            Some(label) => out.push_str(&format!(
+                "  n{} -> n{} [label=\"{}\"];\n",
+                site, child_site, label
+            )),
+            None => out.push_str(&format!("  n{} -> n{};\n", site, child_site)),
+        }
+        dot_node(child, out);
+    };
+    match node {
+        TreeNode::Loop { body, .. } | TreeNode::Block { body, .. } => {
+            for child in body {
+                edge(out, child, None);
+            }
+        }
+        TreeNode::Branch {
+            then_body,
+            else_body,
+            ..
+        } => {
+            edge(out, then_body, Some("then"));
+            if let Some(else_body) = else_body {
+                edge(out, else_body, Some("else"));
+            }
+        }
+        TreeNode::Ref { .. } => {}
+    }
+}
+
+/// Escapes the quotes in a DOT string. `\n` in labels is a DOT line break and stays.
+fn escape(s: &str) -> String {
+    s.replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+
+    fn program() -> Program {
+        Program::new(
+            &parse(
+                "double A[4][4]; int x[4];
+                 for (i = 0; i < 4; i++)
+                   for (j = i; j <= 3; j += 2)
+                     if (i == j) x[i] += 1; else A[i][j + 1] = A[j][i];",
+                &[],
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn json() {
+        let program = program();
+        let tree = tree(&program);
+        assert_eq!(tree.data_size, 144);
+        assert_eq!(
+            tree.arrays
+                .iter()
+                .map(|a| (a.name.as_str(), a.elem_size, a.base))
+                .collect::<Vec<_>>(),
+            [("x", 4, 0), ("A", 8, 16)]
+        );
+        let TreeNode::Loop { body, .. } = &tree.root else {
+            panic!("the root is not a loop")
+        };
+        let TreeNode::Loop {
+            lb,
+            test,
+            step,
+            body,
+            rank,
+            ..
+        } = &body[0]
+        else {
+            panic!("the inner loop is missing")
+        };
+        assert_eq!(
+            (lb.as_deref(), test.as_deref(), *step, *rank),
+            (Some("i"), Some("<="), Some(2), 1)
+        );
+        let TreeNode::Branch {
+            cond, else_body, ..
+        } = &body[0]
+        else {
+            panic!("the branch is missing")
+        };
+        assert_eq!(cond.as_deref(), Some("i-j == 0"));
+        let Some(else_body) = else_body else {
+            panic!("the else arm is missing")
+        };
+        let TreeNode::Block { body, .. } = else_body.as_ref() else {
+            panic!("the else arm is not a block")
+        };
+        assert_eq!(
+            body[1],
+            TreeNode::Ref {
+                site: 8,
+                ref_id: 3,
+                array: "A".to_string(),
+                kind: AccessKind::Write,
+                subscripts: Some(vec!["i".to_string(), "j+1".to_string()]),
+                base: 16,
+                elem_size: 8,
+                rank: 2,
+            }
+        );
+
+        let json = to_json(&program);
+        assert!(json.contains("\"type\": \"branch\""));
+        assert_eq!(serde_json::from_str::<Tree>(&json).unwrap(), tree);
+    }
+
+    #[test]
+    fn dot() {
+        let dot = to_dot(&program(), "k");
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(
+            lines[2],
+            "  n0 [label=\"for i = 0; i < 4; step 1\\nrank 0\"];"
+        );
+        assert!(lines.contains(&"  n2 [shape=diamond, label=\"if i-j == 0\"];"));
+        assert!(lines.contains(&"  n2 -> n3 [label=\"then\"];"));
+        assert!(lines.contains(&"  n2 -> n6 [label=\"else\"];"));
+        assert!(
+            lines.contains(&"  n8 [shape=ellipse, label=\"#3 W A[i][j+1]\\nbase 16, rank 2\"];")
+        );
+        assert_eq!(dot.matches("->").count(), program().len() - 1);
+
+        // closures have no symbolic form
+        let mut aloop = crate::ast::Node::new_single_loop_dyn_ub("i", 0, Box::new(|_| 4));
+        let mut aref = crate::ast::Node::new_ref("A", vec![4], |i| vec![i[0] as usize]);
+        crate::ast::Node::extend_loop_body(&mut aloop, &mut aref);
+        let dot = to_dot(&Program::new(&aloop), "dyn");
+        assert!(dot.contains("for i = 0; i < dynamic; step 1"));
+        assert!(dot.contains("#0 R A[dynamic]"));
+    }
+}
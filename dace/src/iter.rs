@@ -19,42 +19,30 @@ impl Walk {
         //     if lp.body.borrow().len() > 0 { Some(0) } else { None } }))] }
     }
 
-    /// This checks the last node in the stack and its visited count. Depending on the type of the Node (Loop, Ref, Block, or Branch), it updates the stack and returns the Node if it's being visited for the first time. Steps through the AST nodes, returning the next node.
+    /// Pops the last node in the stack, pushing it back with its next child on top if it has
+    /// one left. Returns the node if it's being visited for the first time.
     fn step(&mut self) -> Option<Rc<Node>> {
         let (node, visited) = self.stack.pop()?;
-        let result = if visited == 0 {
-            Some(node.clone())
-        } else {
-            None
-        };
-        // Visit the node for the first time
-
-        match &node.as_ref().stmt {
-            Stmt::Loop(loop_stmt) => {
-                if visited < loop_stmt.body.len() {
-                    self.stack.push((node.clone(), visited + 1));
-                    self.stack.push((loop_stmt.body[visited].clone(), 0));
-                }
-            }
-            Stmt::Branch(branch) => {
-                if visited == 1 && branch.else_body.is_some() {
-                    self.stack.push((node.clone(), visited + 1));
-                    self.stack
-                        .push((branch.else_body.as_ref().unwrap().clone(), 0));
-                } else if visited == 0 {
-                    self.stack.push((node.clone(), visited + 1));
-                    self.stack.push((branch.then_body.clone(), 0));
-                }
-            }
-            Stmt::Block(blk) => {
-                if visited < blk.len() {
-                    self.stack.push((node.clone(), visited + 1));
-                    self.stack.push((blk[visited].clone(), 0));
-                }
-            }
-            _ => {} // AryRef (pop) or Block (handled in the same way as loop but without .body)
+        if let Some(child) = nth_child(&node, visited) {
+            self.stack.push((node.clone(), visited + 1));
+            self.stack.push((child, 0));
         }
-        result
+        (visited == 0).then_some(node)
+    }
+}
+
+/// The `k`-th child of `node`: the body of a loop or block, or the then and (optional) else
+/// parts of a branch.
+fn nth_child(node: &Node, k: usize) -> Option<Rc<Node>> {
+    match &node.stmt {
+        Stmt::Loop(loop_stmt) => loop_stmt.body.borrow().get(k).cloned(),
+        Stmt::Block(blk) => blk.get(k).cloned(),
+        Stmt::Branch(branch) => match k {
+            0 => Some(branch.then_body.clone()),
+            1 => branch.else_body.clone(),
+            _ => None,
+        },
+        Stmt::Ref(_) => None,
     }
 }
 
@@ -72,6 +60,67 @@ impl Iterator for Walk {
     }
 }
 
+/// Like `Walk`, but returns every node after all the nodes beneath it, e.g. the references
+/// of a loop before the loop.
+pub struct PostWalk {
+    stack: Vec<(Rc<Node>, usize)>,
+}
+
+impl PostWalk {
+    pub fn new(root: &Rc<Node>) -> Self {
+        PostWalk {
+            stack: vec![(root.clone(), 0)],
+        }
+    }
+}
+
+impl Iterator for PostWalk {
+    type Item = Rc<Node>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, visited)) = self.stack.pop() {
+            match nth_child(&node, visited) {
+                Some(child) => {
+                    self.stack.push((node, visited + 1));
+                    self.stack.push((child, 0));
+                }
+                None => return Some(node),
+            }
+        }
+        None
+    }
+}
+
+/// Like `Walk`, but returns every node with its parent, `None` for the root. A node shared
+/// by several parents is returned once under each.
+pub struct ParentWalk {
+    stack: Vec<(Rc<Node>, Option<Rc<Node>>)>,
+}
+
+impl ParentWalk {
+    pub fn new(root: &Rc<Node>) -> Self {
+        ParentWalk {
+            stack: vec![(root.clone(), None)],
+        }
+    }
+}
+
+impl Iterator for ParentWalk {
+    type Item = (Rc<Node>, Option<Rc<Node>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, parent) = self.stack.pop()?;
+        let children: Vec<Rc<Node>> = (0..).map_while(|k| nth_child(&node, k)).collect();
+        self.stack.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|child| (child, Some(node.clone()))),
+        );
+        Some((node, parent))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -129,4 +178,40 @@ mod test {
         let awalk = Walk::new(&iloop);
         assert_eq!(awalk.fold(0, |cnt, _stmt| cnt + 1), 4);
     }
+
+    #[test]
+    fn post_order_and_parents() {
+        // i { A[i]; if (..) { B[i] } else { C[i] } }
+        let mut aloop = Node::new_single_loop("i", 0, 10);
+        let mut aref = Node::new_ref("A", vec![10], |i| vec![i[0] as usize]);
+        let bref = Node::new_ref("B", vec![10], |i| vec![i[0] as usize]);
+        let cref = Node::new_ref("C", vec![10], |i| vec![i[0] as usize]);
+        let mut branch = crate::branch_node!(if (|i: &[i32]| i[0] < 5) { bref } else { cref });
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        Node::extend_loop_body(&mut aloop, &mut branch);
+
+        let name = |node: &Rc<Node>| match &node.stmt {
+            Stmt::Loop(aloop) => aloop.iv.clone(),
+            Stmt::Ref(aref) => aref.name.clone(),
+            Stmt::Branch(_) => "if".to_string(),
+            Stmt::Block(_) => "block".to_string(),
+        };
+        let pre: Vec<String> = Walk::new(&aloop).map(|n| name(&n)).collect();
+        assert_eq!(pre, ["i", "A", "if", "B", "C"]);
+        let post: Vec<String> = PostWalk::new(&aloop).map(|n| name(&n)).collect();
+        assert_eq!(post, ["A", "B", "C", "if", "i"]);
+        let parents: Vec<(String, Option<String>)> = ParentWalk::new(&aloop)
+            .map(|(n, p)| (name(&n), p.map(|p| name(&p))))
+            .collect();
+        assert_eq!(
+            parents,
+            [
+                ("i".to_string(), None),
+                ("A".to_string(), Some("i".to_string())),
+                ("if".to_string(), Some("i".to_string())),
+                ("B".to_string(), Some("if".to_string())),
+                ("C".to_string(), Some("if".to_string())),
+            ]
+        );
+    }
 }
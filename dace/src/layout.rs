@@ -0,0 +1,164 @@
+//! How the elements of a multi-dimensional array are ordered in memory.
+//!
+//! A `Layout` maps the index of an element, one subscript per dimension, to its offset in
+//! elements from the start of the array. The subscripts of a kernel stay the same whatever
+//! the layout, so the effect of a layout on reuse can be studied by changing only the array
+//! declarations, see `Node::with_layout`.
+
+use serde::{Deserialize, Serialize};
+
+/// The order of the elements of an array, see the module documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Layout {
+    /// The last subscript varies fastest, as in C.
+    #[default]
+    RowMajor,
+    /// The first subscript varies fastest, as in Fortran.
+    ColumnMajor,
+    /// Row-major order of the dimensions listed from slowest to fastest varying, e.g. `[1, 0]`
+    /// is column-major for a matrix.
+    Permuted(Vec<usize>),
+    /// The array is cut into tiles of the given size in each dimension, and both the tiles and
+    /// the elements in a tile are in row-major order. Partial tiles at the edges are padded.
+    Blocked(Vec<usize>),
+    /// Z-order: the bits of the subscripts are interleaved, most significant first and the
+    /// first dimension first at each bit. Each dimension is padded to a power of two.
+    Morton,
+}
+
+impl Layout {
+    /// The offset in elements of the element at `index` in an array of dimensions `dim`.
+    pub fn offset(&self, index: &[usize], dim: &[usize]) -> usize {
+        match self {
+            Layout::RowMajor => index.iter().zip(dim).fold(0, |acc, (&i, &d)| acc * d + i),
+            Layout::ColumnMajor => index
+                .iter()
+                .zip(dim)
+                .rev()
+                .fold(0, |acc, (&i, &d)| acc * d + i),
+            Layout::Permuted(order) => order.iter().fold(0, |acc, &k| acc * dim[k] + index[k]),
+            Layout::Blocked(tile) => {
+                let (mut outer, mut inner) = (0, 0);
+                for ((&i, &d), &b) in index.iter().zip(dim).zip(tile) {
+                    outer = outer * d.div_ceil(b) + i / b;
+                    inner = inner * b + i % b;
+                }
+                outer * tile.iter().product::<usize>() + inner
+            }
+            Layout::Morton => {
+                let bits: Vec<u32> = dim.iter().map(|&d| morton_bits(d)).collect();
+                let top = bits.iter().copied().max().unwrap_or(0);
+                let mut offset = 0;
+                for level in (0..top).rev() {
+                    for (&i, &b) in index.iter().zip(&bits) {
+                        if level < b {
+                            offset = offset << 1 | (i >> level & 1);
+                        }
+                    }
+                }
+                offset
+            }
+        }
+    }
+
+    /// The number of elements an array of dimensions `dim` spans, including any padding.
+    ///
+    /// # Panics
+    /// If the layout does not fit an array of `dim.len()` dimensions, e.g. a permutation of
+    /// the wrong length or a zero tile size.
+    pub fn size(&self, dim: &[usize]) -> usize {
+        match self {
+            Layout::RowMajor | Layout::ColumnMajor => dim.iter().product(),
+            Layout::Permuted(order) => {
+                let mut sorted = order.clone();
+                sorted.sort_unstable();
+                assert!(
+                    sorted.iter().copied().eq(0..dim.len()),
+                    "{:?} is not a permutation of the {} dimension(s)",
+                    order,
+                    dim.len()
+                );
+                dim.iter().product()
+            }
+            Layout::Blocked(tile) => {
+                assert!(
+                    tile.len() == dim.len() && tile.iter().all(|&b| b > 0),
+                    "{:?} are not tile sizes for the {} dimension(s)",
+                    tile,
+                    dim.len()
+                );
+                dim.iter()
+                    .zip(tile)
+                    .map(|(&d, &b)| d.div_ceil(b) * b)
+                    .product()
+            }
+            Layout::Morton => 1 << dim.iter().map(|&d| morton_bits(d)).sum::<u32>(),
+        }
+    }
+}
+
+/// The number of bits of a subscript in `0..d`.
+fn morton_bits(d: usize) -> u32 {
+    d.next_power_of_two().trailing_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offsets of a 2-D array, row by row.
+    fn offsets(layout: &Layout, dim: [usize; 2]) -> Vec<Vec<usize>> {
+        (0..dim[0])
+            .map(|i| (0..dim[1]).map(|j| layout.offset(&[i, j], &dim)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn dense() {
+        assert_eq!(offsets(&Layout::RowMajor, [2, 3]), [[0, 1, 2], [3, 4, 5]]);
+        assert_eq!(
+            offsets(&Layout::ColumnMajor, [2, 3]),
+            [[0, 2, 4], [1, 3, 5]]
+        );
+        assert_eq!(
+            offsets(&Layout::Permuted(vec![1, 0]), [2, 3]),
+            offsets(&Layout::ColumnMajor, [2, 3])
+        );
+        assert_eq!(Layout::ColumnMajor.size(&[2, 3]), 6);
+
+        // A[i][j][k] with j slowest and i fastest
+        let layout = Layout::Permuted(vec![1, 2, 0]);
+        assert_eq!(layout.offset(&[1, 0, 0], &[2, 3, 4]), 1);
+        assert_eq!(layout.offset(&[0, 0, 1], &[2, 3, 4]), 2);
+        assert_eq!(layout.offset(&[0, 1, 0], &[2, 3, 4]), 8);
+    }
+
+    #[test]
+    fn blocked() {
+        let layout = Layout::Blocked(vec![2, 2]);
+        assert_eq!(
+            offsets(&layout, [3, 4]),
+            [[0, 1, 4, 5], [2, 3, 6, 7], [8, 9, 12, 13]]
+        );
+        // the last row of tiles is padded
+        assert_eq!(layout.size(&[3, 4]), 16);
+    }
+
+    #[test]
+    fn morton() {
+        assert_eq!(
+            offsets(&Layout::Morton, [4, 4]),
+            [[0, 1, 4, 5], [2, 3, 6, 7], [8, 9, 12, 13], [10, 11, 14, 15]]
+        );
+        // 3 rows take 2 bits and 2 columns 1, so only the high row bit stands alone
+        assert_eq!(offsets(&Layout::Morton, [3, 2]), [[0, 1], [2, 3], [4, 5]]);
+        assert_eq!(Layout::Morton.size(&[3, 2]), 8);
+        assert_eq!(Layout::Morton.size(&[5]), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a permutation")]
+    fn bad_permutation() {
+        Layout::Permuted(vec![0, 0]).size(&[2, 2]);
+    }
+}
@@ -1,9 +1,20 @@
-#![feature(get_mut_unchecked)]
-#![allow(internal_features)] // to hide the warning of using unstable features for the line below
-#![feature(core_intrinsics)]
-
+pub mod affine;
 pub mod arybase;
 pub mod ast;
+pub mod bounds;
 pub mod construct;
+pub mod deps;
+pub mod emit;
+pub mod exec;
+pub mod export;
 pub mod iter;
+pub mod layout;
+pub mod parse;
+pub mod program;
+pub mod serial;
+pub mod transform;
 pub mod types;
+pub mod validate;
+pub mod visit;
+
+pub use validate::validate;
@@ -0,0 +1,1150 @@
+//! A small C-like loop language that builds DACE loop trees.
+//!
+//! Kernels are written as a sequence of parameter and array declarations followed by
+//! statements:
+//!
+//! ```text
+//! param N = 1024;
+//! double A[N][N];
+//! double B[N][N];
+//! double C[N][N];
+//!
+//! for (i = 0; i < N; i++)
+//!   for (j = 0; j < N; j++) {
+//!     C[i][j] *= beta;
+//!     for (k = 0; k < N; k++)
+//!       C[i][j] += alpha * A[i][k] * B[k][j];
+//!   }
+//! ```
+//!
+//! Loop bounds, branch predicates and array subscripts must be affine in the enclosing loop
+//! indices and the size parameters. Every array access in a statement becomes a reference
+//! node, in evaluation order: the right-hand side from left to right, then the left-hand side
+//! (read first if the assignment is compound, e.g. `+=`). The assigned element is a
+//! `AccessKind::Write`, every other access a `AccessKind::Read`.
+//!
+//! # Examples
+//! ```rust
+//! let code = dace::parse::parse(
+//!     "param N; double A[N]; for (i = 0; i < N; i++) A[i] = A[i] + 1;",
+//!     &[("N", 16)],
+//! )
+//! .unwrap();
+//! assert_eq!(code.node_count(), 3);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::affine::{compile_sub, AffineExpr};
+use crate::ast::{AccessKind, AryRef, CType, Cmp, ElemType, LoopBound, Node, Predicate, Stmt};
+use crate::layout::Layout;
+
+/// Multi-character operators first, so that the lexer always takes the longest match.
+const PUNCTS: [&str; 32] = [
+    "++", "--", "+=", "-=", "*=", "/=", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "{", "}",
+    "[", "]", ";", ",", "=", "+", "-", "*", "/", "%", "<", ">", "!", "?", ":", ".",
+];
+
+/// Position of a token in the source text, 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, span: Span) -> Self {
+        ParseError {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Int(i32),
+    Float,
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    span: Span,
+}
+
+fn lex(src: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let (mut pos, mut line, mut column) = (0, 1, 1);
+
+    let advance = |pos: &mut usize, line: &mut usize, column: &mut usize, n: usize| {
+        for _ in 0..n {
+            if chars[*pos] == '\n' {
+                *line += 1;
+                *column = 1;
+            } else {
+                *column += 1;
+            }
+            *pos += 1;
+        }
+    };
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let span = Span { line, column };
+        if c.is_whitespace() {
+            advance(&mut pos, &mut line, &mut column, 1);
+        } else if c == '/' && chars.get(pos + 1) == Some(&'/') {
+            while pos < chars.len() && chars[pos] != '\n' {
+                advance(&mut pos, &mut line, &mut column, 1);
+            }
+        } else if c == '/' && chars.get(pos + 1) == Some(&'*') {
+            advance(&mut pos, &mut line, &mut column, 2);
+            loop {
+                if pos + 1 >= chars.len() {
+                    return Err(ParseError::new("unterminated comment", span));
+                }
+                if chars[pos] == '*' && chars[pos + 1] == '/' {
+                    advance(&mut pos, &mut line, &mut column, 2);
+                    break;
+                }
+                advance(&mut pos, &mut line, &mut column, 1);
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                advance(&mut pos, &mut line, &mut column, 1);
+            }
+            let ident: String = chars[start..pos].iter().collect();
+            tokens.push(Token {
+                tok: Tok::Ident(ident),
+                span,
+            });
+        } else if c.is_ascii_digit() {
+            let start = pos;
+            let mut float = false;
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+                float |= chars[pos] == '.';
+                advance(&mut pos, &mut line, &mut column, 1);
+            }
+            let text: String = chars[start..pos].iter().collect();
+            let tok = if float {
+                Tok::Float
+            } else {
+                Tok::Int(text.parse().map_err(|_| {
+                    ParseError::new(format!("integer literal `{}` is too large", text), span)
+                })?)
+            };
+            tokens.push(Token { tok, span });
+        } else {
+            let punct = PUNCTS
+                .iter()
+                .find(|p| {
+                    p.chars()
+                        .enumerate()
+                        .all(|(k, pc)| chars.get(pos + k) == Some(&pc))
+                })
+                .ok_or_else(|| ParseError::new(format!("unexpected character `{}`", c), span))?;
+            advance(&mut pos, &mut line, &mut column, punct.len());
+            tokens.push(Token {
+                tok: Tok::Punct(punct),
+                span,
+            });
+        }
+    }
+    tokens.push(Token {
+        tok: Tok::Eof,
+        span: Span { line, column },
+    });
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Int(i32, Span),
+    Float(Span),
+    Var(String, Span),
+    Access(String, Vec<Expr>, Span),
+    Call(String, Vec<Expr>, Span),
+    Neg(Box<Expr>, Span),
+    Bin(&'static str, Box<Expr>, Box<Expr>, Span),
+}
+
+impl Expr {
+    fn span(&self) -> Span {
+        match self {
+            Expr::Int(_, s)
+            | Expr::Float(s)
+            | Expr::Var(_, s)
+            | Expr::Access(_, _, s)
+            | Expr::Call(_, _, s)
+            | Expr::Neg(_, s)
+            | Expr::Bin(_, _, _, s) => *s,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |args: &[Expr], sep: &str| {
+            args.iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+        match self {
+            Expr::Int(v, _) => write!(f, "{}", v),
+            Expr::Float(_) => write!(f, "<float>"),
+            Expr::Var(name, _) => write!(f, "{}", name),
+            Expr::Access(name, subs, _) => write!(f, "{}[{}]", name, list(subs, "][")),
+            Expr::Call(name, args, _) => write!(f, "{}({})", name, list(args, ", ")),
+            Expr::Neg(e, _) => write!(f, "-{}", e),
+            Expr::Bin(op, l, r, _) => write!(f, "{}{}{}", l, op, r),
+        }
+    }
+}
+
+fn contains_call(e: &Expr) -> bool {
+    match e {
+        Expr::Call(..) => true,
+        Expr::Neg(x, _) => contains_call(x),
+        Expr::Bin(_, l, r, _) => contains_call(l) || contains_call(r),
+        _ => false,
+    }
+}
+
+/// The error for an expression whose value does not fit an `i32`.
+fn overflow(e: &Expr, span: Span) -> ParseError {
+    ParseError::new(format!("expression `{}` overflows", e), span)
+}
+
+const ASSIGNMENTS: [&str; 5] = ["=", "+=", "-=", "*=", "/="];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    params: HashMap<String, i32>,
+    /// Dimensions and element type of every declared array.
+    arrays: HashMap<String, (Vec<usize>, ElemType)>,
+    /// Indices of the loops enclosing the statement being parsed, outermost first.
+    ivs: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn span(&self) -> Span {
+        self.tokens[self.pos].span
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.tok != Tok::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(q) if *q == p)
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Tok::Ident(id) if id == kw)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let found = match self.peek() {
+            Tok::Ident(id) => format!("`{}`", id),
+            Tok::Int(v) => format!("`{}`", v),
+            Tok::Float => "a floating-point literal".to_string(),
+            Tok::Punct(p) => format!("`{}`", p),
+            Tok::Eof => "end of input".to_string(),
+        };
+        ParseError::new(
+            format!("expected {}, found {}", expected, found),
+            self.span(),
+        )
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<()> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", p)))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<(String, Span)> {
+        match self.peek().clone() {
+            Tok::Ident(id) => {
+                let span = self.next().span;
+                Ok((id, span))
+            }
+            _ => Err(self.unexpected("an identifier")),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Rc<Node>>> {
+        let mut nodes = vec![];
+        while *self.peek() != Tok::Eof {
+            if self.is_keyword("param") {
+                self.param_decl()?;
+            } else if CType::ALL.iter().any(|ty| self.is_keyword(ty.name())) {
+                self.array_decl()?;
+            } else {
+                nodes.extend(self.stmt()?);
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn param_decl(&mut self) -> Result<()> {
+        self.next();
+        let (name, span) = self.expect_ident()?;
+        let default = if self.eat_punct("=") {
+            let value = self.expr()?;
+            Some(self.constant(&value)?)
+        } else {
+            None
+        };
+        self.expect_punct(";")?;
+        match (self.params.contains_key(&name), default) {
+            (true, _) => {}
+            (false, Some(value)) => {
+                self.params.insert(name, value);
+            }
+            (false, None) => {
+                return Err(ParseError::new(
+                    format!("parameter `{}` has no value", name),
+                    span,
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn array_decl(&mut self) -> Result<()> {
+        let c_type = CType::ALL
+            .into_iter()
+            .find(|ty| self.is_keyword(ty.name()))
+            .unwrap();
+        let elem = ElemType::of(c_type);
+        self.next();
+        let (name, span) = self.expect_ident()?;
+        if self.arrays.contains_key(&name) {
+            return Err(ParseError::new(
+                format!("array `{}` is declared twice", name),
+                span,
+            ));
+        }
+        let mut dims = vec![];
+        while self.eat_punct("[") {
+            let dim = self.expr()?;
+            let size = self.constant(&dim)?;
+            if size <= 0 {
+                return Err(ParseError::new(
+                    format!("array dimension must be positive, found {}", size),
+                    dim.span(),
+                ));
+            }
+            dims.push(size as usize);
+            self.expect_punct("]")?;
+        }
+        if dims.is_empty() {
+            return Err(self.unexpected("`[`"));
+        }
+        self.expect_punct(";")?;
+        self.arrays.insert(name, (dims, elem));
+        Ok(())
+    }
+
+    /// Parses one statement into the nodes it contributes to the enclosing body.
+    fn stmt(&mut self) -> Result<Vec<Rc<Node>>> {
+        if self.is_keyword("for") {
+            Ok(vec![self.for_stmt()?])
+        } else if self.is_keyword("if") {
+            Ok(vec![self.if_stmt()?])
+        } else if self.eat_punct("{") {
+            let mut nodes = vec![];
+            while !self.eat_punct("}") {
+                if *self.peek() == Tok::Eof {
+                    return Err(self.unexpected("`}`"));
+                }
+                nodes.extend(self.stmt()?);
+            }
+            Ok(nodes)
+        } else if self.eat_punct(";") {
+            Ok(vec![])
+        } else if self.is_keyword("param") || CType::ALL.iter().any(|ty| self.is_keyword(ty.name()))
+        {
+            Err(ParseError::new(
+                "declarations are only allowed at the top level",
+                self.span(),
+            ))
+        } else {
+            self.simple_stmt()
+        }
+    }
+
+    fn simple_stmt(&mut self) -> Result<Vec<Rc<Node>>> {
+        let lhs = self.expr()?;
+        let assign = ASSIGNMENTS.iter().copied().find(|op| self.is_punct(op));
+        let mut refs = vec![];
+        match assign {
+            Some(op) => {
+                self.next();
+                let rhs = self.expr()?;
+                let Expr::Access(name, subs, span) = &lhs else {
+                    return Err(ParseError::new(
+                        "the left-hand side of an assignment must be an array element",
+                        lhs.span(),
+                    ));
+                };
+                self.collect_refs(&rhs, &mut refs)?;
+                if op != "=" {
+                    refs.push(self.make_ref(name, subs, *span, AccessKind::Read)?);
+                }
+                refs.push(self.make_ref(name, subs, *span, AccessKind::Write)?);
+            }
+            None => self.collect_refs(&lhs, &mut refs)?,
+        }
+        self.expect_punct(";")?;
+        Ok(refs)
+    }
+
+    fn for_stmt(&mut self) -> Result<Rc<Node>> {
+        self.next();
+        self.expect_punct("(")?;
+        if self.is_keyword("int") {
+            self.next();
+        }
+        let (iv, iv_span) = self.expect_ident()?;
+        if self.params.contains_key(&iv) || self.ivs.contains(&iv) {
+            return Err(ParseError::new(
+                format!("loop index `{}` shadows an enclosing name", iv),
+                iv_span,
+            ));
+        }
+        self.expect_punct("=")?;
+        let lb = self.expr()?;
+        let lb = self.bound(&lb)?;
+        self.expect_punct(";")?;
+
+        let (test_iv, test_span) = self.expect_ident()?;
+        if test_iv != iv {
+            return Err(ParseError::new(
+                format!("loop test must compare the loop index `{}`", iv),
+                test_span,
+            ));
+        }
+        let cmp = match self.peek() {
+            Tok::Punct(op) if ["<", "<=", ">", ">="].contains(op) => *op,
+            _ => return Err(self.unexpected("one of `<`, `<=`, `>`, `>=`")),
+        };
+        self.next();
+        let ub = self.expr()?;
+        let ub = self.bound(&ub)?;
+        self.expect_punct(";")?;
+        let stride = self.step(&iv)?;
+        self.expect_punct(")")?;
+
+        self.ivs.push(iv.clone());
+        let body = self.stmt();
+        self.ivs.pop();
+
+        let cmp = Cmp::from_op(cmp).unwrap();
+        let mut aloop = Node::new_strided_loop(&iv, lb, ub, cmp, stride);
+        for mut child in body? {
+            Node::extend_loop_body(&mut aloop, &mut child);
+        }
+        Ok(aloop)
+    }
+
+    /// Parses the increment clause of a loop header and returns the stride.
+    fn step(&mut self, iv: &str) -> Result<i32> {
+        let span = self.span();
+        let check_iv = |name: &str| {
+            if name == iv {
+                Ok(())
+            } else {
+                Err(ParseError::new(
+                    format!("loop step must update the loop index `{}`", iv),
+                    span,
+                ))
+            }
+        };
+        let stride = if self.eat_punct("++") {
+            check_iv(&self.expect_ident()?.0)?;
+            1
+        } else if self.eat_punct("--") {
+            check_iv(&self.expect_ident()?.0)?;
+            -1
+        } else {
+            check_iv(&self.expect_ident()?.0)?;
+            if self.eat_punct("++") {
+                1
+            } else if self.eat_punct("--") {
+                -1
+            } else if self.eat_punct("+=") {
+                let e = self.expr()?;
+                self.constant(&e)?
+            } else if self.eat_punct("-=") {
+                let e = self.expr()?;
+                -self.constant(&e)?
+            } else if self.eat_punct("=") {
+                // i = i + c  or  i = i - c
+                let e = self.expr()?;
+                let lin = self.affine_in(&e, &[iv.to_string()])?;
+                if lin.coeff(0) != 1 || lin.depth() != 1 {
+                    return Err(ParseError::new(
+                        format!("loop step must have the form `{} = {} + c`", iv, iv),
+                        e.span(),
+                    ));
+                }
+                lin.constant
+            } else {
+                return Err(self.unexpected("a loop increment"));
+            }
+        };
+        if stride == 0 {
+            return Err(ParseError::new("loop step must not be zero", span));
+        }
+        Ok(stride)
+    }
+
+    fn if_stmt(&mut self) -> Result<Rc<Node>> {
+        self.next();
+        self.expect_punct("(")?;
+        let cond = self.cond_or()?;
+        self.expect_punct(")")?;
+        let then_body = block_of(self.stmt()?);
+        let else_body = if self.is_keyword("else") {
+            self.next();
+            Some(block_of(self.stmt()?))
+        } else {
+            None
+        };
+        Ok(Node::new_branch(cond, then_body, else_body))
+    }
+
+    fn cond_or(&mut self) -> Result<Predicate> {
+        let mut cond = self.cond_and()?;
+        while self.eat_punct("||") {
+            cond = Predicate::Or(Box::new(cond), Box::new(self.cond_and()?));
+        }
+        Ok(cond)
+    }
+
+    fn cond_and(&mut self) -> Result<Predicate> {
+        let mut cond = self.cond_not()?;
+        while self.eat_punct("&&") {
+            cond = Predicate::And(Box::new(cond), Box::new(self.cond_not()?));
+        }
+        Ok(cond)
+    }
+
+    fn cond_not(&mut self) -> Result<Predicate> {
+        if self.eat_punct("!") {
+            return Ok(Predicate::Not(Box::new(self.cond_not()?)));
+        }
+        if self.is_punct("(") {
+            // Either a parenthesized predicate or a parenthesized arithmetic operand.
+            let saved = self.pos;
+            self.next();
+            if let Ok(cond) = self.cond_or() {
+                if self.eat_punct(")") && !matches!(self.peek(), Tok::Punct(p) if is_operator(p)) {
+                    return Ok(cond);
+                }
+            }
+            self.pos = saved;
+        }
+        let lhs = self.expr()?;
+        let op = match self.peek() {
+            Tok::Punct(op) => Cmp::from_op(op),
+            _ => None,
+        };
+        let Some(op) = op else {
+            return Err(self.unexpected("a comparison operator"));
+        };
+        self.next();
+        let rhs = self.expr()?;
+        let diff = self.affine(&lhs)? - self.affine(&rhs)?;
+        Ok(Predicate::Cmp(op, diff))
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        while let Tok::Punct(op @ ("+" | "-")) = *self.peek() {
+            let span = self.next().span;
+            let rhs = self.term()?;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Tok::Punct(op @ ("*" | "/" | "%")) = *self.peek() {
+            let span = self.next().span;
+            let rhs = self.unary()?;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs), span);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.is_punct("-") {
+            let span = self.next().span;
+            return Ok(Expr::Neg(Box::new(self.unary()?), span));
+        }
+        if self.eat_punct("+") {
+            return self.unary();
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self.next();
+        match token.tok {
+            Tok::Int(v) => Ok(Expr::Int(v, token.span)),
+            Tok::Float => Ok(Expr::Float(token.span)),
+            Tok::Ident(name) => {
+                if self.is_punct("[") {
+                    let mut subs = vec![];
+                    while self.eat_punct("[") {
+                        subs.push(self.expr()?);
+                        self.expect_punct("]")?;
+                    }
+                    Ok(Expr::Access(name, subs, token.span))
+                } else if self.eat_punct("(") {
+                    let mut args = vec![];
+                    if !self.eat_punct(")") {
+                        loop {
+                            args.push(self.expr()?);
+                            if self.eat_punct(")") {
+                                break;
+                            }
+                            self.expect_punct(",")?;
+                        }
+                    }
+                    Ok(Expr::Call(name, args, token.span))
+                } else {
+                    Ok(Expr::Var(name, token.span))
+                }
+            }
+            Tok::Punct("(") => {
+                let e = self.expr()?;
+                self.expect_punct(")")?;
+                Ok(e)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("an expression"))
+            }
+        }
+    }
+
+    fn constant(&self, e: &Expr) -> Result<i32> {
+        let lin = self.affine_in(e, &[])?;
+        Ok(lin.constant)
+    }
+
+    /// Converts a loop bound, which besides affine expressions may use `min`, `max`,
+    /// `floord` and `ceild` with constant divisors.
+    fn bound(&self, e: &Expr) -> Result<LoopBound> {
+        match e {
+            Expr::Call(name, args, span) if ["min", "max"].contains(&name.as_str()) => {
+                if args.len() < 2 {
+                    return Err(ParseError::new(
+                        format!("`{}` takes at least two arguments", name),
+                        *span,
+                    ));
+                }
+                let mut bounds = args.iter().map(|a| self.bound(a));
+                let first = bounds.next().unwrap()?;
+                bounds.try_fold(first, |acc, b| {
+                    Ok(if name == "min" {
+                        acc.min(b?)
+                    } else {
+                        acc.max(b?)
+                    })
+                })
+            }
+            Expr::Call(name, args, span) if ["floord", "ceild"].contains(&name.as_str()) => {
+                let [x, k] = &args[..] else {
+                    return Err(ParseError::new(
+                        format!("`{}` takes two arguments", name),
+                        *span,
+                    ));
+                };
+                let k = self.affine(k)?;
+                if !k.is_constant() || k.constant <= 0 {
+                    return Err(ParseError::new(
+                        format!("the divisor of `{}` must be a positive constant", name),
+                        *span,
+                    ));
+                }
+                let x = self.bound(x)?;
+                Ok(if name == "floord" {
+                    x.floor_div(k.constant)
+                } else {
+                    x.ceil_div(k.constant)
+                })
+            }
+            Expr::Bin(op, l, r, span) if ["+", "-", "*"].contains(op) && contains_call(e) => {
+                let (l, r) = (self.bound(l)?, self.bound(r)?);
+                let bound = match *op {
+                    "+" => l + r,
+                    "-" => l - r,
+                    _ => l * r,
+                };
+                if !bound.is_symbolic() {
+                    return Err(ParseError::new(
+                        format!("bound `{}` is not a min/max of affine expressions", e),
+                        *span,
+                    ));
+                }
+                Ok(bound)
+            }
+            Expr::Neg(x, _) if contains_call(x) => Ok(LoopBound::Fixed(-1) * self.bound(x)?),
+            _ => Ok(self.affine(e)?.into()),
+        }
+    }
+
+    fn affine(&self, e: &Expr) -> Result<AffineExpr> {
+        self.affine_in(e, &self.ivs)
+    }
+
+    /// Converts `e` into an affine function of `ivs`, folding in the size parameters.
+    fn affine_in(&self, e: &Expr, ivs: &[String]) -> Result<AffineExpr> {
+        match e {
+            Expr::Int(v, _) => Ok(AffineExpr::constant(*v)),
+            Expr::Var(name, span) => {
+                if let Some(pos) = ivs.iter().position(|iv| iv == name) {
+                    Ok(AffineExpr::var(pos))
+                } else if let Some(&value) = self.params.get(name) {
+                    Ok(AffineExpr::constant(value))
+                } else {
+                    Err(ParseError::new(
+                        format!(
+                            "`{}` is neither an enclosing loop index nor a parameter",
+                            name
+                        ),
+                        *span,
+                    ))
+                }
+            }
+            Expr::Neg(x, span) => self
+                .affine_in(x, ivs)?
+                .checked_mul(-1)
+                .ok_or_else(|| overflow(e, *span)),
+            Expr::Bin(op, l, r, span) => {
+                let (l, r) = (self.affine_in(l, ivs)?, self.affine_in(r, ivs)?);
+                let value = match *op {
+                    "+" => l.checked_add(&r),
+                    "-" => l.checked_sub(&r),
+                    "*" if r.is_constant() => l.checked_mul(r.constant),
+                    "*" if l.is_constant() => r.checked_mul(l.constant),
+                    "/" | "%" if l.is_constant() && r.is_constant() => {
+                        if r.constant == 0 {
+                            return Err(ParseError::new("division by zero", *span));
+                        }
+                        let value = if *op == "/" {
+                            l.constant.checked_div(r.constant)
+                        } else {
+                            l.constant.checked_rem(r.constant)
+                        };
+                        value.map(AffineExpr::constant)
+                    }
+                    _ => {
+                        return Err(ParseError::new(
+                            format!("expression `{}` is not affine", e),
+                            *span,
+                        ))
+                    }
+                };
+                value.ok_or_else(|| overflow(e, *span))
+            }
+            Expr::Float(span) | Expr::Access(_, _, span) | Expr::Call(_, _, span) => Err(
+                ParseError::new(format!("expression `{}` is not affine", e), *span),
+            ),
+        }
+    }
+
+    /// Collects a reference node for every array access in `e`, in evaluation order.
+    fn collect_refs(&self, e: &Expr, refs: &mut Vec<Rc<Node>>) -> Result<()> {
+        match e {
+            Expr::Access(name, subs, span) => {
+                refs.push(self.make_ref(name, subs, *span, AccessKind::Read)?)
+            }
+            Expr::Call(_, args, _) => {
+                for arg in args {
+                    self.collect_refs(arg, refs)?;
+                }
+            }
+            Expr::Neg(e, _) => self.collect_refs(e, refs)?,
+            Expr::Bin(_, l, r, _) => {
+                self.collect_refs(l, refs)?;
+                self.collect_refs(r, refs)?;
+            }
+            Expr::Int(..) | Expr::Float(_) | Expr::Var(..) => {}
+        }
+        Ok(())
+    }
+
+    fn make_ref(
+        &self,
+        name: &str,
+        subs: &[Expr],
+        span: Span,
+        kind: AccessKind,
+    ) -> Result<Rc<Node>> {
+        let (dim, elem) = self
+            .arrays
+            .get(name)
+            .ok_or_else(|| ParseError::new(format!("array `{}` is not declared", name), span))?;
+        if dim.len() != subs.len() {
+            return Err(ParseError::new(
+                format!(
+                    "array `{}` has {} dimension(s) but is accessed with {} subscript(s)",
+                    name,
+                    dim.len(),
+                    subs.len()
+                ),
+                span,
+            ));
+        }
+        let sub_affine = subs
+            .iter()
+            .map(|s| self.affine(s))
+            .collect::<Result<Vec<_>>>()?;
+        let ref_stmt = AryRef {
+            name: name.to_string(),
+            dim: dim.clone(),
+            indices: subs.iter().map(|s| s.to_string()).collect(),
+            sub: compile_sub(&sub_affine),
+            sub_affine: Some(sub_affine),
+            ri: vec![],
+            kind,
+            elem: Some(*elem),
+            layout: Layout::RowMajor,
+        };
+        Ok(Node::new_node(Stmt::Ref(ref_stmt)))
+    }
+}
+
+fn is_operator(p: &str) -> bool {
+    Cmp::from_op(p).is_some() || ["+", "-", "*", "/", "%"].contains(&p)
+}
+
+fn block_of(mut nodes: Vec<Rc<Node>>) -> Rc<Node> {
+    if nodes.len() == 1 {
+        nodes.pop().unwrap()
+    } else {
+        Node::new_node(Stmt::Block(nodes))
+    }
+}
+
+/// Parses a kernel and builds its loop tree.
+///
+/// `params` binds size parameters by name; a binding overrides the default value given in a
+/// `param N = ...;` declaration. A program with several top-level statements is wrapped in a
+/// `Stmt::Block`.
+pub fn parse(src: &str, params: &[(&str, i32)]) -> std::result::Result<Rc<Node>, ParseError> {
+    let mut parser = Parser {
+        tokens: lex(src)?,
+        pos: 0,
+        params: params
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value))
+            .collect(),
+        arrays: HashMap::new(),
+        ivs: vec![],
+    };
+    Ok(block_of(parser.program()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+
+    fn refs_of(node: &Rc<Node>) -> Vec<Rc<Node>> {
+        crate::iter::Walk::new(node)
+            .filter(|n| matches!(n.stmt, Stmt::Ref(_)))
+            .collect()
+    }
+
+    #[test]
+    fn gemm() {
+        let code = parse(
+            "param N = 8;
+             double A[N][N]; double B[N][N]; double C[N][N];
+             for (i = 0; i < N; i++)
+               for (j = 0; j < N; j++) {
+                 C[i][j] *= beta;
+                 for (k = 0; k < N; k++)
+                   C[i][j] += alpha * A[i][k] * B[k][j];
+               }",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(code.node_count(), 9);
+
+        let names: Vec<String> = refs_of(&code)
+            .iter()
+            .map(|n| n.ref_only(|r| r.name.clone()).unwrap())
+            .collect();
+        assert_eq!(names, ["C", "C", "A", "B", "C", "C"]);
+        let kinds: Vec<AccessKind> = refs_of(&code)
+            .iter()
+            .map(|n| n.ref_only(|r| r.kind).unwrap())
+            .collect();
+        use AccessKind::{Read, Write};
+        assert_eq!(kinds, [Read, Write, Read, Read, Read, Write]);
+
+        let b = &refs_of(&code)[3];
+        b.ref_only(|r| {
+            assert_eq!((r.sub)(&[1, 2, 3]), [3, 2]);
+            assert_eq!(r.indices, ["k", "j"]);
+            assert_eq!(
+                r.sub_affine.as_deref(),
+                Some(&[AffineExpr::var(2), AffineExpr::var(1)][..])
+            );
+        });
+        let program = Program::new(&code);
+        assert_eq!(
+            program.base(program.refs().nth(3).unwrap()),
+            Some(64 * 2 * 8)
+        );
+    }
+
+    #[test]
+    fn element_types() {
+        let code = parse(
+            "char S[3]; double D[2]; int I[2];
+             for (i = 0; i < 2; i++) D[i] = S[i] + I[i];",
+            &[],
+        )
+        .unwrap();
+        let program = Program::new(&code);
+        let layout: Vec<(Option<usize>, Option<usize>)> = program
+            .refs()
+            .map(|r| (program.base(r), program.elem_size(r)))
+            .collect();
+        // arrays are placed in the order of their first reference, S, I, D, each aligned to
+        // its element size
+        assert_eq!(
+            layout,
+            [(Some(0), Some(1)), (Some(4), Some(4)), (Some(16), Some(8))]
+        );
+        assert_eq!(program.data_size(), 32);
+    }
+
+    #[test]
+    fn params_override_defaults() {
+        let src = "param N = 4; double A[N]; for (i = 0; i < N; i++) A[i] = 0;";
+        let small = parse(src, &[]).unwrap();
+        let large = parse(src, &[("N", 100)]).unwrap();
+        assert_eq!(
+            small.loop_only(|lp| format!("{:?}", lp.ub)).unwrap(),
+            "Fixed(4)"
+        );
+        assert_eq!(
+            large.loop_only(|lp| format!("{:?}", lp.ub)).unwrap(),
+            "Fixed(100)"
+        );
+    }
+
+    #[test]
+    fn affine_bounds_and_subscripts() {
+        let code = parse(
+            "param N; double A[N][N];
+             for (i = 1; i <= N - 1; i += 2)
+               for (j = i + 1; j < 2 * i + N; j++)
+                 A[i - 1][2*j - i] = 0;",
+            &[("N", 10)],
+        )
+        .unwrap();
+        code.loop_only(|lp| {
+            assert_eq!(format!("{:?}", lp.lb), "Fixed(1)");
+            assert_eq!(format!("{:?}", lp.ub), "Fixed(9)");
+            assert!((lp.test)(9, 9));
+            assert_eq!((lp.step)(1), 3);
+            lp.body.borrow()[0].loop_only(|inner| {
+                assert_eq!(format!("{:?}", inner.lb), "Affine([1], 1)");
+                assert_eq!(format!("{:?}", inner.ub), "Affine([2], 10)");
+                inner.body.borrow()[0].ref_only(|r| assert_eq!((r.sub)(&[3, 5]), [2, 7]));
+            });
+        });
+    }
+
+    #[test]
+    fn tiled_bounds() {
+        let code = parse(
+            "param N = 10; param T = 4; double A[N];
+             for (t = 0; t <= floord(N - 1, T); t++)
+               for (i = T * t; i < min(T * t + T, N); i++)
+                 A[i];",
+            &[],
+        )
+        .unwrap();
+        code.loop_only(|lp| {
+            assert_eq!(format!("{:?}", lp.ub), "Fixed(2)");
+            lp.body.borrow()[0].loop_only(|inner| {
+                assert!(inner.ub.is_symbolic());
+                assert_eq!(inner.ub.to_string_with(&["t".into()]), "min(4*t+4, 10)");
+                assert_eq!(inner.ub.eval(&[1]), 8);
+                assert_eq!(inner.ub.eval(&[2]), 10);
+            });
+        });
+        assert!(parse("double A[4]; for (i = 0; i < min(4); i++) A[i];", &[]).is_err());
+        assert!(parse("double A[4]; for (i = 0; i < floord(4, 0); i++) A[i];", &[]).is_err());
+    }
+
+    #[test]
+    fn branches() {
+        let code = parse(
+            "param N = 10; double A[N]; double B[N];
+             B[0];
+             for (i = 0; i < N; i++)
+               if ((i + 1) * 2 > 2 && !(i >= 5) || i == 9) { A[i] = B[i]; } else B[i];",
+            &[],
+        )
+        .unwrap();
+        let Stmt::Block(top) = &code.stmt else {
+            panic!("expected a block");
+        };
+        top[1].loop_only(|lp| {
+            let Stmt::Branch(branch) = &lp.body.borrow()[0].stmt else {
+                panic!("expected a branch");
+            };
+            let taken: Vec<i32> = (0..10).filter(|&i| (branch.cond)(&[i])).collect();
+            assert_eq!(taken, [1, 2, 3, 4, 9]);
+            assert_eq!(branch.then_body.node_count(), 3);
+            assert_eq!(branch.else_body.as_ref().unwrap().node_count(), 1);
+        });
+    }
+
+    #[test]
+    fn errors_carry_spans() {
+        let err = |src: &str| parse(src, &[("N", 4)]).unwrap_err();
+
+        let e = err("double A[N];\nfor (i = 0; i < N; i++)\n  A[i*i] = 0;");
+        assert_eq!(e.span, Span { line: 3, column: 6 });
+        assert!(e.message.contains("not affine"), "{}", e);
+
+        let e = err("for (i = 0; i < N; i++) B[i] = 0;");
+        assert_eq!(
+            e.span,
+            Span {
+                line: 1,
+                column: 25
+            }
+        );
+        assert_eq!(e.to_string(), "1:25: array `B` is not declared");
+
+        let e = err("double A[N][N];\nfor (i = 0; i < N; i++) A[i] = 0;");
+        assert!(e.message.contains("2 dimension(s)"), "{}", e);
+
+        let e = err("double A[N];\nfor (i = 0; i < N; i++) A[j] = 0;");
+        assert_eq!(
+            e.span,
+            Span {
+                line: 2,
+                column: 27
+            }
+        );
+
+        let e = err("param M; double A[M];");
+        assert_eq!(e.span, Span { line: 1, column: 7 });
+
+        let e = err("double A[N];\nfor (i = 0; i < N; i++ {\n}");
+        assert_eq!(
+            e.span,
+            Span {
+                line: 2,
+                column: 24
+            }
+        );
+
+        let e = err("double A[N]; for (i = 0; j < N; i++) A[i];");
+        assert!(e.message.contains("compare the loop index"), "{}", e);
+
+        let e = err("double A[N]; /* unterminated");
+        assert_eq!(
+            e.span,
+            Span {
+                line: 1,
+                column: 14
+            }
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let err = |src: &str| parse(src, &[]).unwrap_err();
+
+        let e = err("param N = 2147483647; double A[10]; for (i = 0; i < N + 1; i++) A[0] = 0;");
+        assert_eq!(e.to_string(), "1:55: expression `N+1` overflows");
+        let e = err("double A[10]; for (i = 0; i < 100000 * 100000; i++) A[0] = 0;");
+        assert_eq!(
+            e.span,
+            Span {
+                line: 1,
+                column: 38
+            }
+        );
+        assert!(e.message.contains("overflows"), "{}", e);
+        let e = err("double A[10]; for (i = 0; i < (-2147483647 - 1) / -1; i++) A[0] = 0;");
+        assert!(e.message.contains("overflows"), "{}", e);
+        let e = err("double A[10]; for (i = 0; i < (-2147483647 - 1) % -1; i++) A[0] = 0;");
+        assert!(e.message.contains("overflows"), "{}", e);
+        let e = err("double A[10]; for (i = 0; i < -(-2147483647 - 1); i++) A[0] = 0;");
+        assert_eq!(
+            e.span,
+            Span {
+                line: 1,
+                column: 31
+            }
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::arybase::{elem_type, set_arybase, Placement, DEFAULT_ELEM_SIZE};
+use crate::ast::{AryRef, LoopStmt, Node, Stmt};
+use crate::serial::{self, SerialError};
+
+/// Handle of one site in a `Program`.
+///
+/// A node shared by several parents (e.g. a reference `clone`d into two loops) occupies one
+/// site per occurrence, each with its own id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Position of the site in pre-order.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+struct Site {
+    node: Rc<Node>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+/// A loop tree flattened into an arena, with the per-site facts the analyses need kept in
+/// side tables instead of inside the (shared, immutable) nodes.
+///
+/// Sites are numbered in pre-order, so the root is always the first one.
+/// - base: start byte address of the array a reference accesses, laid out by `set_arybase`.
+/// - element size: bytes per element of that array.
+/// - ref id: the reference sites numbered in pre-order, starting at 0.
+/// - rank: the nesting depth of a loop, 0 for the outermost.
+pub struct Program {
+    sites: Vec<Site>,
+    bases: Vec<Option<usize>>,
+    elem_sizes: Vec<Option<usize>>,
+    ref_ids: Vec<Option<usize>>,
+    ranks: Vec<Option<i32>>,
+    ary_bases: HashMap<String, usize>,
+    data_size: usize,
+    default_elem_size: usize,
+}
+
+impl Program {
+    /// Lays out the arrays with `DEFAULT_ELEM_SIZE` bytes per element unless they declare
+    /// their own element type.
+    pub fn new(root: &Rc<Node>) -> Program {
+        Program::with_elem_size(root, DEFAULT_ELEM_SIZE)
+    }
+
+    /// Lays out the arrays with `elem_size` bytes per element unless they declare their own
+    /// element type.
+    pub fn with_elem_size(root: &Rc<Node>, elem_size: usize) -> Program {
+        Program::with_placement(root, elem_size, &Placement::Packed)
+    }
+
+    /// Like `with_elem_size`, with the arrays placed by `placement` rather than packed.
+    pub fn with_placement(root: &Rc<Node>, elem_size: usize, placement: &Placement) -> Program {
+        let (ary_bases, data_size) = set_arybase(root, elem_size, placement);
+        let mut program = Program {
+            sites: vec![],
+            bases: vec![],
+            elem_sizes: vec![],
+            ref_ids: vec![],
+            ranks: vec![],
+            ary_bases,
+            data_size,
+            default_elem_size: elem_size,
+        };
+        program.add_site(root, None, 0, &mut 0);
+        program
+    }
+
+    /// Reads a program saved by `save`, or written by another tool in the format of
+    /// `serial`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Program, SerialError> {
+        serial::from_str(&fs::read_to_string(path)?)
+    }
+
+    /// Writes the program to a file in the format of `serial`.
+    ///
+    /// # Errors
+    /// Besides I/O errors, if a subscript, bound, loop test or step, or branch condition of
+    /// the program is known only as a closure.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SerialError> {
+        fs::write(path, serial::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn add_site(
+        &mut self,
+        node: &Rc<Node>,
+        parent: Option<NodeId>,
+        rank: i32,
+        ref_counter: &mut usize,
+    ) -> NodeId {
+        let id = NodeId(self.sites.len());
+        self.sites.push(Site {
+            node: Rc::clone(node),
+            parent,
+            children: vec![],
+        });
+        let (base, elem_size, ref_id, loop_rank) = match &node.stmt {
+            Stmt::Ref(aref) => {
+                *ref_counter += 1;
+                (
+                    self.ary_bases.get(&aref.name).copied(),
+                    Some(elem_type(aref, self.default_elem_size).size),
+                    Some(*ref_counter - 1),
+                    None,
+                )
+            }
+            Stmt::Loop(_) => (None, None, None, Some(rank)),
+            _ => (None, None, None, None),
+        };
+        self.bases.push(base);
+        self.elem_sizes.push(elem_size);
+        self.ref_ids.push(ref_id);
+        self.ranks.push(loop_rank);
+
+        let children: Vec<NodeId> = match &node.stmt {
+            Stmt::Loop(aloop) => aloop
+                .body
+                .borrow()
+                .iter()
+                .map(|child| self.add_site(child, Some(id), rank + 1, ref_counter))
+                .collect(),
+            Stmt::Block(blk) => blk
+                .iter()
+                .map(|child| self.add_site(child, Some(id), rank, ref_counter))
+                .collect(),
+            Stmt::Branch(branch) => std::iter::once(&branch.then_body)
+                .chain(branch.else_body.as_ref())
+                .map(|child| self.add_site(child, Some(id), rank, ref_counter))
+                .collect(),
+            Stmt::Ref(_) => vec![],
+        };
+        self.sites[id.0].children = children;
+        id
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// The tree the program was built from.
+    pub fn root_node(&self) -> &Rc<Node> {
+        &self.sites[0].node
+    }
+
+    pub fn node(&self, id: NodeId) -> &Rc<Node> {
+        &self.sites[id.0].node
+    }
+
+    pub fn stmt(&self, id: NodeId) -> &Stmt {
+        &self.sites[id.0].node.stmt
+    }
+
+    /// The children of a site: the body of a loop or block, or the then and (optional) else
+    /// parts of a branch.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.sites[id.0].children
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.sites[id.0].parent
+    }
+
+    pub fn loop_stmt(&self, id: NodeId) -> Option<&LoopStmt> {
+        match self.stmt(id) {
+            Stmt::Loop(aloop) => Some(aloop),
+            _ => None,
+        }
+    }
+
+    pub fn ary_ref(&self, id: NodeId) -> Option<&AryRef> {
+        match self.stmt(id) {
+            Stmt::Ref(aref) => Some(aref),
+            _ => None,
+        }
+    }
+
+    /// Base byte address of the array accessed at a reference site.
+    pub fn base(&self, id: NodeId) -> Option<usize> {
+        self.bases[id.0]
+    }
+
+    /// Bytes per element of the arrays without an element type of their own.
+    pub fn default_elem_size(&self) -> usize {
+        self.default_elem_size
+    }
+
+    /// Bytes per element of the array accessed at a reference site.
+    pub fn elem_size(&self, id: NodeId) -> Option<usize> {
+        self.elem_sizes[id.0]
+    }
+
+    pub fn ref_id(&self, id: NodeId) -> Option<usize> {
+        self.ref_ids[id.0]
+    }
+
+    pub fn rank(&self, id: NodeId) -> Option<i32> {
+        self.ranks[id.0]
+    }
+
+    /// Base byte address of every array, as assigned by `set_arybase`.
+    pub fn ary_bases(&self) -> &HashMap<String, usize> {
+        &self.ary_bases
+    }
+
+    /// Number of bytes from address 0 to the end of the last array, including alignment and
+    /// padding.
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    /// Number of sites.
+    pub fn len(&self) -> usize {
+        self.sites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    /// All sites in pre-order.
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> {
+        (0..self.sites.len()).map(NodeId)
+    }
+
+    /// The reference sites in pre-order, i.e. by increasing ref id.
+    pub fn refs(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.ids().filter(|&id| self.ref_ids[id.0].is_some())
+    }
+
+    /// A site and everything beneath it, in pre-order.
+    pub fn subtree(&self, id: NodeId) -> Vec<NodeId> {
+        let mut ids = vec![];
+        let mut stack = vec![id];
+        while let Some(cur) = stack.pop() {
+            ids.push(cur);
+            stack.extend(self.children(cur).iter().rev());
+        }
+        ids
+    }
+
+    /// The loops enclosing a site, outermost first.
+    pub fn enclosing_loops(&self, id: NodeId) -> Vec<NodeId> {
+        let mut loops = vec![];
+        let mut cur = self.parent(id);
+        while let Some(p) = cur {
+            if self.loop_stmt(p).is_some() {
+                loops.push(p);
+            }
+            cur = self.parent(p);
+        }
+        loops.reverse();
+        loops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::construct;
+
+    #[test]
+    fn shared_ref_sites() {
+        // i { A[i]; j { A[i]; B[j] } } with the same A node at both places
+        let mut ref_a = Node::new_ref("A", vec![10], |iv| vec![iv[0] as usize]);
+        let mut ref_b = Node::new_ref("B", vec![10], |iv| vec![iv[1] as usize]);
+        let mut iloop = Node::new_single_loop("i", 0, 10);
+        let mut jloop = Node::new_single_loop("j", 0, 10);
+        Node::extend_loop_body(&mut iloop, &mut ref_a);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        Node::extend_loop_body(&mut jloop, &mut ref_a.clone());
+        Node::extend_loop_body(&mut jloop, &mut ref_b);
+
+        let program = Program::new(&iloop);
+        assert_eq!(program.len(), 5);
+        let refs: Vec<NodeId> = program.refs().collect();
+        assert_eq!(refs.len(), 3);
+        assert!(Rc::ptr_eq(program.node(refs[0]), program.node(refs[1])));
+        assert_eq!(
+            refs.iter().map(|&r| program.ref_id(r)).collect::<Vec<_>>(),
+            [Some(0), Some(1), Some(2)]
+        );
+        assert_eq!(
+            refs.iter().map(|&r| program.base(r)).collect::<Vec<_>>(),
+            [Some(0), Some(0), Some(80)]
+        );
+        assert_eq!(program.parent(refs[0]), Some(program.root()));
+        assert_eq!(program.enclosing_loops(refs[1]).len(), 2);
+        assert_eq!(program.rank(program.root()), Some(0));
+        assert_eq!(program.rank(program.children(program.root())[1]), Some(1));
+        assert_eq!(program.ary_bases().get("B"), Some(&80));
+        assert_eq!(program.data_size(), 160);
+        let program = Program::with_elem_size(&iloop, 4);
+        assert_eq!(program.ary_bases().get("B"), Some(&40));
+        assert_eq!(program.elem_size(refs[2]), Some(4));
+    }
+
+    #[test]
+    fn ranks() {
+        let mut nest = construct::nested_loops(&["i", "j", "k"], 4);
+        let mut aref = construct::a_ref("A", vec![4, 4], vec!["i", "k"]);
+        construct::insert_at(&mut aref, &mut nest, "k");
+        let program = Program::new(&nest);
+        let ranks: Vec<Option<i32>> = program.ids().map(|id| program.rank(id)).collect();
+        assert_eq!(ranks, [Some(0), Some(1), Some(2), None]);
+        assert_eq!(program.ref_id(NodeId(3)), Some(0));
+    }
+}
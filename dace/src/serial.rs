@@ -0,0 +1,486 @@
+//! A file format for programs, so that kernels can be saved, and generated by other tools,
+//! instead of being rebuilt in Rust code on every run.
+//!
+//! A program is stored as JSON (see `ProgramFile` for the schema) with the symbolic forms
+//! of its subscripts, bounds, loop tests and steps, and branch conditions, from which the
+//! closures are compiled again on loading. A program with a part known only as a closure
+//! can't be saved. The base of every array is stored too, so a loaded program has the same
+//! addresses as the saved one.
+//!
+//! `Program` implements `Serialize` and `Deserialize` through this format, and
+//! `Program::save` and `Program::load` read and write files in it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::affine::{compile_sub, AffineExpr};
+use crate::arybase::Placement;
+use crate::ast::{AccessKind, AryRef, Cmp, ElemType, LoopBound, Node, Predicate, Stmt};
+use crate::layout::Layout;
+use crate::program::Program;
+use crate::validate::{validate, Severity};
+
+/// The version written by `to_string`. Files of other versions are refused.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SerialError {
+    Io(std::io::Error),
+    /// The text is not JSON, or not a program in this format.
+    Json(serde_json::Error),
+    /// The program can't be saved, or the file does not describe a valid program.
+    Program(String),
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialError::Io(err) => write!(f, "{}", err),
+            SerialError::Json(err) => write!(f, "{}", err),
+            SerialError::Program(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SerialError {}
+
+impl From<std::io::Error> for SerialError {
+    fn from(err: std::io::Error) -> Self {
+        SerialError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SerialError {
+    fn from(err: serde_json::Error) -> Self {
+        SerialError::Json(err)
+    }
+}
+
+type Result<T> = std::result::Result<T, SerialError>;
+
+/// The stored form of a `Program`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgramFile {
+    pub version: u32,
+    /// Bytes per element of the arrays without an element type, see `Program::with_elem_size`.
+    pub elem_size: usize,
+    /// Base byte address of each array. Arrays left out are packed after the others, see
+    /// `Placement::Explicit`.
+    #[serde(default)]
+    pub bases: BTreeMap<String, usize>,
+    pub root: NodeFile,
+}
+
+/// The stored form of a `Node`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeFile {
+    /// `for (iv = lb; iv test ub; iv += step)`
+    Loop {
+        iv: String,
+        lb: BoundFile,
+        ub: BoundFile,
+        test: Cmp,
+        step: i32,
+        body: Vec<NodeFile>,
+    },
+    Ref {
+        array: String,
+        dim: Vec<usize>,
+        /// One affine expression per dimension.
+        subscripts: Vec<AffineExpr>,
+        /// The text of the subscripts, see `AryRef::indices`.
+        #[serde(default)]
+        indices: Vec<String>,
+        #[serde(default)]
+        kind: AccessKind,
+        #[serde(default)]
+        elem: Option<ElemType>,
+        #[serde(default)]
+        layout: Layout,
+    },
+    Block {
+        body: Vec<NodeFile>,
+    },
+    Branch {
+        cond: PredicateFile,
+        then_body: Box<NodeFile>,
+        else_body: Option<Box<NodeFile>>,
+    },
+}
+
+/// The stored form of a symbolic `LoopBound`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundFile {
+    Affine(AffineExpr),
+    Min(Box<BoundFile>, Box<BoundFile>),
+    Max(Box<BoundFile>, Box<BoundFile>),
+    FloorDiv(Box<BoundFile>, i32),
+    CeilDiv(Box<BoundFile>, i32),
+}
+
+/// The stored form of a `Predicate`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PredicateFile {
+    /// `expr op 0`
+    Cmp {
+        op: Cmp,
+        expr: AffineExpr,
+    },
+    And(Box<PredicateFile>, Box<PredicateFile>),
+    Or(Box<PredicateFile>, Box<PredicateFile>),
+    Not(Box<PredicateFile>),
+}
+
+impl ProgramFile {
+    pub fn new(program: &Program) -> Result<Self> {
+        Ok(ProgramFile {
+            version: FORMAT_VERSION,
+            elem_size: program.default_elem_size(),
+            bases: program
+                .ary_bases()
+                .iter()
+                .map(|(name, &base)| (name.clone(), base))
+                .collect(),
+            root: NodeFile::new(program.root_node(), &mut vec![])?,
+        })
+    }
+
+    /// Builds the program, after checking the version and that it passes `validate`.
+    pub fn build(&self) -> Result<Program> {
+        if self.version != FORMAT_VERSION {
+            return Err(SerialError::Program(format!(
+                "format version {} is not supported, only {}",
+                self.version, FORMAT_VERSION
+            )));
+        }
+        let root = self.root.build()?;
+        if let Some(diag) = validate(&root)
+            .into_iter()
+            .find(|d| d.severity == Severity::Error)
+        {
+            return Err(SerialError::Program(diag.to_string()));
+        }
+        let bases: HashMap<String, usize> = self.bases.clone().into_iter().collect();
+        Ok(Program::with_placement(
+            &root,
+            self.elem_size,
+            &Placement::Explicit(bases),
+        ))
+    }
+}
+
+impl NodeFile {
+    /// `ivs` holds the indices of the loops enclosing `node`, outermost first, to say where
+    /// a closure is.
+    fn new(node: &Node, ivs: &mut Vec<String>) -> Result<Self> {
+        let unsaved = |ivs: &[String], what: String| {
+            let place = match ivs.is_empty() {
+                true => String::new(),
+                false => format!("in loop {}: ", ivs.join(" > ")),
+            };
+            SerialError::Program(format!("{}{} has no symbolic form", place, what))
+        };
+        Ok(match &node.stmt {
+            Stmt::Loop(aloop) => {
+                let (Some(test), Some(step)) = (aloop.cmp, aloop.stride) else {
+                    return Err(unsaved(
+                        ivs,
+                        format!("the test or step of loop `{}`", aloop.iv),
+                    ));
+                };
+                let bound = |b| {
+                    BoundFile::new(b)
+                        .ok_or_else(|| unsaved(ivs, format!("a bound of loop `{}`", aloop.iv)))
+                };
+                let (lb, ub) = (bound(&aloop.lb)?, bound(&aloop.ub)?);
+                ivs.push(aloop.iv.clone());
+                let body = aloop
+                    .body
+                    .borrow()
+                    .iter()
+                    .map(|child| NodeFile::new(child, ivs))
+                    .collect::<Result<_>>();
+                ivs.pop();
+                NodeFile::Loop {
+                    iv: aloop.iv.clone(),
+                    lb,
+                    ub,
+                    test,
+                    step,
+                    body: body?,
+                }
+            }
+            Stmt::Ref(aref) => NodeFile::Ref {
+                array: aref.name.clone(),
+                dim: aref.dim.clone(),
+                subscripts: aref
+                    .sub_affine
+                    .clone()
+                    .ok_or_else(|| unsaved(ivs, format!("a subscript of `{}`", aref.name)))?,
+                indices: aref.indices.clone(),
+                kind: aref.kind,
+                elem: aref.elem,
+                layout: aref.layout.clone(),
+            },
+            Stmt::Block(children) => NodeFile::Block {
+                body: children
+                    .iter()
+                    .map(|child| NodeFile::new(child, ivs))
+                    .collect::<Result<_>>()?,
+            },
+            Stmt::Branch(branch) => NodeFile::Branch {
+                cond: branch
+                    .pred
+                    .as_ref()
+                    .map(PredicateFile::new)
+                    .ok_or_else(|| unsaved(ivs, "a branch condition".to_string()))?,
+                then_body: Box::new(NodeFile::new(&branch.then_body, ivs)?),
+                else_body: match &branch.else_body {
+                    Some(body) => Some(Box::new(NodeFile::new(body, ivs)?)),
+                    None => None,
+                },
+            },
+        })
+    }
+
+    fn build(&self) -> Result<Rc<Node>> {
+        Ok(match self {
+            NodeFile::Loop {
+                iv,
+                lb,
+                ub,
+                test,
+                step,
+                body,
+            } => {
+                if *step == 0 {
+                    return Err(SerialError::Program(format!(
+                        "loop `{}` has a step of zero",
+                        iv
+                    )));
+                }
+                let mut aloop = Node::new_strided_loop(iv, lb.build()?, ub.build()?, *test, *step);
+                for child in body {
+                    Node::extend_loop_body(&mut aloop, &mut child.build()?);
+                }
+                aloop
+            }
+            NodeFile::Ref {
+                array,
+                dim,
+                subscripts,
+                indices,
+                kind,
+                elem,
+                layout,
+            } => Node::new_node(Stmt::Ref(AryRef {
+                name: array.clone(),
+                dim: dim.clone(),
+                indices: indices.clone(),
+                sub: compile_sub(subscripts),
+                sub_affine: Some(subscripts.clone()),
+                ri: vec![],
+                kind: *kind,
+                elem: *elem,
+                layout: layout.clone(),
+            })),
+            NodeFile::Block { body } => Node::new_node(Stmt::Block(
+                body.iter()
+                    .map(|child| child.build())
+                    .collect::<Result<_>>()?,
+            )),
+            NodeFile::Branch {
+                cond,
+                then_body,
+                else_body,
+            } => Node::new_branch(
+                cond.build()?,
+                then_body.build()?,
+                match else_body {
+                    Some(body) => Some(body.build()?),
+                    None => None,
+                },
+            ),
+        })
+    }
+}
+
+impl BoundFile {
+    /// `None` if the bound has a `Dynamic` part.
+    fn new(bound: &LoopBound) -> Option<Self> {
+        let boxed = |b: &LoopBound| BoundFile::new(b).map(Box::new);
+        Some(match bound {
+            LoopBound::Fixed(_) | LoopBound::Affine { .. } => {
+                BoundFile::Affine(bound.as_affine().unwrap())
+            }
+            LoopBound::Dynamic(_) => return None,
+            LoopBound::Min(x, y) => BoundFile::Min(boxed(x)?, boxed(y)?),
+            LoopBound::Max(x, y) => BoundFile::Max(boxed(x)?, boxed(y)?),
+            LoopBound::FloorDiv(x, k) => BoundFile::FloorDiv(boxed(x)?, *k),
+            LoopBound::CeilDiv(x, k) => BoundFile::CeilDiv(boxed(x)?, *k),
+        })
+    }
+
+    fn build(&self) -> Result<LoopBound> {
+        let divisor = |k: i32| match k {
+            0 => Err(SerialError::Program(
+                "a loop bound is divided by zero".to_string(),
+            )),
+            _ => Ok(k),
+        };
+        Ok(match self {
+            BoundFile::Affine(e) => e.clone().into(),
+            BoundFile::Min(x, y) => LoopBound::Min(Box::new(x.build()?), Box::new(y.build()?)),
+            BoundFile::Max(x, y) => LoopBound::Max(Box::new(x.build()?), Box::new(y.build()?)),
+            BoundFile::FloorDiv(x, k) => LoopBound::FloorDiv(Box::new(x.build()?), divisor(*k)?),
+            BoundFile::CeilDiv(x, k) => LoopBound::CeilDiv(Box::new(x.build()?), divisor(*k)?),
+        })
+    }
+}
+
+impl PredicateFile {
+    fn new(pred: &Predicate) -> Self {
+        let boxed = |p: &Predicate| Box::new(PredicateFile::new(p));
+        match pred {
+            Predicate::Cmp(op, expr) => PredicateFile::Cmp {
+                op: *op,
+                expr: expr.clone(),
+            },
+            Predicate::And(a, b) => PredicateFile::And(boxed(a), boxed(b)),
+            Predicate::Or(a, b) => PredicateFile::Or(boxed(a), boxed(b)),
+            Predicate::Not(a) => PredicateFile::Not(boxed(a)),
+        }
+    }
+
+    fn build(&self) -> Result<Predicate> {
+        let boxed = |p: &PredicateFile| p.build().map(Box::new);
+        Ok(match self {
+            PredicateFile::Cmp { op, expr } => Predicate::Cmp(*op, expr.clone()),
+            PredicateFile::And(a, b) => Predicate::And(boxed(a)?, boxed(b)?),
+            PredicateFile::Or(a, b) => Predicate::Or(boxed(a)?, boxed(b)?),
+            PredicateFile::Not(a) => Predicate::Not(boxed(a)?),
+        })
+    }
+}
+
+/// `program` as indented JSON.
+pub fn to_string(program: &Program) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&ProgramFile::new(program)?)?)
+}
+
+/// The program in `text`, as written by `to_string`.
+pub fn from_str(text: &str) -> Result<Program> {
+    serde_json::from_str::<ProgramFile>(text)?.build()
+}
+
+impl Serialize for Program {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        ProgramFile::new(self)
+            .map_err(ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Program {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        ProgramFile::deserialize(deserializer)?
+            .build()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse;
+    use crate::transform::tile;
+
+    #[test]
+    fn round_trip() {
+        let code = parse(
+            "double A[10][10]; int x[10];
+             for (i = 0; i < 10; i++)
+               for (j = 9; j >= i; j -= 2)
+                 if (i == j || !(j > 4)) x[i] += A[i][j]; else A[j][i] = 0;",
+            &[],
+        )
+        .unwrap();
+        let program = tile(&Program::new(&code), &[("i", 3)]).unwrap();
+        let text = to_string(&program).unwrap();
+        let loaded = from_str(&text).unwrap();
+        assert!(loaded.root_node().structural_eq(program.root_node()));
+        assert_eq!(loaded.ary_bases(), program.ary_bases());
+        assert_eq!(loaded.data_size(), program.data_size());
+        assert_eq!(to_string(&loaded).unwrap(), text);
+
+        // the serde impls go through the same format
+        let json = serde_json::to_value(&program).unwrap();
+        assert_eq!(json["version"], FORMAT_VERSION);
+        assert_eq!(json["root"]["type"], "loop");
+        let loaded: Program = serde_json::from_value(json).unwrap();
+        assert!(loaded.root_node().structural_eq(program.root_node()));
+    }
+
+    #[test]
+    fn placement() {
+        let code = parse(
+            "char c[3]; double d[2]; for (i = 0; i < 2; i++) d[i] = c[i];",
+            &[],
+        )
+        .unwrap();
+        let mut bases = HashMap::new();
+        bases.insert("c".to_string(), 64);
+        let program = Program::with_placement(&code, 4, &Placement::Explicit(bases));
+        let loaded = from_str(&to_string(&program).unwrap()).unwrap();
+        assert_eq!(loaded.ary_bases()["c"], 64);
+        assert_eq!(loaded.ary_bases()["d"], program.ary_bases()["d"]);
+        assert_eq!(loaded.default_elem_size(), 4);
+    }
+
+    #[test]
+    fn errors() {
+        let mut aloop = Node::new_single_loop("i", 0, 4);
+        let mut aref = Node::new_ref("A", vec![4], |i| vec![i[0] as usize]);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        let Err(err) = to_string(&Program::new(&aloop)) else {
+            panic!("a closure is saved")
+        };
+        assert_eq!(
+            err.to_string(),
+            "in loop i: a subscript of `A` has no symbolic form"
+        );
+
+        let text = r#"{
+            "version": 1,
+            "elem_size": 8,
+            "root": {
+                "type": "loop", "iv": "i", "test": "<", "step": 1,
+                "lb": {"affine": {"coeffs": [], "constant": 0}},
+                "ub": {"affine": {"coeffs": [], "constant": 4}},
+                "body": [{"type": "ref", "array": "A", "dim": [4],
+                          "subscripts": [{"coeffs": [0, 1], "constant": 0}]}]
+            }
+        }"#;
+        let Err(SerialError::Program(message)) = from_str(text) else {
+            panic!("an invalid program is loaded")
+        };
+        assert!(message.contains("uses the index of loop 1"), "{}", message);
+        let Err(SerialError::Json(_)) = from_str(&text.replace("\"<\"", "\"!\"")) else {
+            panic!("an unknown test is loaded")
+        };
+        let fixed = text.replace("[0, 1]", "[1]");
+        assert_eq!(from_str(&fixed).map(|p| p.len()).unwrap(), 2);
+        let Err(SerialError::Program(message)) =
+            from_str(&fixed.replace("\"version\": 1", "\"version\": 2"))
+        else {
+            panic!("an unknown version is loaded")
+        };
+        assert!(message.starts_with("format version 2"));
+    }
+}
@@ -0,0 +1,1213 @@
+//! Loop transformations.
+//!
+//! A transformation leaves its input untouched and returns a new `Program`, with the arrays
+//! laid out as in the input. Loop indices keep their names; the subscripts, bounds and branch
+//! predicates beneath a transformed loop are rewritten for the new order of the iteration
+//! vector, symbolically when they have an affine form and by wrapping the closure otherwise.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::affine::{compile_sub, AffineExpr};
+use crate::arybase::Placement;
+use crate::ast::{AryRef, BranchStmt, Cmp, LoopBound, LoopStmt, Node, Stmt};
+use crate::deps::{dependences, DepKind, Dependence, Direction};
+use crate::program::{NodeId, Program};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransformError {
+    pub message: String,
+}
+
+impl TransformError {
+    fn new(message: impl Into<String>) -> Self {
+        TransformError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+type Result<T> = std::result::Result<T, TransformError>;
+
+/// Swaps the loop `outer_iv` with the loop `inner_iv` directly inside it.
+///
+/// The two loops must be perfectly nested, i.e. `inner_iv` is the only statement in the body
+/// of `outer_iv`. When the bounds of the inner loop do not depend on the outer index the loops
+/// are simply swapped. A triangular nest, whose inner bounds step by one with the outer index
+/// (or are a max or min of such expressions), is rewritten by projecting its iteration space:
+///
+/// ```text
+/// for (i = 0; i < N; i++)          for (j = 0; j < N - 1; j++)
+///   for (j = 0; j < i; j++)   =>     for (i = j + 1; i < N; i++)
+/// ```
+///
+/// The interchange is refused if it may reverse a dependence between two references to the
+/// same array, at least one of which writes.
+///
+/// # Examples
+/// ```rust
+/// use dace::program::Program;
+/// let code = dace::parse::parse(
+///     "double A[8][8]; for (i = 0; i < 8; i++) for (j = 0; j < i; j++) A[i][j] = 0;",
+///     &[],
+/// )
+/// .unwrap();
+/// let swapped = dace::transform::interchange(&Program::new(&code), "i", "j").unwrap();
+/// let expected = dace::parse::parse(
+///     "double A[8][8]; for (j = 0; j < 7; j++) for (i = j + 1; i < 8; i++) A[i][j] = 0;",
+///     &[],
+/// )
+/// .unwrap();
+/// assert!(swapped.root_node().structural_eq(&expected));
+/// ```
+pub fn interchange(program: &Program, outer_iv: &str, inner_iv: &str) -> Result<Program> {
+    let band = find_band(program, &[outer_iv, inner_iv])?;
+    let (outer, inner) = (band[0], band[1]);
+    let outer_lp = program.loop_stmt(outer).unwrap();
+    let inner_lp = program.loop_stmt(inner).unwrap();
+    let depth = program.enclosing_loops(outer).len();
+    let action = format!("interchanging `{}` and `{}`", outer_iv, inner_iv);
+    check_permutable(program, inner, depth..depth + 2, &action)?;
+    let [inner_lb, inner_ub, outer_lb, outer_ub] = interchanged_bounds(outer_lp, inner_lp, depth)?;
+
+    let edit = |id: NodeId, map: &mut Vec<usize>, depth: usize| {
+        if id != outer {
+            return None;
+        }
+        let mut new_outer = loop_like(inner_lp, inner_lb.clone(), inner_ub.clone());
+        let mut new_inner = loop_like(outer_lp, outer_lb.clone(), outer_ub.clone());
+        // the old outer index moves one level down, the old inner one level up
+        map.extend([depth + 1, depth]);
+        for mut child in copy_sites(program, program.children(inner), map, depth + 2, &no_edit) {
+            Node::extend_loop_body(&mut new_inner, &mut child);
+        }
+        map.truncate(depth);
+        Node::extend_loop_body(&mut new_outer, &mut new_inner);
+        Some(vec![new_outer])
+    };
+    Ok(copy_program(program, &edit))
+}
+
+/// Tiles a band of perfectly nested loops, given outermost first with their tile sizes.
+///
+/// Each loop `i` is strip-mined into a tile loop `ii`, stepping over the range of `i` by the
+/// tile size, and a point loop `i` over one tile, whose upper bound is a `min` with the old one
+/// so the last tile may be partial. The tile loops are moved outside the point loops:
+///
+/// ```text
+/// for (i = 0; i < N; i++)          for (ii = 0; ii < N; ii += 32)
+///   for (j = 0; j < N; j++)   =>     for (jj = 0; jj < N; jj += 32)
+///     ...                              for (i = ii; i < min(ii + 32, N); i++)
+///                                        for (j = jj; j < min(jj + 32, N); j++)
+///                                          ...
+/// ```
+///
+/// The loops must count up by one, and their bounds may depend only on loops outside the
+/// band. Tiling more than one loop reorders iterations like an interchange and is refused
+/// on the same dependences.
+///
+/// # Examples
+/// ```rust
+/// use dace::program::Program;
+/// let gemm = dace::parse::parse(
+///     "param N; double A[N][N]; double B[N][N]; double C[N][N];
+///      for (i = 0; i < N; i++)
+///        for (j = 0; j < N; j++)
+///          for (k = 0; k < N; k++)
+///            C[i][j] += A[i][k] * B[k][j];",
+///     &[("N", 100)],
+/// )
+/// .unwrap();
+/// let tiled = dace::transform::tile(&Program::new(&gemm), &[("i", 32), ("j", 32)]).unwrap();
+/// assert_eq!(tiled.root_node().node_count(), 9);
+/// ```
+pub fn tile(program: &Program, tiles: &[(&str, i32)]) -> Result<Program> {
+    let ivs: Vec<&str> = tiles.iter().map(|&(iv, _)| iv).collect();
+    if ivs.is_empty() {
+        return Err(TransformError::new("no loops to tile"));
+    }
+    let band = find_band(program, &ivs)?;
+    let depth = program.enclosing_loops(band[0]).len();
+    let n = band.len();
+    for (&id, &(iv, size)) in band.iter().zip(tiles) {
+        let lp = program.loop_stmt(id).unwrap();
+        if size < 1 {
+            return Err(TransformError::new(format!(
+                "the tile size of loop `{}` must be positive, not {}",
+                iv, size
+            )));
+        }
+        if lp.stride != Some(1) || !matches!(lp.cmp, Some(Cmp::Lt | Cmp::Le)) {
+            return Err(TransformError::new(format!(
+                "loop `{}` must count up by one with a `<` or `<=` test to be tiled",
+                iv
+            )));
+        }
+        for bound in [&lp.lb, &lp.ub] {
+            let inside = (depth..depth + n).map(|pos| uses_var(bound, pos));
+            match inside.collect::<Option<Vec<bool>>>() {
+                Some(uses) if !uses.contains(&true) => {}
+                // the first loop has no other band loop outside it
+                None if id == band[0] => {}
+                _ => {
+                    return Err(TransformError::new(format!(
+                        "the bounds of loop `{}` must not depend on the other loops tiled with it",
+                        iv
+                    )))
+                }
+            }
+        }
+        let tile_iv = format!("{}{}", iv, iv);
+        if program
+            .ids()
+            .any(|id| program.loop_stmt(id).is_some_and(|lp| lp.iv == tile_iv))
+        {
+            return Err(TransformError::new(format!(
+                "there is already a loop `{}` for the tiles of `{}`",
+                tile_iv, iv
+            )));
+        }
+    }
+    let action = format!(
+        "tiling {}",
+        ivs.iter()
+            .map(|iv| format!("`{}`", iv))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    check_permutable(program, band[n - 1], depth..depth + n, &action)?;
+
+    let edit = |id: NodeId, map: &mut Vec<usize>, depth: usize| {
+        if id != band[0] {
+            return None;
+        }
+        let mut loops = vec![];
+        for (&lp_id, &(iv, size)) in band.iter().zip(tiles) {
+            let lp = program.loop_stmt(lp_id).unwrap();
+            let (lb, ub) = (lp.lb.clone(), lp.ub.clone());
+            loops.push(Node::new_strided_loop(
+                &format!("{}{}", iv, iv),
+                lb,
+                ub,
+                lp.cmp.unwrap(),
+                size,
+            ));
+        }
+        for (k, (&lp_id, &(_, size))) in band.iter().zip(tiles).enumerate() {
+            let lp = program.loop_stmt(lp_id).unwrap();
+            let start = AffineExpr::var(depth + k);
+            // the last index of the tile is start + size - 1
+            let end = if lp.cmp == Some(Cmp::Lt) {
+                size
+            } else {
+                size - 1
+            };
+            let ub = LoopBound::from(start.clone() + end.into()).min(lp.ub.clone());
+            loops.push(loop_like(lp, start.into(), ub));
+        }
+        // the band loops move below the tile loops
+        map.extend((0..n).map(|k| depth + n + k));
+        let mut innermost = loops.pop().unwrap();
+        let body = program.children(band[n - 1]);
+        for mut child in copy_sites(program, body, map, depth + 2 * n, &no_edit) {
+            Node::extend_loop_body(&mut innermost, &mut child);
+        }
+        map.truncate(depth);
+        let nest = loops
+            .into_iter()
+            .rev()
+            .fold(innermost, |mut body, mut outer| {
+                Node::extend_loop_body(&mut outer, &mut body);
+                outer
+            });
+        Some(vec![nest])
+    };
+    Ok(copy_program(program, &edit))
+}
+
+/// Fuses the loop `b` into the loop `a` right before it, appending the body of `b` to the body
+/// of `a`.
+///
+/// The two loops must be adjacent statements of the same loop body or block and have the same
+/// bounds, test and step; their indices may have different names. The fusion is refused if a
+/// reference in `a` may access an element that a reference in `b` accesses at an earlier
+/// iteration, and either one writes it, since the fused loop would run `b` first.
+pub fn fuse(program: &Program, a: NodeId, b: NodeId) -> Result<Program> {
+    let (Some(first), Some(second)) = (program.loop_stmt(a), program.loop_stmt(b)) else {
+        return Err(TransformError::new("only loops can be fused"));
+    };
+    let adjacent = program
+        .parent(a)
+        .filter(|&p| !matches!(program.stmt(p), Stmt::Branch(_)) && program.parent(b) == Some(p));
+    if !adjacent.is_some_and(|p| program.children(p).windows(2).any(|w| w == [a, b])) {
+        return Err(TransformError::new(format!(
+            "loop `{}` must come right after loop `{}` in the same body to be fused with it",
+            second.iv, first.iv
+        )));
+    }
+    if !same_header(first, second) {
+        return Err(TransformError::new(format!(
+            "loops `{}` and `{}` must have the same bounds, test and step to be fused",
+            first.iv, second.iv
+        )));
+    }
+    let depth = program.enclosing_loops(a).len();
+    let action = format!("fusing `{}` and `{}`", first.iv, second.iv);
+    let (head, tail) = (affine_refs(program, &[a])?, affine_refs(program, &[b])?);
+
+    let edit = |id: NodeId, map: &mut Vec<usize>, depth: usize| {
+        if id == b {
+            return Some(vec![]);
+        }
+        if id != a {
+            return None;
+        }
+        let mut fused = loop_like(first, first.lb.clone(), first.ub.clone());
+        map.push(depth);
+        let body = [program.children(a), program.children(b)].concat();
+        for mut child in copy_sites(program, &body, map, depth + 1, &no_edit) {
+            Node::extend_loop_body(&mut fused, &mut child);
+        }
+        map.pop();
+        Some(vec![fused])
+    };
+    let fused = copy_program(program, &edit);
+
+    // the fused loop runs the references of `a`, then those of `b`, in the original order
+    let old_ids: Vec<NodeId> = program.refs().collect();
+    let new_ids: Vec<NodeId> = fused.refs().collect();
+    let to_new = |refs: &[NodeId]| -> Vec<NodeId> {
+        refs.iter()
+            .map(|&id| new_ids[program.ref_id(id).unwrap()])
+            .collect()
+    };
+    let to_old = |id: NodeId| old_ids[fused.ref_id(id).unwrap()];
+    if let Some((src, snk)) = check_split(&fused, &to_new(&head), &to_new(&tail), depth) {
+        // it came from `snk` in `a` to `src` in `b` before the fusion
+        return Err(reversal_error(program, &action, to_old(snk), to_old(src)));
+    }
+    Ok(fused)
+}
+
+/// Splits the loop `aloop` into two loops with its header, the first running the statements of
+/// its body before `split_index` and the second the rest.
+///
+/// The distribution is refused if a reference in the second part may access an element that a
+/// reference in the first part accesses at a later iteration, and either one writes it, since
+/// all of the first part would run first.
+pub fn distribute(program: &Program, aloop: NodeId, split_index: usize) -> Result<Program> {
+    let Some(lp) = program.loop_stmt(aloop) else {
+        return Err(TransformError::new("only loops can be distributed"));
+    };
+    let body = program.children(aloop);
+    if split_index == 0 || split_index >= body.len() {
+        return Err(TransformError::new(format!(
+            "the body of loop `{}` has {} statements and cannot be split before statement {}",
+            lp.iv,
+            body.len(),
+            split_index
+        )));
+    }
+    let (head, tail) = body.split_at(split_index);
+    let depth = program.enclosing_loops(aloop).len();
+    let action = format!("distributing `{}`", lp.iv);
+    let (head_refs, tail_refs) = (affine_refs(program, head)?, affine_refs(program, tail)?);
+    if let Some((src, snk)) = check_split(program, &head_refs, &tail_refs, depth) {
+        return Err(reversal_error(program, &action, src, snk));
+    }
+
+    let edit = |id: NodeId, map: &mut Vec<usize>, depth: usize| {
+        if id != aloop {
+            return None;
+        }
+        let mut loops = vec![];
+        map.push(depth);
+        for part in [head, tail] {
+            let mut copy = loop_like(lp, lp.lb.clone(), lp.ub.clone());
+            for mut child in copy_sites(program, part, map, depth + 1, &no_edit) {
+                Node::extend_loop_body(&mut copy, &mut child);
+            }
+            loops.push(copy);
+        }
+        map.pop();
+        Some(loops)
+    };
+    Ok(copy_program(program, &edit))
+}
+
+/// Whether two loops run their indices through the same values.
+fn same_header(a: &LoopStmt, b: &LoopStmt) -> bool {
+    let same_test = match (a.cmp, b.cmp) {
+        (Some(x), Some(y)) => x == y,
+        _ => Rc::ptr_eq(&a.test, &b.test),
+    };
+    let same_step = match (a.stride, b.stride) {
+        (Some(x), Some(y)) => x == y,
+        _ => Rc::ptr_eq(&a.step, &b.step),
+    };
+    a.lb == b.lb && a.ub == b.ub && same_test && same_step
+}
+
+/// The only band of loops named `ivs`, outermost first, each the whole body of the one before.
+fn find_band(program: &Program, ivs: &[&str]) -> Result<Vec<NodeId>> {
+    let is_loop = |id: NodeId, iv: &str| program.loop_stmt(id).is_some_and(|lp| lp.iv == iv);
+    for iv in ivs {
+        if !program.ids().any(|id| is_loop(id, iv)) {
+            return Err(TransformError::new(format!("there is no loop `{}`", iv)));
+        }
+    }
+    let mut bands: Vec<Vec<NodeId>> = program
+        .ids()
+        .filter(|&id| is_loop(id, ivs[0]))
+        .filter_map(|first| {
+            let mut band = vec![first];
+            for iv in &ivs[1..] {
+                match program.children(*band.last().unwrap()) {
+                    [child] if is_loop(*child, iv) => band.push(*child),
+                    _ => return None,
+                }
+            }
+            Some(band)
+        })
+        .collect();
+    let names = ivs
+        .iter()
+        .map(|iv| format!("`{}`", iv))
+        .collect::<Vec<_>>()
+        .join(", ");
+    match bands.len() {
+        1 => Ok(bands.pop().unwrap()),
+        0 => Err(TransformError::new(format!(
+            "loops {} are not perfectly nested in this order: each must be the only statement \
+             in the body of the one before",
+            names
+        ))),
+        n => Err(TransformError::new(format!(
+            "loops {} are nested this way at {} places",
+            names, n
+        ))),
+    }
+}
+
+/// The bounds of the interchanged loops, in the new order of the iteration vector:
+/// `[inner_lb, inner_ub, outer_lb, outer_ub]` where `inner` is the loop that moves out.
+fn interchanged_bounds(outer: &LoopStmt, inner: &LoopStmt, depth: usize) -> Result<[LoopBound; 4]> {
+    match (uses_var(&inner.lb, depth), uses_var(&inner.ub, depth)) {
+        (Some(false), Some(false)) => {
+            return Ok([
+                inner.lb.clone(),
+                inner.ub.clone(),
+                outer.lb.clone(),
+                outer.ub.clone(),
+            ])
+        }
+        (Some(_), Some(_)) => {}
+        _ => {
+            return Err(TransformError::new(format!(
+                "the bounds of loop `{}` are not symbolic, so whether they depend on `{}` is \
+                 unknown",
+                inner.iv, outer.iv
+            )))
+        }
+    }
+
+    let ascending =
+        |lp: &LoopStmt| lp.stride == Some(1) && matches!(lp.cmp, Some(Cmp::Lt | Cmp::Le));
+    if !ascending(outer) || !ascending(inner) {
+        return Err(TransformError::new(format!(
+            "loop `{}` depends on `{}`, so both must count up by one with a `<` or `<=` test",
+            inner.iv, outer.iv
+        )));
+    }
+    // the values an index takes are max(lower) ..= min(upper)
+    let range = |lp: &LoopStmt| {
+        let last = if lp.cmp == Some(Cmp::Lt) { 1 } else { 0 };
+        match (pieces(&lp.lb, true), pieces(&lp.ub, false)) {
+            (Some(lower), Some(upper)) => {
+                let upper = upper.into_iter().map(|e| e - last.into()).collect();
+                Ok((lower, upper))
+            }
+            _ => Err(TransformError::new(format!(
+                "loop `{}` depends on `{}`, so the bounds of both must be affine, or a max \
+                 (lower) or min (upper) of affine expressions",
+                inner.iv, outer.iv
+            ))),
+        }
+    };
+    let (mut x_lower, mut x_upper) = range(outer)?;
+    let (y_lower, y_upper) = range(inner)?;
+
+    // x moves to depth + 1 and y to depth; turn the bounds of y into bounds of x
+    let y = AffineExpr::var(depth);
+    let (mut new_y_lower, mut new_y_upper) = (vec![], vec![]);
+    for (bounds, is_lower) in [(y_lower, true), (y_upper, false)] {
+        for e in bounds {
+            let (c, rest) = split(e, depth);
+            match (c, is_lower) {
+                (0, true) => new_y_lower.push(LoopBound::from(rest)),
+                (0, false) => new_y_upper.push(LoopBound::from(rest)),
+                // x + rest <= y, i.e. x <= y - rest
+                (1, true) => x_upper.push(y.clone() - rest),
+                (-1, true) => x_lower.push(rest - y.clone()),
+                (1, false) => x_lower.push(y.clone() - rest),
+                (-1, false) => x_upper.push(rest - y.clone()),
+                _ => {
+                    return Err(TransformError::new(format!(
+                        "the bounds of loop `{}` must step by one with `{}`",
+                        inner.iv, outer.iv
+                    )))
+                }
+            }
+        }
+    }
+    // y takes the values for which some x is in range
+    for lo in &x_lower {
+        for hi in &x_upper {
+            // c*y + rest <= 0
+            let (c, rest) = split(lo.clone() - hi.clone(), depth);
+            match c.signum() {
+                1 => new_y_upper.push(LoopBound::from(-rest).floor_div(c)),
+                -1 => new_y_lower.push(LoopBound::from(rest).ceil_div(-c)),
+                _ => {}
+            }
+        }
+    }
+    let affine_of = |bounds: &[LoopBound]| -> Vec<AffineExpr> {
+        bounds.iter().filter_map(LoopBound::as_affine).collect()
+    };
+    let (y_lo, y_hi) = (affine_of(&new_y_lower), affine_of(&new_y_upper));
+    let x_lower = prune(x_lower, true, depth, &y_lo, &y_hi);
+    let x_upper = prune(x_upper, false, depth, &y_lo, &y_hi);
+
+    let fold = |bounds: Vec<LoopBound>, is_lower: bool| {
+        bounds
+            .into_iter()
+            .reduce(|p, q| if is_lower { p.max(q) } else { p.min(q) })
+    };
+    let past = |lp: &LoopStmt, last: LoopBound| {
+        if lp.cmp == Some(Cmp::Lt) {
+            last + LoopBound::Fixed(1)
+        } else {
+            last
+        }
+    };
+    let to_bounds = |exprs: Vec<AffineExpr>| exprs.into_iter().map(LoopBound::from).collect();
+    match (fold(new_y_lower, true), fold(new_y_upper, false)) {
+        (Some(lb), Some(ub)) => Ok([
+            lb,
+            past(inner, ub),
+            fold(to_bounds(x_lower), true).unwrap(),
+            past(outer, fold(to_bounds(x_upper), false).unwrap()),
+        ]),
+        _ => Err(TransformError::new(format!(
+            "the range of loop `{}` is unbounded once it encloses `{}`",
+            inner.iv, outer.iv
+        ))),
+    }
+}
+
+/// The affine expressions a bound is the max (`is_lower`) or min of.
+fn pieces(bound: &LoopBound, is_lower: bool) -> Option<Vec<AffineExpr>> {
+    match bound {
+        LoopBound::Max(x, y) if is_lower => Some([pieces(x, true)?, pieces(y, true)?].concat()),
+        LoopBound::Min(x, y) if !is_lower => Some([pieces(x, false)?, pieces(y, false)?].concat()),
+        _ => bound.as_affine().map(|e| vec![e]),
+    }
+}
+
+/// Drops the candidates of a `max` (or of a `min` when `is_lower` is false) that never decide
+/// it while the index at `depth` is at least every one of `y_lower` and at most every one of
+/// `y_upper`.
+fn prune(
+    mut exprs: Vec<AffineExpr>,
+    is_lower: bool,
+    depth: usize,
+    y_lower: &[AffineExpr],
+    y_upper: &[AffineExpr],
+) -> Vec<AffineExpr> {
+    // a constant the value of `e` never falls below, if there is one
+    let least = |e: AffineExpr| {
+        let (c, rest) = split(e, depth);
+        if c == 0 {
+            return rest.is_constant().then_some(rest.constant);
+        }
+        let bounds = if c > 0 { y_lower } else { y_upper };
+        bounds
+            .iter()
+            .map(|b| rest.clone() + b.clone() * c)
+            .filter(AffineExpr::is_constant)
+            .map(|e| e.constant)
+            .max()
+    };
+    let mut i = 0;
+    while i < exprs.len() {
+        let redundant = (0..exprs.len()).any(|j| {
+            let gap = if is_lower {
+                exprs[j].clone() - exprs[i].clone()
+            } else {
+                exprs[i].clone() - exprs[j].clone()
+            };
+            j != i && least(gap).is_some_and(|v| v >= 0)
+        });
+        if redundant {
+            exprs.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    exprs
+}
+
+/// The coefficient of the index at `pos` and the rest of the expression.
+fn split(e: AffineExpr, pos: usize) -> (i32, AffineExpr) {
+    let c = e.coeff(pos);
+    (c, e - AffineExpr::var(pos) * c)
+}
+
+/// Whether a bound depends on the index at `pos`, or `None` if it has a closure in it.
+fn uses_var(bound: &LoopBound, pos: usize) -> Option<bool> {
+    match bound {
+        LoopBound::Fixed(_) => Some(false),
+        LoopBound::Affine { a, .. } => Some(a.get(pos).is_some_and(|&c| c != 0)),
+        LoopBound::Dynamic(_) => None,
+        LoopBound::Min(x, y) | LoopBound::Max(x, y) => Some(uses_var(x, pos)? || uses_var(y, pos)?),
+        LoopBound::FloorDiv(x, _) | LoopBound::CeilDiv(x, _) => uses_var(x, pos),
+    }
+}
+
+/// The reference sites beneath `roots`, which must all have affine subscripts.
+fn affine_refs(program: &Program, roots: &[NodeId]) -> Result<Vec<NodeId>> {
+    let mut refs = vec![];
+    for id in roots.iter().flat_map(|&root| program.subtree(root)) {
+        if let Some(aref) = program.ary_ref(id) {
+            if aref.sub_affine.is_none() {
+                return Err(TransformError::new(format!(
+                    "the subscripts of `{}` are not affine, so its dependences cannot be checked",
+                    aref.name
+                )));
+            }
+            refs.push(id);
+        }
+    }
+    Ok(refs)
+}
+
+/// The first dependence of `program` from a reference in `srcs` to one in `snks` whose
+/// direction vector `reverses` says the transformation would reverse. Two reads may run in
+/// any order, so input dependences never count.
+fn find_reversed(
+    program: &Program,
+    srcs: &[NodeId],
+    snks: &[NodeId],
+    reverses: impl Fn(&[Direction]) -> bool,
+) -> Option<Dependence> {
+    dependences(program, |id| program.ary_ref(id).unwrap().kind.writes())
+        .into_iter()
+        .filter(|dep| dep.kind != DepKind::Input)
+        .find(|dep| srcs.contains(&dep.src) && snks.contains(&dep.snk) && reverses(&dep.direction))
+}
+
+fn reversal_error(program: &Program, action: &str, src: NodeId, snk: NodeId) -> TransformError {
+    TransformError::new(format!(
+        "{} may reverse a dependence from {} to {}",
+        action,
+        describe_ref(program, src),
+        describe_ref(program, snk)
+    ))
+}
+
+/// Whether a direction may be `dir`.
+fn may_be(d: Direction, dir: Direction) -> bool {
+    d == dir || d == Direction::Star
+}
+
+/// Whether a dependence may stay within one iteration of each loop above `depth`.
+fn same_outer(direction: &[Direction], depth: usize) -> bool {
+    direction[..depth].iter().all(|&d| may_be(d, Direction::Eq))
+}
+
+/// Refuses to reorder the loops at the depths in `band` if two references beneath them may
+/// touch the same element in iterations whose order a reordering can reverse: the later access
+/// is at a later iteration of one loop of the band but an earlier one of another.
+fn check_permutable(
+    program: &Program,
+    innermost: NodeId,
+    band: Range<usize>,
+    action: &str,
+) -> Result<()> {
+    let refs = affine_refs(program, &[innermost])?;
+    let reverses = |dir: &[Direction]| {
+        same_outer(dir, band.start)
+            && band.clone().any(|pos| {
+                may_be(dir[pos], Direction::Lt)
+                    && (pos + 1..band.end).any(|neg| may_be(dir[neg], Direction::Gt))
+            })
+    };
+    match find_reversed(program, &refs, &refs, reverses) {
+        Some(dep) => Err(reversal_error(program, action, dep.src, dep.snk)),
+        None => Ok(()),
+    }
+}
+
+/// Refuses to run all of `head` before all of `tail`, where both are in the body of a loop at
+/// `depth` in `program`: a reference in `tail` may access an element that one in `head`
+/// accesses at a later iteration. Returns the offending pair, source first.
+fn check_split(
+    program: &Program,
+    head: &[NodeId],
+    tail: &[NodeId],
+    depth: usize,
+) -> Option<(NodeId, NodeId)> {
+    // in the same iteration, `head` runs first, so a dependence from `tail` to `head` is
+    // carried at `depth` or above
+    find_reversed(program, tail, head, |dir| same_outer(dir, depth)).map(|dep| (dep.src, dep.snk))
+}
+
+/// A reference as written in the source, e.g. `A[i][j+1]`.
+fn describe_ref(program: &Program, id: NodeId) -> String {
+    let aref = program.ary_ref(id).unwrap();
+    let names: Vec<String> = program
+        .enclosing_loops(id)
+        .into_iter()
+        .map(|l| program.loop_stmt(l).unwrap().iv.clone())
+        .collect();
+    let subs = aref.sub_affine.iter().flatten();
+    let subs: String = subs
+        .map(|e| format!("[{}]", e.to_string_with(&names)))
+        .collect();
+    format!("`{}{}`", aref.name, subs)
+}
+
+/// Changes a site of a copy: given the site, the index map and the depth, returns the nodes
+/// that replace it (possibly none), or `None` to copy it as is.
+type Edit<'a> = &'a dyn Fn(NodeId, &mut Vec<usize>, usize) -> Option<Vec<Rc<Node>>>;
+
+fn no_edit(_: NodeId, _: &mut Vec<usize>, _: usize) -> Option<Vec<Rc<Node>>> {
+    None
+}
+
+/// Copies the whole program, changing it with `edit`. The arrays keep their element sizes and
+/// base addresses.
+fn copy_program(program: &Program, edit: Edit) -> Program {
+    let nodes = copy_sites(program, &[program.root()], &mut vec![], 0, edit);
+    let placement = Placement::Explicit(program.ary_bases().clone());
+    Program::with_placement(&block_of(nodes), program.default_elem_size(), &placement)
+}
+
+/// One node for a sequence of them.
+fn block_of(mut nodes: Vec<Rc<Node>>) -> Rc<Node> {
+    if nodes.len() == 1 {
+        nodes.pop().unwrap()
+    } else {
+        Node::new_node(Stmt::Block(nodes))
+    }
+}
+
+/// Copies the trees at `ids`, moving loop indices as `map` says: the loop that enclosed them at
+/// depth `k` is at depth `map[k]` in the copy, which is enclosed by `depth` loops.
+fn copy_sites(
+    program: &Program,
+    ids: &[NodeId],
+    map: &mut Vec<usize>,
+    depth: usize,
+    edit: Edit,
+) -> Vec<Rc<Node>> {
+    let mut nodes = vec![];
+    for &id in ids {
+        match edit(id, map, depth) {
+            Some(replacement) => nodes.extend(replacement),
+            None => nodes.push(copy_site(program, id, map, depth, edit)),
+        }
+    }
+    nodes
+}
+
+fn copy_site(
+    program: &Program,
+    id: NodeId,
+    map: &mut Vec<usize>,
+    depth: usize,
+    edit: Edit,
+) -> Rc<Node> {
+    match program.stmt(id) {
+        Stmt::Ref(aref) => Node::new_node(Stmt::Ref(rename_ref(aref, map))),
+        Stmt::Loop(aloop) => {
+            let lb = rename_bound(&aloop.lb, map);
+            let ub = rename_bound(&aloop.ub, map);
+            let mut copy = loop_like(aloop, lb, ub);
+            map.push(depth);
+            for mut child in copy_sites(program, program.children(id), map, depth + 1, edit) {
+                Node::extend_loop_body(&mut copy, &mut child);
+            }
+            map.pop();
+            copy
+        }
+        Stmt::Block(_) => Node::new_node(Stmt::Block(copy_sites(
+            program,
+            program.children(id),
+            map,
+            depth,
+            edit,
+        ))),
+        Stmt::Branch(branch) => {
+            // the then part is the first child, the else part (if any) the second
+            let children = program.children(id);
+            let then_body = block_of(copy_sites(program, &children[..1], map, depth, edit));
+            let else_body = match children.len() {
+                1 => None,
+                _ => Some(block_of(copy_sites(
+                    program,
+                    &children[1..],
+                    map,
+                    depth,
+                    edit,
+                ))),
+            };
+            match &branch.pred {
+                Some(pred) => {
+                    Node::new_branch(pred.map(&|e| rename_affine(e, map)), then_body, else_body)
+                }
+                None => Node::new_node(Stmt::Branch(BranchStmt {
+                    cond: remap(&branch.cond, map),
+                    pred: None,
+                    then_body,
+                    else_body,
+                })),
+            }
+        }
+    }
+}
+
+/// A loop with the index, test and step of `aloop`, the given bounds and an empty body.
+fn loop_like(aloop: &LoopStmt, lb: LoopBound, ub: LoopBound) -> Rc<Node> {
+    Node::new_node(Stmt::Loop(LoopStmt {
+        iv: aloop.iv.clone(),
+        lb,
+        ub,
+        test: Rc::clone(&aloop.test),
+        step: Rc::clone(&aloop.step),
+        cmp: aloop.cmp,
+        stride: aloop.stride,
+        body: RefCell::new(vec![]),
+    }))
+}
+
+fn is_identity(map: &[usize]) -> bool {
+    map.iter().enumerate().all(|(k, &p)| k == p)
+}
+
+fn rename_affine(e: &AffineExpr, map: &[usize]) -> AffineExpr {
+    e.coeffs
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c != 0)
+        .fold(AffineExpr::constant(e.constant), |acc, (k, &c)| {
+            acc + AffineExpr::var(map[k]) * c
+        })
+}
+
+fn rename_bound(bound: &LoopBound, map: &[usize]) -> LoopBound {
+    match bound {
+        LoopBound::Fixed(_) => bound.clone(),
+        LoopBound::Affine { .. } => rename_affine(&bound.as_affine().unwrap(), map).into(),
+        LoopBound::Dynamic(f) => LoopBound::Dynamic(remap(f, map)),
+        LoopBound::Min(x, y) => rename_bound(x, map).min(rename_bound(y, map)),
+        LoopBound::Max(x, y) => rename_bound(x, map).max(rename_bound(y, map)),
+        LoopBound::FloorDiv(x, k) => rename_bound(x, map).floor_div(*k),
+        LoopBound::CeilDiv(x, k) => rename_bound(x, map).ceil_div(*k),
+    }
+}
+
+fn rename_ref(aref: &AryRef, map: &[usize]) -> AryRef {
+    match &aref.sub_affine {
+        Some(subs) => {
+            let subs: Vec<AffineExpr> = subs.iter().map(|e| rename_affine(e, map)).collect();
+            AryRef {
+                sub: compile_sub(&subs),
+                sub_affine: Some(subs),
+                ..aref.clone()
+            }
+        }
+        None => AryRef {
+            sub: remap(&aref.sub, map),
+            ..aref.clone()
+        },
+    }
+}
+
+/// A closure of the enclosing loop indices, like a subscript, bound or branch predicate.
+type IvecFn<T> = dyn Fn(&[i32]) -> T;
+
+/// Wraps a closure over the old iteration vector into one over the new.
+fn remap<T: 'static>(f: &Rc<IvecFn<T>>, map: &[usize]) -> Rc<IvecFn<T>> {
+    if is_identity(map) {
+        return Rc::clone(f);
+    }
+    let (f, map) = (Rc::clone(f), map.to_vec());
+    Rc::new(move |ivec: &[i32]| {
+        let old: Vec<i32> = map.iter().map(|&p| ivec[p]).collect();
+        f(&old)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::AccessStream;
+    use crate::parse::parse;
+
+    fn program(src: &str) -> Program {
+        Program::new(&parse(src, &[("N", 10)]).unwrap())
+    }
+
+    fn addresses(program: &Program) -> Vec<usize> {
+        AccessStream::new(program).map(|a| a.addr).collect()
+    }
+
+    fn sorted(mut v: Vec<usize>) -> Vec<usize> {
+        v.sort_unstable();
+        v
+    }
+
+    #[test]
+    fn gemm_ikj() {
+        let gemm = "param N; double A[N][N]; double B[N][N]; double C[N][N];
+            for (i = 0; i < N; i++)
+              for (j = 0; j < N; j++)
+                for (k = 0; k < N; k++)
+                  C[i][j] += A[i][k] * B[k][j];";
+        let ikj = interchange(&program(gemm), "j", "k").unwrap();
+        let expected = program(
+            "param N; double A[N][N]; double B[N][N]; double C[N][N];
+            for (i = 0; i < N; i++)
+              for (k = 0; k < N; k++)
+                for (j = 0; j < N; j++)
+                  C[i][j] += A[i][k] * B[k][j];",
+        );
+        assert!(ikj.root_node().structural_eq(expected.root_node()));
+        assert_eq!(addresses(&ikj), addresses(&expected));
+        assert_ne!(addresses(&ikj), addresses(&program(gemm)));
+    }
+
+    #[test]
+    fn closure_subscripts() {
+        // i { j { A[j][i] } } with a closure subscript and a dynamic bound
+        let mut aref = Node::new_ref("A", vec![10, 10], |iv| vec![iv[1] as usize, iv[0] as usize]);
+        let mut iloop = Node::new_single_loop("i", 0, 10);
+        let mut jloop = Node::new_single_loop_dyn_ub("j", 0, Box::new(|_: &[i32]| 5));
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        let before = Program::new(&iloop);
+
+        // the closure cannot be checked for dependences
+        let err = interchange(&before, "i", "j").map(|_| ()).unwrap_err();
+        assert!(err.message.contains("not affine"), "{}", err);
+
+        // a closure deeper in the nest is wrapped for the new index order
+        let mut aref = Node::new_affine_ref(
+            "B",
+            vec![10, 10],
+            vec![AffineExpr::var(0), AffineExpr::var(1)],
+        );
+        let mut iloop = Node::new_single_loop("i", 0, 4);
+        let mut jloop = Node::new_single_loop("j", 0, 3);
+        let mut kloop = Node::new_single_loop_dyn_ub("k", 0, Box::new(|iv: &[i32]| iv[1] + 1));
+        Node::extend_loop_body(&mut kloop, &mut aref);
+        Node::extend_loop_body(&mut jloop, &mut kloop);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        let before = Program::new(&iloop);
+        let after = interchange(&before, "i", "j").unwrap();
+        // k runs j + 1 times either way
+        assert_eq!(addresses(&after).len(), addresses(&before).len());
+        assert_eq!(sorted(addresses(&after)), sorted(addresses(&before)));
+    }
+
+    #[test]
+    fn triangular() {
+        for (src, outer, inner) in [
+            (
+                "for (i = 0; i < N; i++) for (j = 0; j < i; j++) A[i][j] = 0;",
+                "i",
+                "j",
+            ),
+            (
+                "for (i = 0; i < N; i++) for (j = i; j < N; j++) A[i][j] = 0;",
+                "i",
+                "j",
+            ),
+            (
+                "for (i = 2; i <= N - 1; i++) for (j = N - i; j <= N - 1; j++) A[i][j] = 0;",
+                "i",
+                "j",
+            ),
+            (
+                "for (i = 1; i < N; i++) for (j = i - 1; j <= i + 1; j++) A[i][j] = 0;",
+                "i",
+                "j",
+            ),
+        ] {
+            let before = program(&format!("param N; double A[N][N + 1]; {}", src));
+            let after = interchange(&before, outer, inner).unwrap();
+            // the same accesses in the transposed order
+            assert_eq!(
+                sorted(addresses(&after)),
+                sorted(addresses(&before)),
+                "{}",
+                src
+            );
+            assert_ne!(addresses(&after), addresses(&before), "{}", src);
+            let back = interchange(&after, inner, outer).unwrap();
+            assert_eq!(addresses(&back), addresses(&before), "{}", src);
+        }
+
+        let after = interchange(
+            &program("param N; double A[N][N]; for (i = 0; i < N; i++) for (j = i; j < N; j++) A[i][j] = 0;"),
+            "i",
+            "j",
+        )
+        .unwrap();
+        let expected = program(
+            "param N; double A[N][N]; for (j = 0; j < N; j++) for (i = 0; i < j + 1; i++) A[i][j] = 0;",
+        );
+        assert!(after.root_node().structural_eq(expected.root_node()));
+    }
+
+    #[test]
+    fn refused() {
+        let err = |src: &str, outer: &str, inner: &str| {
+            interchange(
+                &program(&format!("param N; double A[N][N]; {}", src)),
+                outer,
+                inner,
+            )
+            .map(|_| ())
+            .unwrap_err()
+            .to_string()
+        };
+        let nest = "for (i = 0; i < N; i++) for (j = 0; j < N; j++) A[i][j] = 0;";
+        assert_eq!(err(nest, "i", "x"), "there is no loop `x`");
+        assert!(err(nest, "j", "i").contains("not perfectly nested"));
+        assert!(err(
+            "for (i = 0; i < N; i++) { A[i][0] = 0; for (j = 0; j < N; j++) A[i][j] = 0; }",
+            "i",
+            "j"
+        )
+        .contains("not perfectly nested"));
+        assert!(err(
+            "for (i = 0; i < N; i++) for (j = 0; j < 2 * i; j++) A[i][j] = 0;",
+            "i",
+            "j"
+        )
+        .contains("step by one"));
+        assert!(err(
+            "for (i = 0; i < N; i += 2) for (j = 0; j < i; j++) A[i][j] = 0;",
+            "i",
+            "j"
+        )
+        .contains("count up by one"));
+        assert_eq!(
+            err(
+                "for (i = 1; i < N; i++) for (j = 0; j < N - 1; j++) A[i][j] = A[i - 1][j + 1];",
+                "i",
+                "j"
+            ),
+            "interchanging `i` and `j` may reverse a dependence from `A[i][j]` to `A[i-1][j+1]`"
+        );
+        assert!(err(
+            "for (k = 0; k < N; k++) for (i = 0; i < N; i++) for (j = 0; j < N; j++) A[i][j] = 0;
+             for (i = 0; i < N; i++) for (j = 0; j < N; j++) A[i][j] = 0;",
+            "i",
+            "j"
+        )
+        .contains("at 2 places"));
+    }
+
+    #[test]
+    fn tiled_gemm() {
+        let gemm = "param N; double A[N][N]; double B[N][N]; double C[N][N];
+            for (i = 0; i < N; i++)
+              for (j = 0; j < N; j++)
+                for (k = 0; k < N; k++)
+                  C[i][j] += A[i][k] * B[k][j];";
+        let before = program(gemm);
+        let tiled = tile(&before, &[("i", 4), ("j", 3)]).unwrap();
+        let expected = program(
+            "param N; double A[N][N]; double B[N][N]; double C[N][N];
+            for (ii = 0; ii < N; ii += 4)
+              for (jj = 0; jj < N; jj += 3)
+                for (i = ii; i < min(ii + 4, N); i++)
+                  for (j = jj; j < min(jj + 3, N); j++)
+                    for (k = 0; k < N; k++)
+                      C[i][j] += A[i][k] * B[k][j];",
+        );
+        assert!(tiled.root_node().structural_eq(expected.root_node()));
+        assert_eq!(addresses(&tiled), addresses(&expected));
+        assert_eq!(sorted(addresses(&tiled)), sorted(addresses(&before)));
+
+        // strip-mining the inner loop of a triangle, with an inclusive bound
+        let before = program(
+            "param N; double A[N][N]; for (i = 0; i < N; i++) for (j = 0; j <= i; j++) A[i][j] = 0;",
+        );
+        let tiled = tile(&before, &[("j", 4)]).unwrap();
+        assert_eq!(addresses(&tiled), addresses(&before));
+    }
+
+    #[test]
+    fn tile_refused() {
+        let err = |src: &str, tiles: &[(&str, i32)]| {
+            tile(
+                &program(&format!("param N; double A[N][N]; {}", src)),
+                tiles,
+            )
+            .map(|_| ())
+            .unwrap_err()
+            .to_string()
+        };
+        let nest = "for (i = 0; i < N; i++) for (j = 0; j < N; j++) A[i][j] = 0;";
+        assert!(err(nest, &[("i", 0)]).contains("must be positive"));
+        assert!(err(nest, &[("j", 4), ("i", 4)]).contains("not perfectly nested"));
+        assert!(err(
+            "for (i = 0; i < N; i++) for (j = 0; j < i; j++) A[i][j] = 0;",
+            &[("i", 4), ("j", 4)]
+        )
+        .contains("must not depend"));
+        assert!(err(
+            "for (ii = 0; ii < N; ii++) for (i = 0; i < N; i++) A[i][ii] = 0;",
+            &[("i", 4)]
+        )
+        .contains("already a loop `ii`"));
+        assert_eq!(
+            err(
+                "for (i = 1; i < N; i++) for (j = 0; j < N - 1; j++) A[i][j] = A[i - 1][j + 1];",
+                &[("i", 4), ("j", 4)]
+            ),
+            "tiling `i`, `j` may reverse a dependence from `A[i][j]` to `A[i-1][j+1]`"
+        );
+        // a single loop can always be strip-mined
+        assert!(tile(
+            &program(
+                "param N; double A[N][N];
+                 for (i = 1; i < N; i++) for (j = 0; j < N - 1; j++) A[i][j] = A[i - 1][j + 1];"
+            ),
+            &[("j", 4)]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn keeps_layout() {
+        let code = parse(
+            "param N; double A[N][N]; double B[N][N];
+             for (i = 0; i < N; i++) for (j = 0; j < N; j++) A[i][j] = B[j][i];",
+            &[("N", 10)],
+        )
+        .unwrap();
+        let before = Program::with_placement(&code, 4, &Placement::Aligned(4096));
+        let after = interchange(&before, "i", "j").unwrap();
+        assert_eq!(after.default_elem_size(), 4);
+        assert_eq!(after.ary_bases(), before.ary_bases());
+        assert_eq!(after.data_size(), before.data_size());
+        assert_eq!(sorted(addresses(&after)), sorted(addresses(&before)));
+        let tiled = tile(&before, &[("i", 4), ("j", 4)]).unwrap();
+        assert_eq!(sorted(addresses(&tiled)), sorted(addresses(&before)));
+    }
+
+    #[test]
+    fn fuse_distribute() {
+        let unfused = program(
+            "param N; double A[N]; double B[N]; double C[N];
+             for (i = 0; i < N; i++) A[i] = B[i];
+             for (i = 0; i < N; i++) C[i] = B[i];",
+        );
+        let loops = unfused.children(unfused.root()).to_vec();
+        let fused = fuse(&unfused, loops[0], loops[1]).unwrap();
+        let expected = program(
+            "param N; double A[N]; double B[N]; double C[N];
+             for (i = 0; i < N; i++) { A[i] = B[i]; C[i] = B[i]; }",
+        );
+        assert_eq!(addresses(&fused), addresses(&expected));
+        assert_eq!(sorted(addresses(&fused)), sorted(addresses(&unfused)));
+
+        let aloop = fused.children(fused.root())[0];
+        let distributed = distribute(&fused, aloop, 2).unwrap();
+        assert!(distributed.root_node().structural_eq(unfused.root_node()));
+        // the root loop of `expected` splits into a block of two loops
+        let distributed = distribute(&expected, expected.root(), 2).unwrap();
+        assert!(distributed.root_node().structural_eq(unfused.root_node()));
+    }
+
+    #[test]
+    fn fuse_nests() {
+        let mvt = program(
+            "param N; double x1[N]; double x2[N]; double y1[N]; double y2[N]; double A[N][N];
+             for (i = 0; i < N; i++) for (j = 0; j < N; j++) x1[i] += A[i][j] * y1[j];
+             for (m = 0; m < N; m++) for (k = 0; k < N; k++) x2[m] += A[k][m] * y2[k];",
+        );
+        let outer = mvt.children(mvt.root()).to_vec();
+        let fused = fuse(&mvt, outer[0], outer[1]).unwrap();
+        let inner = fused.children(fused.children(fused.root())[0]).to_vec();
+        let fused = fuse(&fused, inner[0], inner[1]).unwrap();
+        assert_eq!(fused.root_node().node_count(), 11);
+        assert_eq!(sorted(addresses(&fused)), sorted(addresses(&mvt)));
+    }
+
+    #[test]
+    fn fuse_distribute_refused() {
+        let ids = |p: &Program| p.children(p.root()).to_vec();
+        let err = |r: Result<Program>| r.map(|_| ()).unwrap_err().to_string();
+
+        let p = program(
+            "param N; double A[N]; double B[N];
+             for (i = 0; i < N; i++) A[i] = 0;
+             for (j = 0; j < N; j++) B[j] = A[j + 1];
+             for (k = 1; k < N; k++) B[k] = 0;
+             for (k = 1; k < N; k++) B[k] = 0;",
+        );
+        let l = ids(&p);
+        assert_eq!(
+            err(fuse(&p, l[0], l[1])),
+            "fusing `i` and `j` may reverse a dependence from `A[i]` to `A[j+1]`"
+        );
+        assert!(err(fuse(&p, l[1], l[0])).contains("must come right after"));
+        assert!(err(fuse(&p, l[0], l[2])).contains("must come right after"));
+        assert!(err(fuse(&p, l[1], l[2])).contains("same bounds"));
+        assert!(fuse(&p, l[2], l[3]).is_ok());
+        assert!(err(fuse(&p, l[0], p.children(l[0])[0])).contains("only loops"));
+
+        let p = program(
+            "param N; double A[N]; double B[N];
+             for (i = 1; i < N; i++) { A[i] = B[i - 1]; B[i] = 0; }",
+        );
+        assert_eq!(
+            err(distribute(&p, p.root(), 2)),
+            "distributing `i` may reverse a dependence from `B[i]` to `B[i-1]`"
+        );
+        assert!(err(distribute(&p, p.root(), 3)).contains("cannot be split before statement 3"));
+        assert!(err(distribute(&p, p.root(), 0)).contains("cannot be split"));
+    }
+
+    #[test]
+    fn fuse_reads() {
+        // B is only read, so the order of its accesses does not matter
+        let p = program(
+            "param N; double A[N]; double B[N + 1]; double C[N];
+             for (i = 0; i < N; i++) A[i] = B[i];
+             for (j = 0; j < N; j++) C[j] = B[j + 1];",
+        );
+        let loops = p.children(p.root()).to_vec();
+        let fused = fuse(&p, loops[0], loops[1]).unwrap();
+        assert_eq!(sorted(addresses(&fused)), sorted(addresses(&p)));
+        let aloop = fused.children(fused.root())[0];
+        assert!(distribute(&fused, aloop, 1).is_ok());
+    }
+}
@@ -0,0 +1,519 @@
+//! Static checks of a loop tree, to report mistakes in building a kernel before they
+//! surface as panics or wrong addresses deep in a tracer.
+//!
+//! `validate` checks
+//! - index scoping: the affine subscripts of a reference, and the index names of a
+//!   reference built by `construct::a_ref`, use only the loops enclosing it;
+//! - bound arity: the affine bounds of a loop use only the loops enclosing it;
+//! - arrays: every reference of an array agrees on its dimensions, element type and
+//!   layout, has one subscript per dimension, and the layout fits the dimensions;
+//! - reachability: branch arms that are never taken and loops that never run. These are
+//!   found by running the loop nest without its references, and only for nests of at most
+//!   `REACH_LIMIT` loop iterations.
+//!
+//! Subscripts and bounds given only as closures can't be checked.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{AryRef, BranchStmt, LoopBound, LoopStmt, Node, Stmt};
+use crate::layout::Layout;
+
+/// The number of loop iterations up to which `validate` looks for unreachable code.
+pub const REACH_LIMIT: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Tracing the program would panic or give wrong results.
+    Error,
+    /// Probably a mistake, e.g. code that never runs.
+    Warning,
+}
+
+/// A problem found by `validate`, with where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Indices of the loops enclosing the problem, outermost first.
+    pub loops: Vec<String>,
+    /// Id of the reference the problem is in, see `Program::ref_id`.
+    pub ref_id: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error")?,
+            Severity::Warning => write!(f, "warning")?,
+        }
+        if !self.loops.is_empty() {
+            write!(f, " in loop {}", self.loops.join(" > "))?;
+        }
+        if let Some(id) = self.ref_id {
+            write!(f, " at reference {}", id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks a loop tree, see the module documentation. The diagnostics are in pre-order of the
+/// nodes they are about, followed by those about reachability.
+///
+/// # Examples
+/// ```rust
+/// use dace::affine::AffineExpr;
+/// use dace::ast::Node;
+/// // A[j] in a loop over i alone
+/// let mut aloop = Node::new_single_loop("i", 0, 10);
+/// let mut aref = Node::new_affine_ref("A", vec![10], vec![AffineExpr::var(1)]);
+/// Node::extend_loop_body(&mut aloop, &mut aref);
+/// let diags = dace::validate(&aloop);
+/// assert_eq!(diags.len(), 1);
+/// assert_eq!(
+///     diags[0].to_string(),
+///     "error in loop i at reference 0: subscript 0 of `A` uses the index of loop 1, but only 1 loop(s) enclose it"
+/// );
+/// ```
+pub fn validate(code: &Node) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        loops: vec![],
+        ref_count: 0,
+        arrays: HashMap::new(),
+        diags: vec![],
+    };
+    checker.node(code);
+    if checker.diags.iter().all(|d| d.severity != Severity::Error) {
+        let mut reach = Reach {
+            ivec: vec![],
+            budget: REACH_LIMIT,
+            taken: HashMap::new(),
+        };
+        if reach.node(code).is_some() {
+            let mut loops = vec![];
+            reach.report(code, &mut loops, &mut checker.diags);
+        }
+    }
+    checker.diags
+}
+
+/// Prints the warnings of `validate` and panics with its errors, if there are any. The
+/// tracers call this before they start.
+pub fn assert_valid(code: &Node) {
+    let diags = validate(code);
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        diags.iter().partition(|d| d.severity == Severity::Error);
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|d| d.to_string()).collect();
+        panic!("invalid program:\n{}", errors.join("\n"));
+    }
+}
+
+struct Checker {
+    loops: Vec<String>,
+    ref_count: usize,
+    /// The first reference of every array, with its id.
+    arrays: HashMap<String, (AryRef, usize)>,
+    diags: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, severity: Severity, ref_id: Option<usize>, message: String) {
+        self.diags.push(Diagnostic {
+            severity,
+            loops: self.loops.clone(),
+            ref_id,
+            message,
+        });
+    }
+
+    fn node(&mut self, node: &Node) {
+        match &node.stmt {
+            Stmt::Ref(aref) => {
+                self.ref_count += 1;
+                self.aref(aref, self.ref_count - 1);
+            }
+            Stmt::Loop(aloop) => {
+                self.bound(aloop, &aloop.lb, "lower");
+                self.bound(aloop, &aloop.ub, "upper");
+                self.loops.push(aloop.iv.clone());
+                for child in aloop.body.borrow().iter() {
+                    self.node(child);
+                }
+                self.loops.pop();
+            }
+            Stmt::Block(children) => children.iter().for_each(|child| self.node(child)),
+            Stmt::Branch(branch) => {
+                self.node(&branch.then_body);
+                if let Some(else_body) = &branch.else_body {
+                    self.node(else_body);
+                }
+            }
+        }
+    }
+
+    fn bound(&mut self, aloop: &LoopStmt, bound: &LoopBound, which: &str) {
+        let depth = self.loops.len();
+        if let Some(pos) = bound_depth(bound).filter(|&d| d > depth) {
+            self.report(
+                Severity::Error,
+                None,
+                format!(
+                    "the {} bound of loop `{}` uses the index of loop {}, but only {} loop(s) enclose it",
+                    which,
+                    aloop.iv,
+                    pos - 1,
+                    depth
+                ),
+            );
+        }
+    }
+
+    fn aref(&mut self, aref: &AryRef, id: usize) {
+        let depth = self.loops.len();
+        let name = &aref.name;
+        match &aref.sub_affine {
+            Some(subs) => {
+                if subs.len() != aref.dim.len() {
+                    self.report(
+                        Severity::Error,
+                        Some(id),
+                        format!(
+                            "`{}` has {} dimension(s) but {} subscript(s)",
+                            name,
+                            aref.dim.len(),
+                            subs.len()
+                        ),
+                    );
+                }
+                for (k, sub) in subs.iter().enumerate() {
+                    if sub.depth() > depth {
+                        self.report(
+                            Severity::Error,
+                            Some(id),
+                            format!(
+                                "subscript {} of `{}` uses the index of loop {}, but only {} loop(s) enclose it",
+                                k,
+                                name,
+                                sub.depth() - 1,
+                                depth
+                            ),
+                        );
+                    }
+                }
+            }
+            // built by `construct::a_ref` but not bound to its loops by `construct::insert_at`
+            None if !aref.indices.is_empty() => {
+                let unbound: Vec<&String> = aref
+                    .indices
+                    .iter()
+                    .filter(|ix| !self.loops.contains(ix))
+                    .collect();
+                let message = if unbound.is_empty() {
+                    format!(
+                        "the subscripts of `{}` were never set, insert it with `construct::insert_at`",
+                        name
+                    )
+                } else {
+                    format!(
+                        "index `{}` of `{}` is not the index of an enclosing loop",
+                        unbound[0], name
+                    )
+                };
+                self.report(Severity::Error, Some(id), message);
+            }
+            None => {}
+        }
+
+        if let Err(message) = layout_fits(&aref.layout, aref.dim.len()) {
+            self.report(Severity::Error, Some(id), format!("`{}` {}", name, message));
+        }
+
+        match self.arrays.get(name) {
+            None => {
+                self.arrays.insert(name.clone(), (aref.clone(), id));
+            }
+            Some((first, first_id)) => {
+                let differs = if first.dim != aref.dim {
+                    Some(format!("dimensions {:?}", aref.dim))
+                } else if first.elem != aref.elem {
+                    Some(format!("element type {:?}", aref.elem))
+                } else if first.layout != aref.layout {
+                    Some(format!("layout {:?}", aref.layout))
+                } else {
+                    None
+                };
+                if let Some(what) = differs {
+                    self.report(
+                        Severity::Error,
+                        Some(id),
+                        format!(
+                            "`{}` has {} here, unlike at reference {}",
+                            name, what, first_id
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// One more than the position of the innermost loop index an affine bound uses, 0 if none.
+fn bound_depth(bound: &LoopBound) -> Option<usize> {
+    match bound {
+        LoopBound::Fixed(_) | LoopBound::Dynamic(_) => Some(0),
+        LoopBound::Affine { a, .. } => Some(a.iter().rposition(|&c| c != 0).map_or(0, |p| p + 1)),
+        LoopBound::Min(x, y) | LoopBound::Max(x, y) => bound_depth(x).max(bound_depth(y)),
+        LoopBound::FloorDiv(x, _) | LoopBound::CeilDiv(x, _) => bound_depth(x),
+    }
+}
+
+fn layout_fits(layout: &Layout, rank: usize) -> Result<(), String> {
+    match layout {
+        Layout::Permuted(order) => {
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            if sorted.iter().copied().eq(0..rank) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "has {} dimension(s), which {:?} does not permute",
+                    rank, order
+                ))
+            }
+        }
+        Layout::Blocked(tile) if tile.len() != rank || tile.contains(&0) => Err(format!(
+            "has {} dimension(s), which {:?} are not tile sizes for",
+            rank, tile
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Runs the loop nest, without its references, to see which code is reached.
+struct Reach {
+    ivec: Vec<i32>,
+    /// Loop iterations left before giving up.
+    budget: usize,
+    /// For every loop, whether its body ran; for every branch, whether each arm was taken.
+    taken: HashMap<*const Node, [bool; 2]>,
+}
+
+impl Reach {
+    /// `None` if the budget ran out.
+    fn node(&mut self, node: &Node) -> Option<()> {
+        match &node.stmt {
+            Stmt::Ref(_) => {}
+            Stmt::Loop(aloop) => {
+                self.taken.entry(node).or_default();
+                let mut i = aloop.lb.eval(&self.ivec);
+                let ub = aloop.ub.eval(&self.ivec);
+                while (aloop.test)(i, ub) {
+                    self.budget = self.budget.checked_sub(1)?;
+                    self.taken.get_mut(&(node as *const Node)).unwrap()[0] = true;
+                    self.ivec.push(i);
+                    for child in aloop.body.borrow().iter() {
+                        self.node(child)?;
+                    }
+                    self.ivec.pop();
+                    i = (aloop.step)(i);
+                }
+            }
+            Stmt::Block(children) => {
+                for child in children {
+                    self.node(child)?;
+                }
+            }
+            Stmt::Branch(BranchStmt {
+                cond,
+                then_body,
+                else_body,
+                ..
+            }) => {
+                let arm = if cond(&self.ivec) { 0 } else { 1 };
+                self.taken.entry(node).or_default()[arm] = true;
+                match arm {
+                    0 => self.node(then_body)?,
+                    _ => {
+                        if let Some(else_body) = else_body {
+                            self.node(else_body)?;
+                        }
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Reports the loops and branch arms of `node` that were reached but never ran.
+    fn report(&self, node: &Node, loops: &mut Vec<String>, diags: &mut Vec<Diagnostic>) {
+        let taken = self.taken.get(&(node as *const Node));
+        let mut warn = |loops: &Vec<String>, message: String| {
+            diags.push(Diagnostic {
+                severity: Severity::Warning,
+                loops: loops.clone(),
+                ref_id: None,
+                message,
+            })
+        };
+        match &node.stmt {
+            Stmt::Ref(_) => {}
+            Stmt::Loop(aloop) => {
+                if let Some([false, _]) = taken {
+                    warn(loops, format!("loop `{}` never runs", aloop.iv));
+                }
+                loops.push(aloop.iv.clone());
+                for child in aloop.body.borrow().iter() {
+                    self.report(child, loops, diags);
+                }
+                loops.pop();
+            }
+            Stmt::Block(children) => {
+                for child in children {
+                    self.report(child, loops, diags);
+                }
+            }
+            Stmt::Branch(branch) => {
+                match taken {
+                    Some([false, _]) => {
+                        warn(loops, "the then arm of a branch is never taken".into())
+                    }
+                    Some([_, false]) if branch.else_body.is_some() => {
+                        warn(loops, "the else arm of a branch is never taken".into())
+                    }
+                    Some([_, false]) => {
+                        warn(loops, "the condition of a branch always holds".into())
+                    }
+                    _ => {}
+                }
+                self.report(&branch.then_body, loops, diags);
+                if let Some(else_body) = &branch.else_body {
+                    self.report(else_body, loops, diags);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::affine::AffineExpr;
+    use crate::ast::ElemType;
+    use crate::{branch_node, construct, loop_node};
+    use std::rc::Rc;
+
+    fn messages(code: &Node) -> Vec<String> {
+        validate(code).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn valid() {
+        let code = crate::parse::parse(
+            "param N = 8; double A[N][N];
+             for (i = 0; i < N; i++) for (j = i; j < min(i + 4, N); j++) A[i][j] += A[j][i];",
+            &[],
+        )
+        .unwrap();
+        assert!(validate(&code).is_empty());
+    }
+
+    #[test]
+    fn scoping() {
+        // i { j = 0 to i + k { A[i][k] } } with k not a loop at all
+        let mut iloop = loop_node!("i", 0 => 4);
+        let ub = AffineExpr::new(vec![1, 0, 1], 0);
+        let mut jloop = Node::new_strided_loop("j", 0.into(), ub.into(), crate::ast::Cmp::Lt, 1);
+        let mut aref = Node::new_affine_ref(
+            "A",
+            vec![4, 4],
+            vec![AffineExpr::var(0), AffineExpr::var(2)],
+        );
+        Node::extend_loop_body(&mut jloop, &mut aref);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        // B[k] placed with `extend_loop_body` instead of `insert_at`
+        let mut bref = construct::a_ref("B", vec![4], vec!["k"]);
+        Node::extend_loop_body(&mut iloop, &mut bref);
+        assert_eq!(
+            messages(&iloop),
+            [
+                "error in loop i: the upper bound of loop `j` uses the index of loop 2, but only 1 loop(s) enclose it",
+                "error in loop i > j at reference 0: subscript 1 of `A` uses the index of loop 2, but only 2 loop(s) enclose it",
+                "error in loop i at reference 1: index `k` of `B` is not the index of an enclosing loop",
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        let refs = vec![
+            Node::new_affine_ref("A", vec![4, 4], vec![AffineExpr::constant(0); 2]),
+            Node::new_affine_ref("A", vec![4], vec![AffineExpr::constant(0)]),
+            Node::new_affine_ref("B", vec![4], vec![AffineExpr::constant(0); 2]),
+            Node::new_ref("C", vec![4], |_| vec![0]),
+            Node::new_ref("C", vec![4], |_| vec![0]).with_elem(ElemType::new(4)),
+            Node::new_ref("D", vec![4], |_| vec![0]).with_layout(Layout::Permuted(vec![1])),
+        ];
+        let block = Node::new_node(Stmt::Block(refs));
+        assert_eq!(
+            messages(&block),
+            [
+                "error at reference 1: `A` has dimensions [4] here, unlike at reference 0",
+                "error at reference 2: `B` has 1 dimension(s) but 2 subscript(s)",
+                "error at reference 4: `C` has element type Some(ElemType { size: 4, align: 4, pad: 0, c_type: None }) here, unlike at reference 3",
+                "error at reference 5: `D` has 1 dimension(s), which [1] does not permute",
+            ]
+        );
+    }
+
+    #[test]
+    fn reachability() {
+        // i = 0 to 10 { if (i < 0) { A[i] } else { A[i] }; j = 5 to 5 { A[j] }; if (i >= 0) { A[i] } }
+        let aref = || Node::new_affine_ref("A", vec![10], vec![AffineExpr::var(0)]);
+        let mut iloop = loop_node!("i", 0 => 10);
+        let (then_ref, else_ref, always_ref) = (aref(), aref(), aref());
+        let mut never = branch_node! {
+            if (|iv: &[i32]| iv[0] < 0) {
+                then_ref
+            } else {
+                else_ref
+            }
+        };
+        let mut always = branch_node! {
+            if (|iv: &[i32]| iv[0] >= 0) {
+                always_ref
+            }
+        };
+        let mut jloop = loop_node!("j", 5 => 5);
+        Node::extend_loop_body(&mut jloop, &mut aref());
+        Node::extend_loop_body(&mut iloop, &mut never);
+        Node::extend_loop_body(&mut iloop, &mut jloop);
+        Node::extend_loop_body(&mut iloop, &mut always);
+        assert_eq!(
+            messages(&iloop),
+            [
+                "warning in loop i: the then arm of a branch is never taken",
+                "warning in loop i: loop `j` never runs",
+                "warning in loop i: the condition of a branch always holds",
+            ]
+        );
+
+        // too many iterations to run
+        let mut big = loop_node!("i", 0 => REACH_LIMIT as i32 + 1);
+        Node::extend_loop_body(&mut big, &mut Rc::clone(&never));
+        assert!(validate(&big).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid program:\nerror at reference 1")]
+    fn assert_valid_panics() {
+        let refs = vec![
+            Node::new_ref("A", vec![4, 4], |_| vec![0, 0]),
+            Node::new_ref("A", vec![4], |_| vec![0]),
+        ];
+        assert_valid(&Node::new_node(Stmt::Block(refs)));
+    }
+}
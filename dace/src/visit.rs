@@ -0,0 +1,343 @@
+//! Traversals of a loop tree with the recursion written once.
+//!
+//! A `Visitor` reads a tree and a `Folder` builds a new one from it. Both get, at every node,
+//! the `Scope` of the node: the loops and branch arms enclosing it. Each method has a default
+//! that recurses into the children, so an analysis overrides only the kinds of node it is
+//! about, e.g. `visit_ref`, and still reaches the references in every loop, block and branch
+//! arm. An override that wants the children too calls the matching `walk_*` or `fold_*`
+//! function, before or after its own work.
+//!
+//! # Examples
+//! ```rust
+//! use dace::ast::AryRef;
+//! use dace::visit::{visit, Scope, Visitor};
+//!
+//! /// The depth of every reference.
+//! struct Depths(Vec<usize>);
+//!
+//! impl Visitor for Depths {
+//!     fn visit_ref(&mut self, _aref: &AryRef, scope: &Scope) {
+//!         self.0.push(scope.loops.len());
+//!     }
+//! }
+//!
+//! let code = dace::parse::parse(
+//!     "double A[4][4]; for (i = 0; i < 4; i++) { A[i][0] = 0; for (j = 0; j < 4; j++) A[i][j] += 1; }",
+//!     &[],
+//! )
+//! .unwrap();
+//! let mut depths = Depths(vec![]);
+//! visit(&mut depths, &code);
+//! assert_eq!(depths.0, [1, 2, 2]);
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::{AryRef, BranchStmt, LoopStmt, Node, Stmt};
+
+/// What encloses a node, outermost first.
+#[derive(Debug, Clone, Default)]
+pub struct Scope<'a> {
+    pub loops: Vec<&'a LoopStmt>,
+    /// The enclosing branches, each with whether the node is in its then arm.
+    pub branches: Vec<(&'a BranchStmt, bool)>,
+}
+
+impl<'a> Scope<'a> {
+    /// The indices of the enclosing loops.
+    pub fn ivs(&self) -> Vec<String> {
+        self.loops.iter().map(|aloop| aloop.iv.clone()).collect()
+    }
+
+    /// Whether the node runs at iteration `ivec` of the enclosing loops, as far as the
+    /// enclosing branches are concerned.
+    pub fn reached(&self, ivec: &[i32]) -> bool {
+        self.branches
+            .iter()
+            .all(|(branch, then)| (branch.cond)(ivec) == *then)
+    }
+
+    fn in_loop<'b>(&self, aloop: &'b LoopStmt) -> Scope<'b>
+    where
+        'a: 'b,
+    {
+        let mut scope: Scope<'b> = self.clone();
+        scope.loops.push(aloop);
+        scope
+    }
+
+    fn in_arm<'b>(&self, branch: &'b BranchStmt, then: bool) -> Scope<'b>
+    where
+        'a: 'b,
+    {
+        let mut scope: Scope<'b> = self.clone();
+        scope.branches.push((branch, then));
+        scope
+    }
+}
+
+/// Reads a loop tree, see the module documentation.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Rc<Node>, scope: &Scope) {
+        walk_node(self, node, scope)
+    }
+
+    fn visit_loop(&mut self, aloop: &LoopStmt, scope: &Scope) {
+        walk_loop(self, aloop, scope)
+    }
+
+    fn visit_ref(&mut self, _aref: &AryRef, _scope: &Scope) {}
+
+    fn visit_block(&mut self, children: &[Rc<Node>], scope: &Scope) {
+        walk_block(self, children, scope)
+    }
+
+    fn visit_branch(&mut self, branch: &BranchStmt, scope: &Scope) {
+        walk_branch(self, branch, scope)
+    }
+}
+
+/// Visits the tree rooted at `root`, which no loop or branch encloses.
+pub fn visit<V: Visitor + ?Sized>(visitor: &mut V, root: &Rc<Node>) {
+    visitor.visit_node(root, &Scope::default())
+}
+
+/// Calls the `Visitor` method for the kind of `node`.
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Rc<Node>, scope: &Scope) {
+    match &node.stmt {
+        Stmt::Loop(aloop) => visitor.visit_loop(aloop, scope),
+        Stmt::Ref(aref) => visitor.visit_ref(aref, scope),
+        Stmt::Block(children) => visitor.visit_block(children, scope),
+        Stmt::Branch(branch) => visitor.visit_branch(branch, scope),
+    }
+}
+
+/// Visits the body of `aloop`, in a scope with `aloop` added.
+pub fn walk_loop<V: Visitor + ?Sized>(visitor: &mut V, aloop: &LoopStmt, scope: &Scope) {
+    let scope = scope.in_loop(aloop);
+    for child in aloop.body.borrow().iter() {
+        visitor.visit_node(child, &scope);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, children: &[Rc<Node>], scope: &Scope) {
+    for child in children {
+        visitor.visit_node(child, scope);
+    }
+}
+
+/// Visits the then arm, then the else arm if there is one, each in a scope with its arm of
+/// `branch` added.
+pub fn walk_branch<V: Visitor + ?Sized>(visitor: &mut V, branch: &BranchStmt, scope: &Scope) {
+    visitor.visit_node(&branch.then_body, &scope.in_arm(branch, true));
+    if let Some(else_body) = &branch.else_body {
+        visitor.visit_node(else_body, &scope.in_arm(branch, false));
+    }
+}
+
+/// Builds a new loop tree from one, see the module documentation.
+///
+/// By default every node is copied, so the folded tree shares no node with the original and
+/// can be given to the tracers or transforms on its own. A method may return a node of a
+/// different kind, e.g. an empty `Stmt::Block` to drop a reference.
+pub trait Folder {
+    fn fold_node(&mut self, node: &Rc<Node>, scope: &Scope) -> Rc<Node> {
+        fold_node(self, node, scope)
+    }
+
+    fn fold_loop(&mut self, aloop: &LoopStmt, scope: &Scope) -> Rc<Node> {
+        fold_loop(self, aloop, scope)
+    }
+
+    fn fold_ref(&mut self, aref: &AryRef, _scope: &Scope) -> Rc<Node> {
+        Node::new_node(Stmt::Ref(aref.clone()))
+    }
+
+    fn fold_block(&mut self, children: &[Rc<Node>], scope: &Scope) -> Rc<Node> {
+        fold_block(self, children, scope)
+    }
+
+    fn fold_branch(&mut self, branch: &BranchStmt, scope: &Scope) -> Rc<Node> {
+        fold_branch(self, branch, scope)
+    }
+}
+
+/// Folds the tree rooted at `root`, which no loop or branch encloses.
+pub fn fold<F: Folder + ?Sized>(folder: &mut F, root: &Rc<Node>) -> Rc<Node> {
+    folder.fold_node(root, &Scope::default())
+}
+
+/// Calls the `Folder` method for the kind of `node`.
+pub fn fold_node<F: Folder + ?Sized>(folder: &mut F, node: &Rc<Node>, scope: &Scope) -> Rc<Node> {
+    match &node.stmt {
+        Stmt::Loop(aloop) => folder.fold_loop(aloop, scope),
+        Stmt::Ref(aref) => folder.fold_ref(aref, scope),
+        Stmt::Block(children) => folder.fold_block(children, scope),
+        Stmt::Branch(branch) => folder.fold_branch(branch, scope),
+    }
+}
+
+/// A loop with the header of `aloop` and its body folded, in a scope with `aloop` added.
+pub fn fold_loop<F: Folder + ?Sized>(folder: &mut F, aloop: &LoopStmt, scope: &Scope) -> Rc<Node> {
+    let mut copy = Node::new_node(Stmt::Loop(LoopStmt {
+        iv: aloop.iv.clone(),
+        lb: aloop.lb.clone(),
+        ub: aloop.ub.clone(),
+        test: Rc::clone(&aloop.test),
+        step: Rc::clone(&aloop.step),
+        cmp: aloop.cmp,
+        stride: aloop.stride,
+        body: RefCell::new(vec![]),
+    }));
+    let scope = scope.in_loop(aloop);
+    for child in aloop.body.borrow().iter() {
+        Node::extend_loop_body(&mut copy, &mut folder.fold_node(child, &scope));
+    }
+    copy
+}
+
+pub fn fold_block<F: Folder + ?Sized>(
+    folder: &mut F,
+    children: &[Rc<Node>],
+    scope: &Scope,
+) -> Rc<Node> {
+    Node::adopt_children(Node::new_node(Stmt::Block(
+        children
+            .iter()
+            .map(|child| folder.fold_node(child, scope))
+            .collect(),
+    )))
+}
+
+/// A branch with the condition of `branch` and its arms folded.
+pub fn fold_branch<F: Folder + ?Sized>(
+    folder: &mut F,
+    branch: &BranchStmt,
+    scope: &Scope,
+) -> Rc<Node> {
+    let then_body = folder.fold_node(&branch.then_body, &scope.in_arm(branch, true));
+    let else_body = branch
+        .else_body
+        .as_ref()
+        .map(|body| folder.fold_node(body, &scope.in_arm(branch, false)));
+    Node::adopt_children(Node::new_node(Stmt::Branch(BranchStmt {
+        cond: Rc::clone(&branch.cond),
+        pred: branch.pred.clone(),
+        then_body,
+        else_body,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::AccessKind;
+    use crate::parse::parse;
+
+    fn code() -> Rc<Node> {
+        parse(
+            "double A[8][8]; double x[8];
+             for (i = 0; i < 8; i++) {
+               x[i] = 0;
+               for (j = 0; j < 8; j++)
+                 if (i < j) x[i] += A[i][j]; else A[i][j] = x[j];
+             }",
+            &[],
+        )
+        .unwrap()
+    }
+
+    /// The indices and arms enclosing every reference.
+    struct Scopes(Vec<(String, Vec<String>, Vec<bool>)>);
+
+    impl Visitor for Scopes {
+        fn visit_ref(&mut self, aref: &AryRef, scope: &Scope) {
+            let arms = scope.branches.iter().map(|&(_, then)| then).collect();
+            self.0.push((aref.name.clone(), scope.ivs(), arms));
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let mut scopes = Scopes(vec![]);
+        visit(&mut scopes, &code());
+        let arms: Vec<(&str, usize, &[bool])> = scopes
+            .0
+            .iter()
+            .map(|(name, ivs, arms)| (name.as_str(), ivs.len(), arms.as_slice()))
+            .collect();
+        assert_eq!(
+            arms,
+            [
+                ("x", 1, &[][..]),
+                ("A", 2, &[true][..]),
+                ("x", 2, &[true][..]),
+                ("x", 2, &[true][..]),
+                ("x", 2, &[false][..]),
+                ("A", 2, &[false][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reached() {
+        // counts the iterations at which each arm runs
+        struct Arms(Vec<usize>);
+        impl Visitor for Arms {
+            fn visit_ref(&mut self, aref: &AryRef, scope: &Scope) {
+                if aref.kind == AccessKind::Write && scope.branches.len() == 1 {
+                    let runs = (0..8)
+                        .flat_map(|i| (0..8).map(move |j| [i, j]))
+                        .filter(|ivec| scope.reached(ivec))
+                        .count();
+                    self.0.push(runs);
+                }
+            }
+        }
+        let mut arms = Arms(vec![]);
+        visit(&mut arms, &code());
+        assert_eq!(arms.0, [28, 36]);
+    }
+
+    #[test]
+    fn folder() {
+        let code = code();
+        // the default folder copies the tree
+        struct Copy;
+        impl Folder for Copy {}
+        let copy = fold(&mut Copy, &code);
+        assert!(copy.structural_eq(&code));
+        assert!(!Rc::ptr_eq(&copy, &code));
+
+        // drops the writes in the else arm
+        struct DropElseWrites;
+        impl Folder for DropElseWrites {
+            fn fold_ref(&mut self, aref: &AryRef, scope: &Scope) -> Rc<Node> {
+                match (aref.kind, scope.branches.last()) {
+                    (AccessKind::Write, Some((_, false))) => Node::new_node(Stmt::Block(vec![])),
+                    _ => Node::new_node(Stmt::Ref(aref.clone())),
+                }
+            }
+        }
+        let folded = fold(&mut DropElseWrites, &code);
+        let mut scopes = Scopes(vec![]);
+        visit(&mut scopes, &folded);
+        assert_eq!(scopes.0.len(), 5);
+        assert_eq!(scopes.0[4].0, "x");
+        // the loop bodies are linked to the new loops
+        let Stmt::Loop(aloop) = &folded.stmt else {
+            panic!("the root is not a loop")
+        };
+        let child = &aloop.body.borrow()[1];
+        assert!(Rc::ptr_eq(
+            &child.parent.borrow().upgrade().unwrap(),
+            &folded
+        ));
+        // and so are the blocks and branch arms
+        for (node, parent) in crate::iter::ParentWalk::new(&folded).skip(1) {
+            let linked = node.parent.borrow().upgrade().unwrap();
+            assert!(Rc::ptr_eq(&linked, &parent.unwrap()));
+        }
+    }
+}
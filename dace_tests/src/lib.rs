@@ -3,6 +3,7 @@ use std::rc::Rc;
 
 use dace::ast::Node;
 
+pub mod dsl;
 //pub mod polybench;
 pub mod polybench_simplify;
 
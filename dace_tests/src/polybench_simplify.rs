@@ -4,13 +4,14 @@
 
 use std::rc::Rc;
 
+use dace::ast::AccessKind;
 #[allow(unused_imports)]
 use dace::ast::LoopBound::{Affine, Dynamic, Fixed};
 use dace::ast::Node;
 use dace::ast::Stmt;
 use dace::construct::{
-    a_ref, create_loops, generate_sub_2, generate_subscript, insert_at, insert_at_innermost,
-    loop_body, nested_loops,
+    a_ref, affine_sub, create_loops, generate_subscript, insert_at, insert_at_innermost, loop_body,
+    nested_loops,
 };
 use dace::{branch_node, loop_node};
 
@@ -300,12 +301,13 @@ pub fn syr2d(n: usize, m: usize) -> Rc<Node> {
 pub fn gemm(n: usize) -> Rc<Node> {
     let loop_indices = &["i", "j", "k"];
 
-    let ary_sub = |loops| generate_sub_2(loop_indices, loops);
-    // let ary_sub = |loops| generate_sub(loops, loop_indices);
+    let ary_sub = |subs: &[&str]| affine_sub(subs, loop_indices);
 
-    let A0 = Node::new_ref("A", vec![n, n], ary_sub(&["i", "k"]));
-    let B0 = Node::new_ref("B", vec![n, n], ary_sub(&["k", "j"]));
-    let C0 = Node::new_ref("C", vec![n, n], ary_sub(&["i", "j"]));
+    let A0 = Node::new_affine_ref("A", vec![n, n], ary_sub(&["i", "k"]));
+    let B0 = Node::new_affine_ref("B", vec![n, n], ary_sub(&["k", "j"]));
+    let C0 = Node::new_affine_ref("C", vec![n, n], ary_sub(&["i", "j"]));
+    let C1 =
+        Node::new_affine_ref("C", vec![n, n], ary_sub(&["i", "j"])).with_kind(AccessKind::Write);
 
     let ubound = n as i32;
 
@@ -321,7 +323,7 @@ pub fn gemm(n: usize) -> Rc<Node> {
         &mut j_loop_ref.clone(),
         &mut C0.clone(),
         &mut j_loop_ref.clone(),
-        &mut C0.clone(),
+        &mut C1.clone(),
     ]);
 
     insert_at(&mut k_loop_ref, &mut j_loop_ref, "j");
@@ -334,7 +336,7 @@ pub fn gemm(n: usize) -> Rc<Node> {
         &mut k_loop_ref.clone(),
         &mut C0.clone(),
         &mut k_loop_ref.clone(),
-        &mut C0.clone(),
+        &mut C1.clone(),
     ]);
 
     i_loop_ref
@@ -454,7 +456,7 @@ pub fn _2mm(NI: usize, NJ: usize, NK: usize, NL: usize) -> Rc<Node> {
 pub fn cholesky(n: usize) -> Rc<Node> {
     let loop_indices = vec!["i", "j", "k"];
     let bounds = |loop_index| generate_subscript(&loop_indices, loop_index);
-    let ary_sub = |loops| generate_sub_2(&loop_indices, loops);
+    let ary_sub = |subs: &[&str]| affine_sub(subs, &loop_indices);
 
     let ubound = n as i32;
 
@@ -490,8 +492,8 @@ pub fn cholesky(n: usize) -> Rc<Node> {
     insert_at(&mut k2_loop_ref, &mut i_loop_ref, "i");
 
     //create A[i * N + i] -= A[i * N + k] * A[i * N + k];
-    let s_ref_aii1 = Node::new_ref("a", vec![n], ary_sub(&["i"]));
-    let s_ref_aik2 = Node::new_ref("a", vec![n, n], ary_sub(&["i", "k"]));
+    let s_ref_aii1 = Node::new_affine_ref("a", vec![n], ary_sub(&["i"]));
+    let s_ref_aik2 = Node::new_affine_ref("a", vec![n, n], ary_sub(&["i", "k"]));
 
     loop_body(&[
         &mut k2_loop_ref.clone(),
@@ -1237,7 +1239,7 @@ pub fn gemver(n: usize) -> Rc<Node> {
         a_ref("u1", vec![n], vec!["i"]),
         a_ref("v1", vec![n], vec!["j"]),
         a_ref("u2", vec![n], vec!["i"]),
-        a_ref("v2", vec![n, n], vec!["j"]),
+        a_ref("v2", vec![n], vec!["j"]),
         a_ref("a2", vec![n, n], vec!["i", "j"]),
     ];
     for node in a1_u1_v1_u2_v2_a2.iter_mut() {
@@ -1273,7 +1275,7 @@ pub fn gemver(n: usize) -> Rc<Node> {
         a_ref("w1", vec![n], vec!["i"]),
         a_ref("a4", vec![n, n], vec!["i", "j"]),
         a_ref("x5", vec![n], vec!["j"]),
-        a_ref("w2", vec![n, n], vec!["i"]),
+        a_ref("w2", vec![n], vec!["i"]),
     ];
     for node in w1_a4_x5_w2.iter_mut() {
         insert_at_innermost(node, &mut i_loop4);
@@ -1284,8 +1286,21 @@ pub fn gemver(n: usize) -> Rc<Node> {
 
 #[cfg(test)]
 mod tests {
+    use dace::program::Program;
+    use dace::transform;
+    use static_rd::LRUSplay;
+
     use super::*;
 
+    #[test]
+    fn lu_ref_sites() {
+        // the same A[i][j] reference appears at several sites, each with its own id
+        let program = Program::new(&lu(4));
+        let ids: Vec<Option<usize>> = program.refs().map(|r| program.ref_id(r)).collect();
+        assert_eq!(ids, (0..11).map(Some).collect::<Vec<_>>());
+        assert!(program.refs().all(|r| program.base(r) == Some(0)));
+    }
+
     #[test]
     fn trmm_trace_test() {
         assert_eq!(trmm_trace(1024, 1024).node_count(), 8);
@@ -1298,6 +1313,32 @@ mod tests {
         mvt(1024).print_structure(0);
     }
 
+    /// Fuses the first two nests of `code`, outer loops and then their single inner loops.
+    fn fuse_first_nests(code: &Rc<Node>) -> Rc<Node> {
+        let program = Program::new(code);
+        let nests = program.children(program.root()).to_vec();
+        let fused = transform::fuse(&program, nests[0], nests[1]).unwrap();
+        let inner = fused.children(fused.children(fused.root())[0]).to_vec();
+        let fused = transform::fuse(&fused, inner[0], inner[1]).unwrap();
+        fused.root_node().clone()
+    }
+
+    #[test]
+    fn fused_rd() {
+        for code in [mvt(10), gemver(10)] {
+            let mut fused = fuse_first_nests(&code);
+            assert_eq!(fused.node_count(), code.node_count() - 2);
+            let (before, _, _) =
+                static_rd::trace::trace(&mut code.deep_clone(), 8, 8, LRUSplay::new());
+            let (after, _, _) = static_rd::trace::trace(&mut fused, 8, 8, LRUSplay::new());
+            assert_eq!(
+                after.hist.values().sum::<usize>(),
+                before.hist.values().sum::<usize>()
+            );
+            assert_eq!(after.hist.get(&None), before.hist.get(&None));
+        }
+    }
+
     #[test]
     fn test_trisolv() {
         assert_eq!(trisolv(1024).node_count(), 11);
@@ -1322,7 +1363,7 @@ mod tests {
         let mut trace = gemm(32);
         let start = Instant::now();
         // let hist = static_rd::trace::trace(&mut trace);
-        let result = static_rd::trace::trace(&mut trace, static_rd::LRUSplay::new());
+        let result = static_rd::trace::trace(&mut trace, 8, 8, static_rd::LRUSplay::new());
         let hist = result.0;
         let end = Instant::now();
         println!("gemm trace time: {:?}", end - start);
@@ -1335,12 +1376,27 @@ mod tests {
         let mut trace = gemm(32);
         let start = Instant::now();
         // let hist = static_rd::trace::trace(&mut trace);
-        let hist = static_rd::trace::trace(&mut trace, static_rd::LRUScaleTree::new(0.1, 10000)).0;
+        let hist =
+            static_rd::trace::trace(&mut trace, 8, 8, static_rd::LRUScaleTree::new(0.1, 10000)).0;
         let end = Instant::now();
         println!("gemm trace time: {:?}", end - start);
         println!("hist: {}", hist);
     }
 
+    #[test]
+    fn test_gemm_rd_histogram_only() {
+        let mut trace = gemm(32);
+        // only the histogram, in memory proportional to the footprint
+        let config = static_rd::trace::TraceConfig::new(dace::exec::TraceOptions::new(8));
+        let program = dace::program::Program::new(&trace);
+        let out =
+            static_rd::trace::trace_with(&program, config, static_rd::LRUSplay::new()).unwrap();
+        assert!(out.data_accesses.get_vec().is_empty());
+        assert!(out.dist_rd.get_vec().is_empty());
+        let hist = static_rd::trace::trace(&mut trace, 8, 8, static_rd::LRUSplay::new()).0;
+        assert_eq!(out.hist.to_vec(), hist.to_vec());
+    }
+
     #[test]
     fn _2mm_test() {
         assert_eq!(_2mm(1024, 1024, 1024, 1024).node_count(), 15);
@@ -3,6 +3,8 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+pub mod mrc;
+
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Hist {
     pub hist: HashMap<Option<usize>, usize>,
@@ -23,7 +25,7 @@ impl Hist {
 
     pub fn to_vec(&self) -> Vec<(Option<usize>, usize)> {
         let mut hvec: Vec<_> = self.hist.iter().map(|(&k, &v)| (k, v)).collect();
-        hvec.sort_by(|a, b| a.0.cmp(&b.0));
+        hvec.sort_by_key(|a| a.0);
         hvec
     }
 }
@@ -0,0 +1,141 @@
+//! Miss-ratio curves, as `(cache_size, miss_ratio)` points by increasing cache size, from
+//! 0 to the size past which only first accesses miss. Cache sizes are in blocks, the unit
+//! the histogram was traced in, e.g. cache lines.
+
+use std::io::{self, Write};
+
+use crate::Hist;
+
+/// The exact miss-ratio curve of a fully associative LRU cache, from a reuse distance
+/// histogram. A distance counts the distinct blocks accessed since the previous access to
+/// the same block, that one included, so an access hits in a cache of at least that many
+/// blocks. First accesses (`None`) always miss.
+pub fn from_rd(rd: &Hist) -> Vec<(usize, f64)> {
+    let total: usize = rd.hist.values().sum();
+    if total == 0 {
+        return vec![];
+    }
+    let hvec = rd.to_vec();
+    let max = hvec.last().and_then(|&(d, _)| d).unwrap_or(0);
+    // misses at size c: the first accesses and the distances above c
+    let mut misses = total;
+    let mut dists = hvec.iter().filter(|(d, _)| d.is_some()).peekable();
+    (0..=max)
+        .map(|c| {
+            while let Some(&&(Some(d), n)) = dists.peek() {
+                if d > c {
+                    break;
+                }
+                misses -= n;
+                dists.next();
+            }
+            (c, misses as f64 / total as f64)
+        })
+        .collect()
+}
+
+/// The approximate miss-ratio curve of a fully associative LRU cache, from a reuse interval
+/// histogram, by the average eviction time model of Hu et al., equivalent to the footprint
+/// theory of Xiang et al. For a cache of `c` blocks, the eviction time `T` is when the
+/// expected number of blocks accessed since a block's last access reaches `c`, i.e. the sum
+/// of `P(t)` for `t < T` is `c`, where `P(t)` is the fraction of accesses whose interval is
+/// longer than `t` (first accesses having no interval). The miss ratio is `P(T)`.
+pub fn from_ri(ri: &Hist) -> Vec<(usize, f64)> {
+    let total: usize = ri.hist.values().sum();
+    if total == 0 {
+        return vec![];
+    }
+    let ratio = |longer: usize| longer as f64 / total as f64;
+
+    // P(t) is constant between consecutive intervals: `longer` accesses have an interval
+    // past `start`, until the next interval
+    let mut mrc = vec![(0, 1.0)];
+    let mut longer = total;
+    let mut start = 0;
+    let mut sum = 0.0;
+    for (interval, n) in ri.to_vec() {
+        let Some(end) = interval else {
+            continue;
+        };
+        let p = ratio(longer);
+        let next = ratio(longer - n);
+        let reach = sum + p * (end - start) as f64;
+        let mut c = mrc.len();
+        while c as f64 <= reach {
+            let t = start as f64 + ((c as f64 - sum) / p).ceil();
+            mrc.push((c, if t < end as f64 { p } else { next }));
+            c += 1;
+        }
+        longer -= n;
+        start = end;
+        sum = reach;
+    }
+    // past the longest interval, only the first accesses miss
+    if mrc.last().unwrap().1 > ratio(longer) {
+        mrc.push((mrc.len(), ratio(longer)));
+    }
+    mrc
+}
+
+/// Writes `mrc` as CSV with a `cache_size,miss_ratio` header.
+pub fn write_csv(mrc: &[(usize, f64)], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "cache_size,miss_ratio")?;
+    for (size, ratio) in mrc {
+        writeln!(out, "{},{}", size, ratio)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hist(dists: &[(Option<usize>, usize)]) -> Hist {
+        let mut h = Hist::new();
+        for &(d, n) in dists {
+            (0..n).for_each(|_| h.add_dist(d));
+        }
+        h
+    }
+
+    #[test]
+    fn lru() {
+        // a cyclic scan of 4 blocks, 3 times
+        let rd = hist(&[(None, 4), (Some(4), 8)]);
+        let mrc = from_rd(&rd);
+        assert_eq!(mrc.len(), 5);
+        assert_eq!(mrc[3], (3, 1.0));
+        assert_eq!(mrc[4], (4, 4.0 / 12.0));
+
+        let rd = hist(&[(None, 2), (Some(1), 4), (Some(3), 2)]);
+        let ratios: Vec<f64> = from_rd(&rd).into_iter().map(|(_, r)| r).collect();
+        assert_eq!(ratios, [1.0, 0.5, 0.5, 0.25]);
+        assert!(from_rd(&Hist::new()).is_empty());
+    }
+
+    #[test]
+    fn aet() {
+        // a cyclic scan of 4 blocks: every interval is 4, the step is exact at c = 4
+        let ri = hist(&[(None, 4), (Some(4), 8)]);
+        let mrc = from_ri(&ri);
+        assert_eq!(
+            mrc,
+            [(0, 1.0), (1, 1.0), (2, 1.0), (3, 1.0), (4, 4.0 / 12.0)]
+        );
+
+        // one block reused every access: it fits in a cache of 1
+        let ri = hist(&[(None, 1), (Some(1), 9)]);
+        assert_eq!(from_ri(&ri), [(0, 1.0), (1, 0.1)]);
+        assert!(from_ri(&Hist::new()).is_empty());
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = vec![];
+        write_csv(&[(0, 1.0), (1, 0.25)], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "cache_size,miss_ratio\n0,1\n1,0.25\n"
+        );
+    }
+}
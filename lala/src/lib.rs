@@ -1,107 +1,77 @@
-#![allow(internal_features)] // to hide the warning of using unstable features for the line below
-#![feature(core_intrinsics)]
-#![feature(get_mut_unchecked)]
 #![allow(dead_code)]
 
 use std::rc::Rc;
 
-use dace::ast::{AryRef, Node, Stmt};
+use dace::ast::{AryRef, Node};
+use dace::visit::{visit, Scope, Visitor};
 
-fn count_arr_refs(node: &Node) -> usize {
-    let mut count = 0;
-
-    match &node.stmt {
-        Stmt::Ref(_) => count += 1,
-        Stmt::Loop(loop_stmt) => {
-            for child in &loop_stmt.body {
-                count += count_arr_refs(child);
-            }
-        }
-        Stmt::Block(block_stmt) => {
-            for child in block_stmt {
-                count += count_arr_refs(child);
-            }
-        }
-        _ => {}
-    }
-
-    count
+/// Counts the references, printing the reuse intervals of each if `print_ri` is set.
+struct RefCounter {
+    print_ri: bool,
+    count: usize,
 }
 
-fn print_ri_and_count_arr_refs(node: &Node) -> usize {
-    let mut count = 0;
-
-    match &node.stmt {
-        Stmt::Ref(arr_ref_stmt) => {
+impl Visitor for RefCounter {
+    fn visit_ref(&mut self, arr_ref_stmt: &AryRef, _scope: &Scope) {
+        if self.print_ri {
             println!("{}'s ri values: {:?}", arr_ref_stmt.name, arr_ref_stmt.ri);
-            count += 1;
         }
-        Stmt::Loop(loop_stmt) => {
-            for child in &loop_stmt.body {
-                count += print_ri_and_count_arr_refs(child);
-            }
-        }
-        Stmt::Block(block_stmt) => {
-            for child in block_stmt {
-                count += print_ri_and_count_arr_refs(child);
-            }
-        }
-        _ => {}
+        self.count += 1;
     }
+}
+
+fn count_arr_refs(node: &Rc<Node>) -> usize {
+    let mut counter = RefCounter {
+        print_ri: false,
+        count: 0,
+    };
+    visit(&mut counter, node);
+    counter.count
+}
 
-    count
+fn print_ri_and_count_arr_refs(node: &Rc<Node>) -> usize {
+    let mut counter = RefCounter {
+        print_ri: true,
+        count: 0,
+    };
+    visit(&mut counter, node);
+    counter.count
 }
 
-fn access_matrix(arr_ref_stmt: &mut AryRef, loops: Vec<String>) -> Vec<Vec<usize>> {
+fn access_matrix(arr_ref_stmt: &AryRef, loops: Vec<String>) -> Vec<Vec<usize>> {
     let mut matrix: Vec<Vec<usize>> = Vec::new();
-    for i in loops {
-        let mut dim: Vec<usize> = Vec::with_capacity(arr_ref_stmt.indices.len());
-        for j in arr_ref_stmt.indices.clone() {
-            if j == i {
-                dim.push(1);
-                //print!("\n{j} and {i}\n");
-            } else {
-                dim.push(0);
-            }
-        }
+    for (depth, i) in loops.into_iter().enumerate() {
+        let dim: Vec<usize> = match &arr_ref_stmt.sub_affine {
+            // a loop influences a dimension iff its coefficient in the subscript is non-zero
+            Some(subs) => subs
+                .iter()
+                .map(|e| (e.coeff(depth) != 0) as usize)
+                .collect(),
+            None => arr_ref_stmt
+                .indices
+                .iter()
+                .map(|j| (*j == i) as usize)
+                .collect(),
+        };
         matrix.push(dim);
     }
     print!("{:?}\n\n", matrix);
     matrix
 }
 
-fn matrix_production(node: &mut Rc<Node>, loops: &mut Vec<String>) -> Vec<Vec<Vec<usize>>> {
-    let node = unsafe { Rc::get_mut_unchecked(node) };
-    let mut matrix: Vec<Vec<Vec<usize>>> = Vec::new();
-
-    match &mut node.stmt {
-        Stmt::Ref(arr_ref_stmt) => {
-            // call some other function which returns Vec<Vec<usize>> which is then pushed to matrix
-            let access_matrix = access_matrix(arr_ref_stmt, loops.to_vec());
-            matrix.push(access_matrix.clone());
-            arr_ref_stmt.access_matrix.push(access_matrix);
-            //return matrix;
-            //print!("MATRIX TEST {:?}\n\n", matrix);
-        }
-        Stmt::Loop(loop_stmt) => {
-            loops.push(loop_stmt.iv.clone());
-            for child in &mut loop_stmt.body {
-                matrix.extend(matrix_production(child, loops));
-            }
-            // loops.pop();
-        }
-        Stmt::Block(block_stmt) => {
-            for child in block_stmt {
-                matrix.extend(matrix_production(child, loops));
-            }
-        }
-        _ => {}
+/// The access matrix of every reference, in program order.
+struct MatrixProduction(Vec<Vec<Vec<usize>>>);
+
+impl Visitor for MatrixProduction {
+    fn visit_ref(&mut self, arr_ref_stmt: &AryRef, scope: &Scope) {
+        self.0.push(access_matrix(arr_ref_stmt, scope.ivs()));
     }
+}
 
-    // print the node and the matrix
-    // println!("Node: {:?}", node);
-    // println!("Matrix: {:?}\n", matrix);
-    matrix
+fn matrix_production(node: &Rc<Node>) -> Vec<Vec<Vec<usize>>> {
+    let mut matrixes = MatrixProduction(vec![]);
+    visit(&mut matrixes, node);
+    matrixes.0
 }
 
 fn find_locality_position(matrix: Vec<Vec<usize>>) -> i32 {
@@ -121,7 +91,7 @@ fn generalized_determine_reuse_intervals(matrixes: Vec<Vec<Vec<usize>>>, referen
     for (ref_index, reference) in references.iter().enumerate() {
         let access_vector: Vec<usize> = matrixes[ref_index]
             .iter()
-            .map(|dim| if dim.iter().any(|&x| x == 1) { 1 } else { 0 })
+            .map(|dim| if dim.contains(&1) { 1 } else { 0 })
             .collect();
 
         let locality_position: i32 = find_locality_position(matrixes[ref_index].clone());
@@ -167,7 +137,8 @@ fn generalized_determine_reuse_intervals(matrixes: Vec<Vec<Vec<usize>>>, referen
 
 #[cfg(test)]
 mod tests {
-    use dace::ast::Node;
+    use dace::affine::AffineExpr;
+    use dace::ast::{Node, Stmt};
     use dace::construct;
     use dace_tests::polybench_simplify;
 
@@ -186,11 +157,9 @@ mod tests {
 
         nested_loops_top.print_structure(0);
 
-        construct::assign_ranks(&mut nested_loops_top, 0);
         let references: Vec<&str> = vec!["C"];
 
-        let loop_matrixes: Vec<Vec<Vec<usize>>> =
-            matrix_production(&mut nested_loops_top, &mut Vec::new());
+        let loop_matrixes: Vec<Vec<Vec<usize>>> = matrix_production(&nested_loops_top);
         generalized_determine_reuse_intervals(loop_matrixes, references);
     }
 
@@ -228,16 +197,13 @@ mod tests {
             .for_each(|s| Node::extend_loop_body(loop_order.last_mut().unwrap(), s));
 
         //the loops which were orignally seperate are not coalessed into eachother so that they are acutally nested
-        let mut nested_loops_top: Rc<Node> = construct::nest_the_loops(loop_order);
+        let nested_loops_top: Rc<Node> = construct::nest_the_loops(loop_order);
         let references: Vec<&str> = vec!["C", "A", "B", "D", "E", "F"];
 
         nested_loops_top.print_structure(0);
-        // rank assignment for loops
-        construct::assign_ranks(&mut nested_loops_top, 0);
         // loop matrix is found where for each array access we store essentially a 2d
         // array by dimension and if a given loop has an influnce on a respective dimension
-        let loop_matrixes: Vec<Vec<Vec<usize>>> =
-            matrix_production(&mut nested_loops_top, &mut Vec::new());
+        let loop_matrixes: Vec<Vec<Vec<usize>>> = matrix_production(&nested_loops_top);
         print!("{:?}\n\n", loop_matrixes);
         generalized_determine_reuse_intervals(loop_matrixes, references);
         //ri output
@@ -265,19 +231,17 @@ mod tests {
             .iter_mut()
             .for_each(|s| Node::extend_loop_body(loop_order.last_mut().unwrap(), s));
 
-        let mut nested_loops_top = construct::nest_the_loops(loop_order);
+        let nested_loops_top = construct::nest_the_loops(loop_order);
 
         //let arr_refs = count_arr_refs(&nested_loops_top);
 
         // let (tbl, _size) = set_arybase(&mut nested_loops_top);
         // println!("{:?}", tbl);
-        construct::assign_ranks(&mut nested_loops_top, 0);
         let references: Vec<&str> = vec!["C", "A", "B"];
 
         // loop matrix is found where for each array access we store essentially a 2d
         // array by dimension and if a given loop has an influnce on a respective dimension
-        let loop_matrixes: Vec<Vec<Vec<usize>>> =
-            matrix_production(&mut nested_loops_top, &mut Vec::new());
+        let loop_matrixes: Vec<Vec<Vec<usize>>> = matrix_production(&nested_loops_top);
         //print!("{:?}\n\n", loop_matrixes);
         generalized_determine_reuse_intervals(loop_matrixes, references);
         //ri output
@@ -289,6 +253,25 @@ mod tests {
         // print_ri_and_count_arr_refs(&nested_loops_top);
     }
 
+    #[test]
+    fn affine_matrix() {
+        let n = 10;
+        // for i, j, k { A[i+1][2*j-k] }
+        let mut nested_loops_top = construct::nested_loops(&["i", "j", "k"], n);
+        let mut ref_a = Node::new_affine_ref(
+            "A",
+            vec![n as usize, n as usize],
+            vec![
+                AffineExpr::var(0) + 1.into(),
+                AffineExpr::var(1) * 2 - AffineExpr::var(2),
+            ],
+        );
+        construct::insert_at(&mut ref_a, &mut nested_loops_top, "k");
+
+        let loop_matrixes = matrix_production(&nested_loops_top);
+        assert_eq!(loop_matrixes, [vec![vec![1, 0], vec![0, 1], vec![0, 1]]]);
+    }
+
     #[test]
     fn test_poly() {
         // let mut bench = polybench_simplify::mvt(1024); // fixed the issue with the mvt with single array
@@ -298,8 +281,7 @@ mod tests {
         // let mut bench = polybench_simplify::syrk(256, 256);
 
         // let mut bench = polybench_simplify::trmm_trace(1024, 1024);
-        let mut bench = polybench_simplify::symm(1024, 1024);
-        construct::assign_ranks(&mut bench, 0);
+        let bench = polybench_simplify::symm(1024, 1024);
         bench.print_structure(0);
         // let arr_refs = count_arr_refs(&bench);
         // calculate_reuse_intervals(&mut bench, &mut HashMap::new(), arr_refs);
@@ -308,4 +290,36 @@ mod tests {
         // let result = trace(&mut bench, LRUStack::new());
         // println!("{}", result.0);
     }
+
+    #[test]
+    fn refs_in_branches() {
+        // for i { for j { if (i < j) C[j] else { C[i]; D[j] } }; C[i] }
+        let mut i_loop = Node::new_single_loop("i", 0, 10);
+        let mut j_loop = Node::new_single_loop("j", 0, 10);
+        let then_ref = construct::a_ref("C", vec![10], vec!["j"]);
+        let else_refs = Node::new_node(Stmt::Block(vec![
+            construct::a_ref("C", vec![10], vec!["i"]),
+            construct::a_ref("D", vec![10], vec!["j"]),
+        ]));
+        let mut branch = dace::branch_node!(if (|iv: &[i32]| iv[0] < iv[1]) {
+            then_ref
+        } else {
+            else_refs
+        });
+        Node::extend_loop_body(&mut j_loop, &mut branch);
+        Node::extend_loop_body(&mut i_loop, &mut j_loop);
+        Node::extend_loop_body(&mut i_loop, &mut construct::a_ref("C", vec![10], vec!["i"]));
+
+        assert_eq!(count_arr_refs(&i_loop), 4);
+        // the last C[i] is outside the j loop
+        assert_eq!(
+            matrix_production(&i_loop),
+            [
+                vec![vec![0], vec![1]],
+                vec![vec![1], vec![0]],
+                vec![vec![0], vec![1]],
+                vec![vec![1]],
+            ]
+        );
+    }
 }
@@ -1,133 +1,300 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufWriter, Write};
 use std::rc::Rc;
 
-use dace::arybase::set_arybase;
-use dace::ast::{AryRef, LoopBound, Node, Stmt};
+use dace::ast::{AccessKind, Node};
+use dace::bounds::BoundsError;
+use dace::exec::{Access, AccessStream, TraceOptions};
+use dace::program::Program;
+use dace::validate::assert_valid;
 use hist::Hist;
 use list_serializable::ListSerializable;
 use stack_alg_sim::LRU;
 
-fn access2addr(ary_ref: &AryRef, ivec: &[i32]) -> usize {
-    let ary_index = (ary_ref.sub)(ivec);
-    if ary_index.len() != ary_ref.dim.len() {
-        panic!("array index and dimension do not match");
-    }
-
-    let offset = ary_index
-        .iter()
-        .zip(ary_ref.dim.iter())
-        .fold(0, |acc, (&i, &d)| acc * d + i);
-
-    ary_ref.base.unwrap() + offset
-}
-
-fn trace_rec_impl<T: LRU<usize>>(
-    code: &Rc<Node>,
-    ivec: &mut Vec<i32>,
-    sim: &mut T,
-    hist: &mut Hist,
-    data_accesses: &mut ListSerializable<usize>,
-    dist_rd: &mut ListSerializable<(usize, Option<usize>)>,
-) {
-    match &code.stmt {
-        Stmt::Ref(ary_ref) => {
-            let addr = access2addr(ary_ref, ivec);
-            data_accesses.add(addr);
-            let rd = sim.rec_access(addr);
-            dist_rd.add((addr, rd));
-            hist.add_dist(rd);
+/// The reuse distance histogram, the (block, distance, access kind) of every access and the
+/// block of every access. A block is a byte address divided by the cache line size.
+pub type Trace = (
+    Hist,
+    ListSerializable<(usize, Option<usize>, AccessKind)>,
+    ListSerializable<usize>,
+);
+
+/// What a trace collects besides the histogram, and how: the shared `TraceOptions`, and
+/// whether to keep or write out every access. By default only the histogram is kept, so a
+/// trace takes memory proportional to the data footprint of the program, not to its number
+/// of accesses.
+///
+/// # Examples
+/// ```rust
+/// use dace::exec::TraceOptions;
+/// use dace::program::Program;
+/// use static_rd::trace::{trace_with, TraceConfig};
+/// use static_rd::LRUSplay;
+///
+/// let code = dace::parse::parse("double A[64]; for (i = 0; i < 64; i++) A[i] += 1;", &[]).unwrap();
+/// let mut csv = vec![];
+/// let config = TraceConfig::new(TraceOptions::new(64).with_by_ref()).with_sink(&mut csv);
+/// let out = trace_with(&Program::new(&code), config, LRUSplay::new()).unwrap();
+/// assert_eq!(out.hist.to_vec(), [(None, 8), (Some(1), 120)]);
+/// assert_eq!(out.by_ref.len(), 2);
+/// assert!(out.data_accesses.get_vec().is_empty());
+/// assert_eq!(String::from_utf8(csv).unwrap().lines().nth(1), Some("0,R,0,"));
+/// ```
+pub struct TraceConfig<'a> {
+    options: TraceOptions,
+    in_memory: bool,
+    sink: Option<Box<dyn Write + 'a>>,
+}
+
+impl<'a> TraceConfig<'a> {
+    /// What `options` asks for, and nothing else.
+    pub fn new(options: TraceOptions) -> Self {
+        TraceConfig {
+            options,
+            in_memory: false,
+            sink: None,
+        }
+    }
+
+    /// Also the block and distance of every access, in `TraceOutput::dist_rd` and
+    /// `TraceOutput::data_accesses`. These grow with the number of accesses.
+    pub fn with_in_memory(mut self) -> Self {
+        self.in_memory = true;
+        self
+    }
+
+    /// Also writes every access to `sink` as it's traced, one CSV line of reference id,
+    /// access kind, block and distance, after a header line. The distance is empty for the
+    /// first access to a block. Writes are buffered.
+    pub fn with_sink(mut self, sink: impl Write + 'a) -> Self {
+        self.sink = Some(Box::new(BufWriter::new(sink)));
+        self
+    }
+}
+
+/// What a trace collected. The outputs its `TraceConfig` didn't ask for are empty.
+#[derive(Default)]
+pub struct TraceOutput {
+    pub hist: Hist,
+    /// The histogram of each reference, by `Program::ref_id`. A reference that never runs
+    /// has no histogram.
+    pub by_ref: HashMap<usize, Hist>,
+    /// The histogram of each array, by name, of the accesses of all its references.
+    pub by_array: HashMap<String, Hist>,
+    /// The histogram of each access kind. A kind that never occurs has no histogram.
+    pub by_kind: HashMap<AccessKind, Hist>,
+    pub data_accesses: ListSerializable<usize>,
+    pub dist_rd: ListSerializable<(usize, Option<usize>, AccessKind)>,
+}
+
+/// Why a trace stopped.
+#[derive(Debug)]
+pub enum TraceError {
+    /// A subscript is out of range, in checked mode.
+    Bounds(Box<BoundsError>),
+    /// Writing to the sink failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Bounds(err) => write!(f, "{}", err),
+            TraceError::Io(err) => write!(f, "cannot write the trace: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<Box<BoundsError>> for TraceError {
+    fn from(err: Box<BoundsError>) -> Self {
+        TraceError::Bounds(err)
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        TraceError::Io(err)
+    }
+}
+
+impl TraceOutput {
+    fn add<T: LRU<usize>>(
+        &mut self,
+        access: Access,
+        config: &mut TraceConfig,
+        sim: &mut T,
+    ) -> io::Result<()> {
+        let Access {
+            ref_id, aref, addr, ..
+        } = access;
+        let options = &config.options;
+        let block = addr / options.cache_line_size;
+        let rd = sim.rec_access(block);
+        self.hist.add_dist(rd);
+        if options.by_ref {
+            self.by_ref.entry(ref_id).or_default().add_dist(rd);
+        }
+        if options.by_array {
+            self.by_array
+                .entry(aref.name.clone())
+                .or_default()
+                .add_dist(rd);
+        }
+        if options.by_kind {
+            self.by_kind.entry(aref.kind).or_default().add_dist(rd);
+        }
+        if config.in_memory {
+            self.data_accesses.add(block);
+            self.dist_rd.add((block, rd, aref.kind));
+        }
+        if let Some(sink) = &mut config.sink {
+            let rd = rd.map_or(String::new(), |rd| rd.to_string());
+            writeln!(sink, "{},{},{},{}", ref_id, aref.kind, block, rd)?;
         }
-        Stmt::Loop(aloop) => {
-            let mut i = match &aloop.lb {
-                LoopBound::Fixed(lb) => *lb,
-                LoopBound::Dynamic(lb) => lb(ivec),
-                LoopBound::Affine { a, b } => {
-                    a.iter()
-                        .copied()
-                        .zip(ivec.iter().copied())
-                        .map(|(x, y)| x * y)
-                        .sum::<i32>()
-                        + *b
-                }
-            };
-            let ub = match &aloop.ub {
-                LoopBound::Fixed(ub) => *ub,
-                LoopBound::Dynamic(ub) => ub(ivec),
-                LoopBound::Affine { a, b } => {
-                    a.iter()
-                        .copied()
-                        .zip(ivec.iter().copied())
-                        .map(|(x, y)| x * y)
-                        .sum::<i32>()
-                        + *b
-                }
-            };
-
-            while (aloop.test)(i, ub) {
-                ivec.push(i);
-                for code in aloop.body.iter() {
-                    trace_rec_impl(code, ivec, sim, hist, data_accesses, dist_rd);
-                }
-                ivec.pop();
-                i = (aloop.step)(i);
+        Ok(())
+    }
+}
+
+/// Traces `program`, with the arrays where it put them, collecting what `config` asks for.
+///
+/// # Panics
+/// If `dace::validate` finds errors in the program.
+pub fn trace_with<T: LRU<usize>>(
+    program: &Program,
+    mut config: TraceConfig,
+    mut analyzer: T,
+) -> Result<TraceOutput, TraceError> {
+    assert_valid(program.root_node());
+    let mut out = TraceOutput::default();
+    if let Some(sink) = &mut config.sink {
+        writeln!(sink, "Ref ID,Kind,Block,Reuse Distance")?;
+    }
+    let stream = AccessStream::new(program);
+    match config.options.check.clone() {
+        Some(kernel) => {
+            for access in stream.checked(&kernel) {
+                out.add(access?, &mut config, &mut analyzer)?;
             }
         }
-        Stmt::Block(blk) => blk
-            .iter()
-            .for_each(|s| trace_rec_impl(s, ivec, sim, hist, data_accesses, dist_rd)),
-        Stmt::Branch(stmt) => {
-            if (stmt.cond)(ivec) {
-                trace_rec_impl(&stmt.then_body, ivec, sim, hist, data_accesses, dist_rd)
-            } else if let Some(else_body) = &stmt.else_body {
-                trace_rec_impl(else_body, ivec, sim, hist, data_accesses, dist_rd)
+        None => {
+            for access in stream {
+                out.add(access, &mut config, &mut analyzer)?;
             }
         }
     }
+    if let Some(sink) = &mut config.sink {
+        sink.flush()?;
+    }
+    Ok(out)
+}
+
+/// The output of a trace without a sink, which only fails in checked mode.
+fn without_sink(out: Result<TraceOutput, TraceError>) -> Result<TraceOutput, Box<BoundsError>> {
+    match out {
+        Ok(out) => Ok(out),
+        Err(TraceError::Bounds(err)) => Err(err),
+        Err(TraceError::Io(_)) => unreachable!("a trace without a sink writes nothing"),
+    }
+}
+
+fn unchecked(out: Result<TraceOutput, TraceError>) -> TraceOutput {
+    without_sink(out).expect("only a checked trace fails")
 }
 
-#[allow(clippy::type_complexity)]
+/// Returns the reuse distance histogram of `code` at cache line granularity, the (block,
+/// distance, access kind) of every access and the block of every access. `data_size` is the
+/// element size of the arrays that don't declare their own, see `Node::with_elem`, and
+/// `cache_line_size` the block size, both in bytes; a block size of 1 gives byte addresses.
+///
+/// With the same sizes, the accesses, blocks and cold misses are those of
+/// `static_ri::tracing_ri`, access for access.
+///
+/// # Panics
+/// If `dace::validate` finds errors in `code`. This holds for all the tracing functions.
 pub fn trace<T: LRU<usize>>(
     code: &mut Rc<Node>,
-    mut analyzer: T,
-) -> (
-    Hist,
-    ListSerializable<(usize, Option<usize>)>,
-    ListSerializable<usize>,
-) {
-    let mut accesses_count: ListSerializable<usize> = ListSerializable::<usize>::new();
-    let mut dist_rd: ListSerializable<(usize, Option<usize>)> =
-        ListSerializable::<(usize, Option<usize>)>::new();
-    let mut hist = Hist::new();
-    set_arybase(code);
+    data_size: usize,
+    cache_line_size: usize,
+    analyzer: T,
+) -> Trace {
     println!("{:?}", code);
-    trace_rec_impl(
-        code,
-        &mut Vec::<i32>::new(),
-        &mut analyzer,
-        &mut hist,
-        &mut accesses_count,
-        &mut dist_rd,
-    );
-    (hist, dist_rd, accesses_count)
+    trace_program(
+        &Program::with_elem_size(code, data_size),
+        cache_line_size,
+        analyzer,
+    )
+}
+
+/// Like `trace`, with the arrays where `program` put them, e.g. by
+/// `Program::with_placement`.
+pub fn trace_program<T: LRU<usize>>(
+    program: &Program,
+    cache_line_size: usize,
+    analyzer: T,
+) -> Trace {
+    let config = TraceConfig::new(TraceOptions::new(cache_line_size)).with_in_memory();
+    let out = unchecked(trace_with(program, config, analyzer));
+    (out.hist, out.dist_rd, out.data_accesses)
+}
+
+/// Like `trace`, but checks every subscript against its array's dimensions and stops at the
+/// first one out of range, naming `kernel` in the error.
+pub fn trace_checked<T: LRU<usize>>(
+    code: &mut Rc<Node>,
+    kernel: &str,
+    data_size: usize,
+    cache_line_size: usize,
+    analyzer: T,
+) -> Result<Trace, Box<BoundsError>> {
+    let options = TraceOptions::new(cache_line_size).with_check(kernel);
+    let config = TraceConfig::new(options).with_in_memory();
+    let program = Program::with_elem_size(code, data_size);
+    let out = without_sink(trace_with(&program, config, analyzer))?;
+    Ok((out.hist, out.dist_rd, out.data_accesses))
+}
+
+/// Like `trace`, but also returns the histogram of each access kind, e.g. to look at the
+/// reuse of stores separately. A kind that never occurs has no histogram.
+pub fn trace_by_kind<T: LRU<usize>>(
+    code: &mut Rc<Node>,
+    data_size: usize,
+    cache_line_size: usize,
+    analyzer: T,
+) -> (Hist, HashMap<AccessKind, Hist>) {
+    let config = TraceConfig::new(TraceOptions::new(cache_line_size).with_by_kind());
+    let program = Program::with_elem_size(code, data_size);
+    let out = unchecked(trace_with(&program, config, analyzer));
+    (out.hist, out.by_kind)
+}
+
+/// Like `trace`, but also returns the histogram of each reference, by `Program::ref_id`, and
+/// of each array, by name. The distance of an access is counted under its own reference and
+/// array, whatever the access it reuses.
+pub fn trace_by_ref<T: LRU<usize>>(
+    code: &mut Rc<Node>,
+    data_size: usize,
+    cache_line_size: usize,
+    analyzer: T,
+) -> (Hist, HashMap<usize, Hist>, HashMap<String, Hist>) {
+    let options = TraceOptions::new(cache_line_size)
+        .with_by_ref()
+        .with_by_array();
+    let config = TraceConfig::new(options);
+    let program = Program::with_elem_size(code, data_size);
+    let out = unchecked(trace_with(&program, config, analyzer));
+    (out.hist, out.by_ref, out.by_array)
 }
 
 #[cfg(test)]
 mod test {
+    use dace::arybase::Placement;
     use stack_alg_sim::stack::LRUStack;
 
     use super::*;
 
-    #[test]
-    fn test_access2addr() {
-        let mut aij_node =
-            Node::new_ref("x", vec![10, 10], |ij| vec![ij[0] as usize, ij[1] as usize]);
-        let mutable = unsafe { Rc::get_mut_unchecked(&mut aij_node) };
-        *mutable.ref_only_mut_ref(|a| &mut a.base).unwrap() = Some(0);
-        if let Stmt::Ref(aij) = &aij_node.stmt {
-            assert_eq!(access2addr(aij, &[0, 0]), 0);
-            assert_eq!(access2addr(aij, &[9, 9]), 99);
-        }
-    }
-
     #[test]
     fn loop_a_i() {
         // i = 0, 10 { a[i] }
@@ -135,7 +302,7 @@ mod test {
         let mut aloop = Node::new_single_loop("i", 0, 10);
         Node::extend_loop_body(&mut aloop, &mut aref);
 
-        let result = trace(&mut aloop, LRUStack::new());
+        let result = trace(&mut aloop, 8, 8, LRUStack::new());
         let hist = result.0;
         assert_eq!(hist.to_vec()[0], (None, 10));
         println!("{}", hist);
@@ -148,10 +315,194 @@ mod test {
         let mut aloop = Node::new_single_loop("i", 0, 10);
         Node::extend_loop_body(&mut aloop, &mut aref);
 
-        let result = trace(&mut aloop, LRUStack::new());
+        let result = trace(&mut aloop, 8, 8, LRUStack::new());
         let hist = result.0;
         assert_eq!(hist.to_vec()[1], (Some(1), 9));
         assert_eq!(hist.to_vec()[0], (None, 1));
         println!("{}", hist);
     }
+
+    #[test]
+    fn split_by_kind() {
+        // i = 0, 10 { a[i] = a[i] + 1 }
+        let mut load = Node::new_ref("A", vec![10], |i| vec![i[0] as usize]);
+        let mut store =
+            Node::new_ref("A", vec![10], |i| vec![i[0] as usize]).with_kind(AccessKind::Write);
+        let mut aloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut aloop, &mut load);
+        Node::extend_loop_body(&mut aloop, &mut store);
+
+        let (hist, by_kind) = trace_by_kind(&mut aloop, 8, 8, LRUStack::new());
+        assert_eq!(hist.to_vec(), [(None, 10), (Some(1), 10)]);
+        assert_eq!(by_kind.len(), 2);
+        assert_eq!(by_kind[&AccessKind::Read].to_vec(), [(None, 10)]);
+        assert_eq!(by_kind[&AccessKind::Write].to_vec(), [(Some(1), 10)]);
+
+        let (_, dist_rd, _) = trace(&mut aloop, 8, 8, LRUStack::new());
+        assert_eq!(
+            dist_rd.get_vec()[..2],
+            [(0, None, AccessKind::Read), (0, Some(1), AccessKind::Write)]
+        );
+    }
+
+    #[test]
+    fn cache_lines() {
+        // i = 0, 16 { a[i]; b[i] } with 8 doubles and 16 ints to a 64-byte line
+        let code = dace::parse::parse(
+            "double A[16]; int B[16]; for (i = 0; i < 16; i++) B[i] = A[i];",
+            &[],
+        )
+        .unwrap();
+        let (hist, dist_rd, blocks) = trace(&mut code.clone(), 8, 64, LRUStack::new());
+        // A takes blocks 0 and 1, B block 2
+        assert_eq!(blocks.get_vec()[..4], [0, 2, 0, 2]);
+        assert_eq!(blocks.get_vec()[16..18], [1, 2]);
+        assert_eq!(dist_rd.get_vec()[2], (0, Some(2), AccessKind::Read));
+        assert_eq!(hist.to_vec(), [(None, 3), (Some(2), 29)]);
+    }
+
+    #[test]
+    fn split_by_ref() {
+        // the B[k][j] of gemm reuses a block across the whole k loop
+        let code = dace::parse::parse(
+            "double C[8][8]; double A[8][8]; double B[8][8];
+             for (i = 0; i < 8; i++) for (j = 0; j < 8; j++) for (k = 0; k < 8; k++)
+               C[i][j] += A[i][k] * B[k][j];",
+            &[],
+        )
+        .unwrap();
+        let (hist, by_ref, by_array) = trace_by_ref(&mut code.clone(), 8, 64, LRUStack::new());
+        // A, B, C read, C written
+        assert_eq!(by_ref.len(), 4);
+        let longest = |h: &Hist| h.to_vec().last().unwrap().0.unwrap();
+        assert!(longest(&by_ref[&1]) > longest(&by_ref[&0]));
+        assert_eq!(by_ref[&3].to_vec(), [(Some(1), 512)]);
+        assert_eq!(by_array.len(), 3);
+        let mut c = by_ref[&2].clone();
+        by_ref[&3]
+            .hist
+            .iter()
+            .for_each(|(&d, &n)| *c.hist.entry(d).or_default() += n);
+        assert_eq!(by_array["C"].to_vec(), c.to_vec());
+        let total: usize = by_array.values().flat_map(|h| h.hist.values()).sum();
+        assert_eq!(total, hist.hist.values().sum::<usize>());
+    }
+
+    #[test]
+    fn configured_outputs() {
+        let code = dace::parse::parse(
+            "double A[8][8]; double x[8];
+             for (i = 0; i < 8; i++) for (j = 0; j < 8; j++) x[i] += A[i][j];",
+            &[],
+        )
+        .unwrap();
+        let program = Program::new(&code);
+        let full = trace_with(
+            &program,
+            TraceConfig::new(TraceOptions::new(8)).with_in_memory(),
+            LRUStack::new(),
+        )
+        .unwrap();
+        assert_eq!(full.data_accesses.get_vec().len(), 192);
+
+        // the histogram only
+        let out = trace_with(
+            &program,
+            TraceConfig::new(TraceOptions::new(8)),
+            LRUStack::new(),
+        )
+        .unwrap();
+        assert_eq!(out.hist.to_vec(), full.hist.to_vec());
+        assert!(out.by_ref.is_empty() && out.by_kind.is_empty());
+        assert!(out.dist_rd.get_vec().is_empty() && out.data_accesses.get_vec().is_empty());
+
+        let mut csv = vec![];
+        let config = TraceConfig::new(TraceOptions::new(8).with_by_ref()).with_sink(&mut csv);
+        let out = trace_with(&program, config, LRUStack::new()).unwrap();
+        // A[i][j], x[i] read, x[i] written
+        assert_eq!(out.by_ref[&0].to_vec(), [(None, 64)]);
+        assert_eq!(out.by_ref[&2].to_vec(), [(Some(1), 64)]);
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 193);
+        assert_eq!(
+            lines[..4],
+            [
+                "Ref ID,Kind,Block,Reuse Distance",
+                "0,R,0,",
+                "1,R,64,",
+                "2,W,64,1"
+            ]
+        );
+        // x[0] is read again after A[0][1]
+        assert_eq!(lines[5], "1,R,64,2");
+    }
+
+    #[test]
+    fn failing_sink() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::StorageFull, "full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let code =
+            dace::parse::parse("double A[4]; for (i = 0; i < 4; i++) A[i] = 0;", &[]).unwrap();
+        let config = TraceConfig::new(TraceOptions::new(8)).with_sink(Full);
+        let Err(TraceError::Io(err)) = trace_with(&Program::new(&code), config, LRUStack::new())
+        else {
+            panic!("the write error is lost")
+        };
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+    }
+
+    #[test]
+    fn placed_arrays() {
+        // i = 0, 2 { a[i]; b[i] } with the arrays 4096 bytes apart
+        let mut aref = Node::new_ref("A", vec![2], |i| vec![i[0] as usize]);
+        let mut bref = Node::new_ref("B", vec![2], |i| vec![i[0] as usize]);
+        let mut aloop = Node::new_single_loop("i", 0, 2);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+        Node::extend_loop_body(&mut aloop, &mut bref);
+
+        let program = Program::with_placement(&aloop, 8, &Placement::Aligned(4096));
+        let (_, _, accesses) = trace_program(&program, 1, LRUStack::new());
+        assert_eq!(accesses.get_vec(), &[0, 4096, 8, 4104]);
+    }
+
+    #[test]
+    fn checked() {
+        // i = 0, 10 { a[i + 1] } overruns a[10]
+        let mut aref = Node::new_ref("A", vec![10], |i| vec![i[0] as usize + 1]);
+        let mut aloop = Node::new_single_loop("i", 0, 10);
+        Node::extend_loop_body(&mut aloop, &mut aref);
+
+        let Err(err) = trace_checked(&mut aloop, "shift", 8, 8, LRUStack::new()) else {
+            panic!("the overrun is not caught")
+        };
+        assert_eq!(err.kernel, "shift");
+        assert_eq!(err.array, "A");
+        assert_eq!(err.ivec, [9]);
+        assert_eq!(err.index, [10]);
+        // unchecked, the overrun goes unnoticed
+        assert_eq!(
+            trace(&mut aloop, 8, 8, LRUStack::new()).0.to_vec(),
+            [(None, 10)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid program")]
+    fn validated() {
+        // A is declared with two different sizes
+        let mut aloop = Node::new_single_loop("i", 0, 4);
+        let mut a4 = Node::new_ref("A", vec![4], |i| vec![i[0] as usize]);
+        let mut a8 = Node::new_ref("A", vec![8], |i| vec![i[0] as usize]);
+        Node::extend_loop_body(&mut aloop, &mut a4);
+        Node::extend_loop_body(&mut aloop, &mut a8);
+        trace(&mut aloop, 8, 8, LRUStack::new());
+    }
 }
@@ -1,6 +1,3 @@
-#![feature(get_mut_unchecked)]
-
-use std::collections::hash_map::Entry;
 use std::collections::{BTreeSet, HashMap};
 use std::fs;
 use std::fs::OpenOptions;
@@ -10,23 +7,27 @@ use std::rc::Rc;
 use fxhash::FxHashMap;
 use tracing::debug;
 
-use dace::arybase::set_arybase;
-use dace::ast::{AryRef, BranchStmt, LoopBound, LoopStmt, Node, Stmt};
-use dace::iter::Walk;
+use dace::ast::{AccessKind, AryRef, Node, Stmt};
+use dace::bounds::BoundsError;
+use dace::exec::{Access, AccessStream, TraceOptions};
+use dace::program::Program;
+use dace::validate::assert_valid;
 use hist::Hist;
 
 /// Calculate the memory address based on the array reference and index vector.
 ///
 /// # Parameters
 /// - `ary_ref`: Reference to the array metadata.
+/// - `base`: Base byte address of the array, see `Program::base`.
 /// - `ivec`: Index vector representing the access pattern.
-/// - `data_size`: Size of the data element in bytes.
+/// - `data_size`: Size of the data element in bytes, see `Program::elem_size`.
 /// - `cache_line_size`: Size of the cache line in bytes.
 ///
 /// # Returns
-/// The computed memory address.
+/// The computed cache line number.
 pub fn access3addr(
     ary_ref: &AryRef,
+    base: usize,
     ivec: &[i32],
     data_size: usize,
     cache_line_size: usize,
@@ -39,31 +40,16 @@ pub fn access3addr(
         panic!("Array index and dimension do not match");
     }
 
-    let offset = ary_index
-        .iter()
-        .zip(ary_ref.dim.iter())
-        .fold(0, |acc, (&i, &d)| acc * d + i);
-
-    (ary_ref.base.unwrap() + offset) * data_size / cache_line_size
-}
-
-pub fn assign_ref_id(node: &Rc<Node>) {
-    println!("Assigning ID...");
-    let mut counter = 0;
-    Walk::new(node)
-        .filter(|node| matches!(&node.stmt, Stmt::Ref(_)))
-        .for_each(|mut node| {
-            let mutable = unsafe { Rc::get_mut_unchecked(&mut node) };
-            let my_ref_id = mutable.ref_only_mut_ref(|aref| &mut aref.ref_id).unwrap();
-            if my_ref_id.is_none() {
-                *my_ref_id = Some(counter);
-                counter += 1;
-            }
-        });
-    println!("number of ID assigned: {}", counter);
+    (base + ary_ref.offset(&ary_index) * data_size) / cache_line_size
 }
 
-fn record_access_trace(ary_ref: &AryRef, ri: Option<usize>, addr: u64, counter: i64) {
+fn record_access_trace(
+    ref_id: Option<usize>,
+    kind: AccessKind,
+    ri: Option<usize>,
+    addr: u64,
+    counter: i64,
+) {
     fs::create_dir_all("out").expect("Failed to create the output folder.");
     let file_path = "out/access_trace.csv";
     let mut file = OpenOptions::new()
@@ -73,15 +59,16 @@ fn record_access_trace(ary_ref: &AryRef, ri: Option<usize>, addr: u64, counter:
         .unwrap();
 
     if file.metadata().unwrap().len() == 0 {
-        let header = "Ref ID,Reuse Interval,Address,Counter\n";
+        let header = "Ref ID,Kind,Reuse Interval,Address,Counter\n";
         file.write_all(header.as_bytes()).unwrap();
     }
 
     let trace_info = format!(
         // "{},{},{},{},{}\n",
         // ary_ref.name,
-        "{},{},{},{}\n",
-        ary_ref.ref_id.unwrap_or(usize::MAX),
+        "{},{},{},{},{}\n",
+        ref_id.unwrap_or(usize::MAX),
+        kind,
         ri.unwrap_or(usize::MAX), // TODO: handle None with danning recursion
         addr,
         counter
@@ -89,121 +76,146 @@ fn record_access_trace(ary_ref: &AryRef, ri: Option<usize>, addr: u64, counter:
     file.write_all(trace_info.as_bytes()).unwrap();
 }
 
+/// What a trace collects besides the histogram: the shared `TraceOptions`, and the splits
+/// and output only this tracer has.
+///
+/// # Examples
+/// ```rust
+/// use dace::exec::TraceOptions;
+/// use dace::program::Program;
+/// use static_ri::{tracing_ri_with, TraceConfig};
+///
+/// let code = dace::parse::parse("double A[64]; for (i = 0; i < 64; i++) A[i] += 1;", &[]).unwrap();
+/// let config = TraceConfig::new(TraceOptions::new(64).with_by_ref()).with_by_pair();
+/// let out = tracing_ri_with(&Program::new(&code), config).unwrap();
+/// assert_eq!(out.hist.to_vec(), [(None, 8), (Some(1), 120)]);
+/// assert_eq!(out.by_ref.len(), 2);
+/// assert_eq!(out.by_pair.len(), 2);
+/// assert!(out.by_kind.is_empty());
+/// ```
+pub struct TraceConfig {
+    options: TraceOptions,
+    by_pair: bool,
+    access_trace: bool,
+}
+
+impl TraceConfig {
+    /// What `options` asks for, and nothing else.
+    pub fn new(options: TraceOptions) -> Self {
+        TraceConfig {
+            options,
+            by_pair: false,
+            access_trace: false,
+        }
+    }
+
+    /// Also the histogram of each pair of references, see `TraceOutput::by_pair`.
+    pub fn with_by_pair(mut self) -> Self {
+        self.by_pair = true;
+        self
+    }
+
+    /// Also writes every access to `out/access_trace.csv`, replacing the file.
+    pub fn with_access_trace(mut self) -> Self {
+        self.access_trace = true;
+        self
+    }
+}
+
+/// What a trace collected, by reuse interval rather than distance but otherwise like
+/// `static_rd::trace::TraceOutput`. The outputs its `TraceConfig` didn't ask for are empty.
+#[derive(Default)]
+pub struct TraceOutput {
+    pub hist: Hist,
+    /// The interval of an access is counted under its own reference, whatever the access it
+    /// reuses.
+    pub by_ref: HashMap<usize, Hist>,
+    pub by_array: HashMap<String, Hist>,
+    /// Under the kind of the reusing access.
+    pub by_kind: HashMap<AccessKind, Hist>,
+    /// The histogram of each (source, sink) pair of references, by `Program::ref_id`: the
+    /// sink reuses the block the source accessed last. First accesses reuse nothing and are
+    /// in no pair.
+    pub by_pair: HashMap<(usize, usize), Hist>,
+}
+
 struct TracingContext<'a> {
-    lat_hash: FxHashMap<String, FxHashMap<u64, i64>>,
-    hist: Hist,
+    /// The last access time of every block, and the reference that made it. Arrays sharing
+    /// a line share its block.
+    lat_hash: FxHashMap<u64, (i64, usize)>,
+    out: TraceOutput,
     ivec: Vec<i32>,
-    code: &'a Rc<Node>,
+    program: &'a Program,
     counter: i64,
-    ds: usize,
-    cls: usize,
-    record_trace: bool,
+    config: TraceConfig,
 }
 
 impl<'a> TracingContext<'a> {
-    fn new(code: &'a Rc<Node>, ds: usize, cls: usize) -> Self {
+    fn new(program: &'a Program, config: TraceConfig) -> Self {
         TracingContext {
             lat_hash: Default::default(),
-            hist: Hist::new(),
+            out: TraceOutput::default(),
             ivec: vec![],
-            code,
+            program,
             counter: 0,
-            ds,
-            cls, //64
-            record_trace: false,
+            config,
         }
     }
 
-    fn trace_ri(&mut self) -> Hist {
-        // set_arybase(self.code);
-        self.trace_node(self.code);
-        self.hist.clone()
-    }
-
-    fn trace_node(&mut self, node: &Rc<Node>) {
-        match &node.stmt {
-            Stmt::Ref(ary_ref) => self.handle_ref_stmt(ary_ref),
-            Stmt::Loop(aloop) => self.handle_loop_stmt(aloop),
-            Stmt::Block(blk) => blk.iter().for_each(|s| self.trace_node(s)),
-            Stmt::Branch(stmt) => self.handle_branch_stmt(stmt),
+    fn trace_ri(&mut self) -> Result<(), Box<BoundsError>> {
+        let stream = AccessStream::new(self.program);
+        match self.config.options.check.clone() {
+            Some(kernel) => {
+                for access in stream.checked(&kernel) {
+                    self.handle_access(access?);
+                }
+            }
+            None => stream.for_each(|access| self.handle_access(access)),
         }
+        Ok(())
     }
 
-    fn handle_ref_stmt(&mut self, ary_ref: &AryRef) {
-        let addr = access3addr(ary_ref, &self.ivec, self.ds, self.cls) as u64;
-        let str_name = ary_ref.name.clone();
-        let mut prev_counter: Option<i64> = None;
+    fn handle_access(&mut self, access: Access) {
+        let Access {
+            ref_id, aref, addr, ..
+        } = access;
+        let config = &self.config;
+        let out = &mut self.out;
+        let options = &config.options;
+        let addr = (addr / options.cache_line_size) as u64;
         let local_counter = self.counter;
+        let prev = self.lat_hash.insert(addr, (local_counter, ref_id));
 
-        match self.lat_hash.entry(str_name) {
-            Entry::Occupied(mut entry) => match entry.get_mut().entry(addr) {
-                Entry::Occupied(mut inner) => {
-                    prev_counter = Some(inner.insert(local_counter));
-                }
-                Entry::Vacant(entry) => {
-                    entry.insert(local_counter);
-                }
-            },
-            Entry::Vacant(entry) => {
-                let mut inner_hash = FxHashMap::default();
-                inner_hash.insert(addr, local_counter);
-                entry.insert(inner_hash);
-            }
+        let ri = prev.map(|(prev_counter, _)| (local_counter - prev_counter) as usize);
+        if config.access_trace {
+            record_access_trace(Some(ref_id), aref.kind, ri, addr, self.counter);
         }
-
-        let ri = prev_counter.map(|prev| (local_counter - prev) as usize);
-        if self.record_trace {
-            record_access_trace(ary_ref, ri, addr, self.counter);
+        out.hist.add_dist(ri);
+        if options.by_kind {
+            out.by_kind.entry(aref.kind).or_default().add_dist(ri);
+        }
+        if let (true, Some((_, source))) = (config.by_pair, prev) {
+            out.by_pair
+                .entry((source, ref_id))
+                .or_default()
+                .add_dist(ri);
+        }
+        if options.by_ref {
+            out.by_ref.entry(ref_id).or_default().add_dist(ri);
+        }
+        if options.by_array {
+            out.by_array
+                .entry(aref.name.clone())
+                .or_default()
+                .add_dist(ri);
         }
-        self.hist.add_dist(ri);
         // FIXME: hist seems weird, how to deal with -1(the ri of never accessed again elements)
 
         self.counter += 1;
 
         debug!("counter: {}", self.counter);
         debug!("LAT_hash:{:#?}", self.lat_hash);
-        debug!("hist: {}", self.hist);
-    }
-
-    fn handle_loop_stmt(&mut self, aloop: &LoopStmt) {
-        let mut lb = match &aloop.lb {
-            LoopBound::Fixed(val) => *val,
-            LoopBound::Dynamic(func) => func(&self.ivec),
-            _ => {
-                panic!("affine bounds are not supported");
-            }
-        };
-
-        let ub = match &aloop.ub {
-            LoopBound::Fixed(val) => *val,
-            LoopBound::Dynamic(func) => func(&self.ivec),
-            _ => {
-                panic!("affine bounds are not supported");
-            }
-        };
-
-        // println!("lb: {}, ub: {}", lb, ub);
-
-        // for i in lb..ub {
-        //     self.ivec.push(i);
-        //     aloop.body.iter().for_each(|stmt| self.trace_node(stmt));
-        //     self.ivec.pop();
-        // }
-
-        while (aloop.test)(lb, ub) {
-            self.ivec.push(lb);
-            aloop.body.iter().for_each(|stmt| self.trace_node(stmt));
-            lb = (aloop.step)(lb);
-            self.ivec.pop();
-        }
-    }
-
-    fn handle_branch_stmt(&mut self, stmt: &BranchStmt) {
-        if (stmt.cond)(&self.ivec) {
-            self.trace_node(&stmt.then_body);
-        } else if let Some(else_body) = &stmt.else_body {
-            self.trace_node(else_body);
-        }
+        debug!("hist: {}", self.out.hist);
     }
 
     #[allow(dead_code)]
@@ -212,10 +224,14 @@ impl<'a> TracingContext<'a> {
         samples: HashMap<usize, BTreeSet<Vec<usize>>>,
         counter_ref: &mut usize,
     ) {
-        match &self.code.stmt {
+        let root = self.program.root();
+        match self.program.stmt(root) {
             Stmt::Ref(ary_ref) => {
-                debug!("sample_ri arr ref: {:#?}", self.code);
-                let _addr = access3addr(ary_ref, &self.ivec, self.ds, self.cls) as u64;
+                debug!("sample_ri arr ref: {:#?}", ary_ref);
+                let base = self.program.base(root).unwrap();
+                let ds = self.program.elem_size(root).unwrap();
+                let cls = self.config.options.cache_line_size;
+                let _addr = access3addr(ary_ref, base, &self.ivec, ds, cls) as u64;
                 if samples.contains_key(counter_ref) {
                     *counter_ref += 1;
                 }
@@ -225,40 +241,119 @@ impl<'a> TracingContext<'a> {
     }
 }
 
+/// Traces `program`, with the arrays where it put them, collecting what `config` asks for.
+/// Only fails in checked mode.
+///
+/// # Panics
+/// If `dace::validate` finds errors in the program.
+pub fn tracing_ri_with(
+    program: &Program,
+    config: TraceConfig,
+) -> Result<TraceOutput, Box<BoundsError>> {
+    assert_valid(program.root_node());
+    if config.access_trace {
+        // Check if the file exists and remove it if it does
+        let file_path = "out/access_trace.csv";
+        if std::path::Path::new(file_path).exists() {
+            fs::remove_file(file_path).expect("Failed to remove the file.");
+        }
+    }
+    let mut context = TracingContext::new(program, config);
+    context.trace_ri()?;
+    Ok(context.out)
+}
+
+fn unchecked(out: Result<TraceOutput, Box<BoundsError>>) -> TraceOutput {
+    out.expect("only a checked trace fails")
+}
+
+/// The reuse interval histogram of `code` at cache line granularity. `data_size` is the
+/// element size of the arrays that don't declare their own, see `Node::with_elem`.
+///
+/// # Panics
+/// If `dace::validate` finds errors in `code`. This holds for all the tracing functions.
 pub fn tracing_ri(code: &mut Rc<Node>, data_size: usize, cache_line_size: usize) -> Hist {
-    set_arybase(code);
-    assign_ref_id(code);
-    let mut context = TracingContext::new(code, data_size, cache_line_size);
+    tracing_ri_program(&Program::with_elem_size(code, data_size), cache_line_size)
+}
 
-    let h = context.trace_ri();
+/// Like `tracing_ri`, with the arrays where `program` put them, e.g. by
+/// `Program::with_placement`.
+pub fn tracing_ri_program(program: &Program, cache_line_size: usize) -> Hist {
+    let h = unchecked(tracing_ri_with(
+        program,
+        TraceConfig::new(TraceOptions::new(cache_line_size)),
+    ))
+    .hist;
     println!("{}", h);
     h
 }
 
+/// Like `tracing_ri`, but checks every subscript against its array's dimensions and stops at
+/// the first one out of range, naming `kernel` in the error.
+pub fn tracing_ri_checked(
+    code: &mut Rc<Node>,
+    kernel: &str,
+    data_size: usize,
+    cache_line_size: usize,
+) -> Result<Hist, Box<BoundsError>> {
+    let config = TraceConfig::new(TraceOptions::new(cache_line_size).with_check(kernel));
+    let program = Program::with_elem_size(code, data_size);
+    Ok(tracing_ri_with(&program, config)?.hist)
+}
+
+/// Like `tracing_ri`, but also returns the reuse interval histogram of each access kind. The
+/// interval of an access is counted under its own kind, whatever the kind of the access it
+/// reuses.
+pub fn tracing_ri_by_kind(
+    code: &mut Rc<Node>,
+    data_size: usize,
+    cache_line_size: usize,
+) -> (Hist, HashMap<AccessKind, Hist>) {
+    let config = TraceConfig::new(TraceOptions::new(cache_line_size).with_by_kind());
+    let program = Program::with_elem_size(code, data_size);
+    let out = unchecked(tracing_ri_with(&program, config));
+    (out.hist, out.by_kind)
+}
+
+/// Like `tracing_ri`, but also returns the reuse interval histogram of each reference, by
+/// `Program::ref_id`, and of each array, by name. The interval of an access is counted under
+/// its own reference and array, whatever the access it reuses.
+pub fn tracing_ri_by_ref(
+    code: &mut Rc<Node>,
+    data_size: usize,
+    cache_line_size: usize,
+) -> (Hist, HashMap<usize, Hist>, HashMap<String, Hist>) {
+    let options = TraceOptions::new(cache_line_size)
+        .with_by_ref()
+        .with_by_array();
+    let config = TraceConfig::new(options);
+    let program = Program::with_elem_size(code, data_size);
+    let out = unchecked(tracing_ri_with(&program, config));
+    (out.hist, out.by_ref, out.by_array)
+}
+
 pub fn tracing_ri_with_trace(
     code: &mut Rc<Node>,
     data_size: usize,
     cache_line_size: usize,
 ) -> Hist {
-    set_arybase(code);
-    assign_ref_id(code);
-    let mut context = TracingContext::new(code, data_size, cache_line_size);
-    context.record_trace = true;
-
-    // Check if the file exists and remove it if it does
-    let file_path = "out/access_trace.csv";
-    if std::path::Path::new(file_path).exists() {
-        fs::remove_file(file_path).expect("Failed to remove the file.");
-    }
+    tracing_ri_program_with_trace(&Program::with_elem_size(code, data_size), cache_line_size)
+}
 
-    let h = context.trace_ri();
+/// Like `tracing_ri_with_trace`, with the arrays where `program` put them, e.g. for a
+/// program read by `Program::load`.
+pub fn tracing_ri_program_with_trace(program: &Program, cache_line_size: usize) -> Hist {
+    let config = TraceConfig::new(TraceOptions::new(cache_line_size)).with_access_trace();
+    let h = unchecked(tracing_ri_with(program, config)).hist;
     println!("{}", h);
     h
 }
 
 #[cfg(test)]
 mod tests {
+    use dace::arybase::Placement;
     use dace::construct;
+    use dace::layout::Layout;
 
     use super::*;
 
@@ -278,7 +373,7 @@ mod tests {
         }
 
         nested_loops_top.print_structure(0);
-        set_arybase(&nested_loops_top);
+        let program = Program::new(&nested_loops_top);
 
         println!("{:?}", ref_c.stmt);
         println!("{:?}", ref_a.stmt);
@@ -291,7 +386,8 @@ mod tests {
 
         for (index, node) in refs.iter().enumerate() {
             if let Stmt::Ref(ary_ref) = &node.stmt {
-                let result = access3addr(ary_ref, &ivec, data_size, cache_line_size);
+                let base = program.ary_bases()[&ary_ref.name];
+                let result = access3addr(ary_ref, base, &ivec, data_size, cache_line_size);
                 print!(
                     "{}{:?}={:?}",
                     ary_ref.name,
@@ -310,14 +406,14 @@ mod tests {
         }
 
         print!("\nb = 1\t");
-        let hist = tracing_ri(&mut nested_loops_top.clone(), 8, 8);
+        let hist = tracing_ri(&mut nested_loops_top.deep_clone(), 8, 8);
         assert_eq!(hist.hist.get(&Some(3)), Some(&900));
         assert_eq!(hist.hist.get(&Some(30)), Some(&900));
         assert_eq!(hist.hist.get(&Some(300)), Some(&900));
         assert_eq!(hist.hist.get(&None), Some(&300));
 
         print!("b = 5\t");
-        let hist2 = tracing_ri(&mut nested_loops_top.clone(), 8, 40);
+        let hist2 = tracing_ri(&mut nested_loops_top.deep_clone(), 8, 40);
         assert_eq!(hist2.hist.get(&Some(3)), Some(&1780));
         assert_eq!(hist2.hist.get(&Some(18)), Some(&180));
         assert_eq!(hist2.hist.get(&Some(30)), Some(&800));
@@ -325,7 +421,7 @@ mod tests {
         assert_eq!(hist2.hist.get(&None), Some(&60));
 
         print!("b = 10\t");
-        let hist3 = tracing_ri(&mut nested_loops_top.clone(), 8, 80);
+        let hist3 = tracing_ri(&mut nested_loops_top.deep_clone(), 8, 80);
         assert_eq!(hist3.hist.get(&Some(3)), Some(&1980));
         assert_eq!(hist3.hist.get(&Some(30)), Some(&990));
         assert_eq!(hist3.hist.get(&None), Some(&30));
@@ -346,4 +442,199 @@ mod tests {
         assert_eq!(hist.hist.get(&Some(772)), Some(&960));
         assert_eq!(hist.hist.get(&None), Some(&64));
     }
+
+    #[test]
+    fn split_by_kind() {
+        let code =
+            dace::parse::parse("double A[10]; for (i = 0; i < 10; i++) A[i] += 1;", &[]).unwrap();
+        let (hist, by_kind) = tracing_ri_by_kind(&mut code.deep_clone(), 8, 8);
+        assert_eq!(hist.to_vec(), [(None, 10), (Some(1), 10)]);
+        assert_eq!(by_kind[&AccessKind::Read].to_vec(), [(None, 10)]);
+        assert_eq!(by_kind[&AccessKind::Write].to_vec(), [(Some(1), 10)]);
+        assert!(!by_kind.contains_key(&AccessKind::ReadWrite));
+    }
+
+    #[test]
+    fn split_by_ref() {
+        // for i { for j { x[i] += A[i][j] } }, x is reused every iteration, A never
+        let code = dace::parse::parse(
+            "double A[8][8]; double x[8];
+             for (i = 0; i < 8; i++) for (j = 0; j < 8; j++) x[i] += A[i][j];",
+            &[],
+        )
+        .unwrap();
+        let (hist, by_ref, by_array) = tracing_ri_by_ref(&mut code.deep_clone(), 8, 8);
+        assert_eq!(
+            hist.to_vec(),
+            tracing_ri(&mut code.deep_clone(), 8, 8).to_vec()
+        );
+        assert_eq!(by_ref[&0].to_vec(), [(None, 64)]);
+        assert_eq!(by_ref[&1].to_vec(), [(None, 8), (Some(2), 56)]);
+        assert_eq!(by_ref[&2].to_vec(), [(Some(1), 64)]);
+        assert_eq!(by_array["A"].to_vec(), by_ref[&0].to_vec());
+        assert_eq!(
+            by_array["x"].to_vec(),
+            [(None, 8), (Some(1), 64), (Some(2), 56)]
+        );
+    }
+
+    #[test]
+    fn split_by_pair() {
+        // for i { for j { x[i] += A[i][j] } } with 64-byte lines: A[i][j] reuses A[i][j-1],
+        // the write of x the read and, x being one line, every read but the first the write
+        let code = dace::parse::parse(
+            "double A[8][8]; double x[8];
+             for (i = 0; i < 8; i++) for (j = 0; j < 8; j++) x[i] += A[i][j];",
+            &[],
+        )
+        .unwrap();
+        // with the reuse of each reference, in the same run
+        let config = TraceConfig::new(TraceOptions::new(64).with_by_ref()).with_by_pair();
+        let out = tracing_ri_with(&Program::new(&code), config).unwrap();
+        let (hist, by_pair) = (out.hist, out.by_pair);
+        assert_eq!(
+            hist.to_vec(),
+            tracing_ri(&mut code.deep_clone(), 8, 64).to_vec()
+        );
+        assert!(out.by_array.is_empty() && out.by_kind.is_empty());
+        // the intervals of a sink, split by source
+        let to_sink_2: usize = by_pair[&(1, 2)].hist.values().sum();
+        assert_eq!(to_sink_2, out.by_ref[&2].hist.values().sum::<usize>());
+        let mut pairs: Vec<_> = by_pair.keys().copied().collect();
+        pairs.sort();
+        assert_eq!(pairs, [(0, 0), (1, 2), (2, 1)]);
+        assert_eq!(by_pair[&(0, 0)].to_vec(), [(Some(3), 56)]);
+        assert_eq!(by_pair[&(1, 2)].to_vec(), [(Some(1), 64)]);
+        assert_eq!(by_pair[&(2, 1)].to_vec(), [(Some(2), 63)]);
+        let reuses: usize = by_pair.values().flat_map(|h| h.hist.values()).sum();
+        assert_eq!(reuses + hist.hist[&None], 192);
+    }
+
+    #[test]
+    fn mixed_element_sizes() {
+        // the int array fills one 64-byte line, the double array two
+        let code = dace::parse::parse(
+            "int I[16]; double D[16]; for (i = 0; i < 16; i++) D[i] = I[i];",
+            &[],
+        )
+        .unwrap();
+        let hist = tracing_ri(&mut code.deep_clone(), 8, 64);
+        assert_eq!(hist.hist.get(&None), Some(&3));
+        assert_eq!(hist.hist.get(&Some(2)), Some(&29));
+    }
+
+    #[test]
+    fn lines_up_with_static_rd() {
+        // A, placed first, ends and x starts in the fifth 64-byte line
+        let code = dace::parse::parse(
+            "int x[6]; double A[6][6];
+             for (i = 0; i < 6; i++) for (j = 0; j < 6; j++) x[i] += A[i][j];",
+            &[],
+        )
+        .unwrap();
+        let rd = static_rd::trace::trace(&mut code.deep_clone(), 8, 64, static_rd::LRUStack::new());
+        let ri = tracing_ri(&mut code.deep_clone(), 8, 64);
+
+        // the reuse intervals of the blocks static_rd saw
+        let mut last = HashMap::new();
+        let mut expected = Hist::new();
+        for (t, &block) in rd.2.get_vec().iter().enumerate() {
+            expected.add_dist(last.insert(block, t).map(|prev| t - prev));
+        }
+        assert_eq!(ri.to_vec(), expected.to_vec());
+        assert_eq!(ri.hist.get(&None), rd.0.hist.get(&None));
+        assert_eq!(rd.2.get_vec()[..4], [0, 4, 4, 0]);
+    }
+
+    #[test]
+    fn miss_ratio_curves() {
+        let code = dace::parse::parse(
+            "double C[16][16]; double A[16][16]; double B[16][16];
+             for (i = 0; i < 16; i++) for (j = 0; j < 16; j++) for (k = 0; k < 16; k++)
+               C[i][j] += A[i][k] * B[k][j];",
+            &[],
+        )
+        .unwrap();
+        let rd = static_rd::trace::trace(&mut code.deep_clone(), 8, 64, static_rd::LRUStack::new());
+        let lru = hist::mrc::from_rd(&rd.0);
+        let aet = hist::mrc::from_ri(&tracing_ri(&mut code.deep_clone(), 8, 64));
+        // both end at the first accesses, and the model follows the exact curve closely but
+        // for the sharp steps
+        let cold = rd.0.hist[&None] as f64 / rd.2.get_vec().len() as f64;
+        assert_eq!(lru.last().unwrap().1, cold);
+        assert_eq!(aet.last().unwrap().1, cold);
+        let error: f64 = lru.iter().zip(&aet).map(|(a, b)| (a.1 - b.1).abs()).sum();
+        assert!(error / (lru.len() as f64) < 0.01);
+    }
+
+    #[test]
+    fn column_major_layout() {
+        // for j { for i { A[i][j] } } walks down the columns
+        let trace = |layout: Layout| {
+            let mut aref =
+                Node::new_ref("A", vec![8, 8], |ji| vec![ji[1] as usize, ji[0] as usize])
+                    .with_layout(layout);
+            let mut jloop = Node::new_single_loop("j", 0, 8);
+            let mut iloop = Node::new_single_loop("i", 0, 8);
+            Node::extend_loop_body(&mut iloop, &mut aref);
+            Node::extend_loop_body(&mut jloop, &mut iloop);
+            tracing_ri(&mut jloop, 8, 64).to_vec()
+        };
+        assert_eq!(trace(Layout::RowMajor), [(None, 8), (Some(8), 56)]);
+        assert_eq!(trace(Layout::ColumnMajor), [(None, 8), (Some(1), 56)]);
+    }
+
+    #[test]
+    fn placement() {
+        // page-aligned arrays, as a conflict study would place them
+        let code = dace::parse::parse(
+            "double A[8]; double B[8]; for (i = 0; i < 8; i++) A[i] = B[i];",
+            &[],
+        )
+        .unwrap();
+        let packed = Program::new(&code);
+        let paged = Program::with_placement(&code, 8, &Placement::Aligned(512));
+        assert_eq!(paged.ary_bases()["A"], 512);
+        // the reuse is the same at line granularity, whatever the placement
+        assert_eq!(
+            tracing_ri_program(&packed, 64).to_vec(),
+            tracing_ri_program(&paged, 64).to_vec()
+        );
+        // but an array that does not start at a line boundary spans one more line
+        let offset = HashMap::from([("B".to_string(), 0), ("A".to_string(), 96)]);
+        let skewed = Program::with_placement(&code, 8, &Placement::Explicit(offset));
+        assert_eq!(tracing_ri_program(&skewed, 64).hist.get(&None), Some(&3));
+    }
+
+    #[test]
+    fn checked() {
+        let code = dace::parse::parse(
+            "param N = 8; double A[N][N];
+             for (i = 0; i < N; i++) for (j = 0; j <= i; j++) A[i][j] = A[i - 1][j];",
+            &[],
+        )
+        .unwrap();
+        let Err(err) = tracing_ri_checked(&mut code.deep_clone(), "stencil", 8, 64) else {
+            panic!("the negative subscript is not caught")
+        };
+        assert_eq!(err.array, "A");
+        assert_eq!(err.ref_id, 0);
+        assert_eq!(err.ivec, [0, 0]);
+        assert_eq!(err.index, [-1, 0]);
+        assert_eq!((err.dim, err.extent), (0, 8));
+        assert!(err
+            .to_string()
+            .starts_with("stencil: reference 0 accesses A[-1][0]"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid program")]
+    fn validated() {
+        let mut aloop = Node::new_single_loop("i", 0, 4);
+        let mut a4 = Node::new_ref("A", vec![4], |i| vec![i[0] as usize]);
+        let mut a8 = Node::new_ref("A", vec![8], |i| vec![i[0] as usize]);
+        Node::extend_loop_body(&mut aloop, &mut a4);
+        Node::extend_loop_body(&mut aloop, &mut a8);
+        tracing_ri(&mut aloop, 8, 64);
+    }
 }
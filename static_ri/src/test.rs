@@ -3,6 +3,7 @@
 mod tests {
     use dace::ast::Node;
     use dace::construct;
+    use dace::program::Program;
     use dace_tests::polybench_simplify;
     use static_ri::tracing_ri;
 
@@ -130,7 +131,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 8);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 8);
     }
 
     #[test]
@@ -148,13 +149,59 @@ mod tests {
         for a_ref in &mut refs {
             construct::insert_at(a_ref, &mut nested_loops_top, "k");
         }
-        tracing_ri(&mut nested_loops_top.clone(), 8, 40);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 40);
 
         let mut nested_loops_top = construct::nested_loops(&["j", "k", "i"], ubound);
         for a_ref in &mut refs {
             construct::insert_at(a_ref, &mut nested_loops_top, "i");
         }
-        tracing_ri(&mut nested_loops_top.clone(), 8, 40);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 40);
+    }
+
+    #[test]
+    fn test_tiled_bounds() {
+        // A strip-mined loop with a partial last tile touches every element once.
+        let mut tiled = dace::parse::parse(
+            "double A[10];
+             for (ii = 0; ii < 10; ii += 4)
+               for (i = ii; i < min(ii + 4, 10); i++)
+                 A[i];",
+            &[],
+        )
+        .unwrap();
+        let hist = tracing_ri(&mut tiled, 8, 8);
+        assert_eq!(hist.hist.values().sum::<usize>(), 10);
+        assert_eq!(hist.hist.get(&None), Some(&10));
+    }
+
+    #[test]
+    fn test_tiled_gemm() {
+        // 4x4 tiles of a 10x10 gemm, with partial tiles, touch the same cache lines
+        let gemm = dace_tests::dsl::gemm(10);
+        let tiled = dace::transform::tile(&Program::new(&gemm), &[("i", 4), ("j", 4)]).unwrap();
+        let before = tracing_ri(&mut gemm.deep_clone(), 8, 64);
+        let after = tracing_ri(&mut tiled.root_node().clone(), 8, 64);
+        assert_eq!(
+            after.hist.values().sum::<usize>(),
+            before.hist.values().sum::<usize>()
+        );
+        assert_eq!(after.hist.get(&None), before.hist.get(&None));
+    }
+
+    #[test]
+    fn test_fused_gemver() {
+        // fusing the first two nests of gemver keeps the accesses and cold misses
+        let gemver = polybench_simplify::gemver(16);
+        let program = Program::new(&gemver);
+        let nests = program.children(program.root()).to_vec();
+        let fused = dace::transform::fuse(&program, nests[0], nests[1]).unwrap();
+        let before = tracing_ri(&mut gemver.deep_clone(), 8, 64);
+        let after = tracing_ri(&mut fused.root_node().clone(), 8, 64);
+        assert_eq!(
+            after.hist.values().sum::<usize>(),
+            before.hist.values().sum::<usize>()
+        );
+        assert_eq!(after.hist.get(&None), before.hist.get(&None));
     }
 
     #[test]
@@ -185,7 +232,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 64);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 64);
     }
 
     #[test]
@@ -199,7 +246,7 @@ mod tests {
         let mut ref_b = construct::a_ref("ref_b", vec![n, n], vec!["k", "j"]);
         construct::insert_at(&mut ref_b, &mut nest_loops, "k");
 
-        tracing_ri(&mut nest_loops.clone(), 1, 4);
+        tracing_ri(&mut nest_loops.deep_clone(), 1, 4);
     }
 
     #[test]
@@ -219,7 +266,7 @@ mod tests {
         let _ref_f = construct::a_ref("ref_f", vec![n, n], vec!["k", "j"]);
         construct::insert_at(&mut ref_b, &mut nest_loops, "n");
 
-        tracing_ri(&mut nest_loops.clone(), 4, 8);
+        tracing_ri(&mut nest_loops.deep_clone(), 4, 8);
     }
 
     #[test]
@@ -234,7 +281,7 @@ mod tests {
             construct::insert_at(s, &mut nest_loops, "k");
         }
 
-        tracing_ri(&mut nest_loops.clone(), 8, 40);
+        tracing_ri(&mut nest_loops.deep_clone(), 8, 40);
     }
 
     #[test]
@@ -257,7 +304,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 8);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 8);
     }
 
     #[test]
@@ -281,7 +328,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 40);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 40);
     }
 
     #[test]
@@ -310,7 +357,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 8);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 8);
     }
 
     #[test]
@@ -334,7 +381,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 8);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 8);
     }
 
     #[test]
@@ -358,7 +405,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 40);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 40);
     }
 
     #[test]
@@ -389,7 +436,7 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 24);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 24);
     }
 
     #[test]
@@ -414,6 +461,27 @@ mod tests {
 
         let nested_loops_top = construct::nest_the_loops(loop_order);
 
-        tracing_ri(&mut nested_loops_top.clone(), 8, 16);
+        tracing_ri(&mut nested_loops_top.deep_clone(), 8, 16);
+    }
+
+    #[test]
+    fn sample_collect_branches() {
+        // for i { for j = i { if (i < j) A[i][j] else A[j][i] } }
+        let mut code =
+            dace::parse::parse(
+                "double A[8][8]; for (i = 0; i < 8; i++) for (j = i; j < 8; j++) if (i < j) A[i][j] = 0; else A[j][i] = 1;",
+                &[],
+            )
+            .unwrap();
+        let mut collected = crate::sample_collect(&code);
+        // the j loop starts at `i`, an affine bound rather than a fixed one, so it is
+        // not sampled
+        assert_eq!(collected.len(), 2);
+        assert!(collected
+            .values()
+            .all(|loops| loops == &[("i".to_string(), 0..8)]));
+        let samples = crate::sample_gen(&mut collected, 0.5);
+        assert_eq!(samples[&0].len(), 4);
+        tracing_ri(&mut code, 8, 64);
     }
 }